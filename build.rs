@@ -0,0 +1,7 @@
+// Generates the gRPC service from `proto/bank_account.proto`, a vendored `protoc` is used so
+// that none needs to be installed.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::compile_protos("proto/bank_account.proto")?;
+    Ok(())
+}
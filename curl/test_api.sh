@@ -4,6 +4,13 @@ RANDOM=$$
 TEST_ACCT="test-acct-$RANDOM"
 TEST_URL="localhost:3030/account/$TEST_ACCT"
 echo "Using test account: $TEST_ACCT"
+ATM_URL="localhost:3030/atm/ATM-N468290"
+# ATMs are managed by the admin role, this is the `ADMIN_API_KEY` that the application runs with.
+ADMIN_HDR="X-Admin-Api-Key: $ADMIN_API_KEY"
+echo "Registering ATM-N468290 (an error is expected if it is already registered)"
+curl -i --location --request POST $ATM_URL --header "$ADMIN_HDR" --header 'Content-Type: application/json' --data-raw '{"RegisterAtm": {"atm_id": "ATM-N468290", "location": "Main St branch"}}'
+echo "Loading cash into the ATM"
+curl -i --location --request POST $ATM_URL --header "$ADMIN_HDR" --header 'Content-Type: application/json' --data-raw '{"LoadCash": {"amount": 10000.0}}'
 echo "Opening an account"
 curl -i --location --request POST $TEST_URL --header 'Content-Type: application/json' --data-raw "{\"OpenAccount\": {\"account_id\": \"$TEST_ACCT\"}}"
 echo "Depositing money"
@@ -15,3 +22,6 @@ curl -i --location --request POST $TEST_URL --header 'Content-Type: application/
 echo "Checking account status (calling a query)"
 curl -i --location $TEST_URL
 echo
+echo "Checking the ATM cash inventory"
+curl -i --location $ATM_URL
+echo
@@ -13,13 +13,21 @@ function _remove_tmpfile {
 }
 
 function call_lambda() {
-    local PAYLOAD=$(echo $1 | sed -e "s/\"/\\\\\\\\\"/g")
-    sed -e "s/\$ACCOUNT/$TEST_ACCT/" -e "s/\$PAYLOAD/$PAYLOAD/" lambda_payload.json > $TMP_FILE
+    call_lambda_route "/account/$TEST_ACCT" "$1"
+}
+
+function call_lambda_route() {
+    local PAYLOAD=$(echo $2 | sed -e "s/\"/\\\\\\\\\"/g")
+    sed -e "s#\$ROUTE#$1#" -e "s/\$PAYLOAD/$PAYLOAD/" -e "s/\$ADMIN_API_KEY/$ADMIN_API_KEY/" lambda_payload.json > $TMP_FILE
     curl -i --location --request POST $TEST_URL --data @$TMP_FILE
     echo
     echo
 }
 
+echo "Registering and loading cash into ATM-N468290 (an error is expected if already registered)"
+call_lambda_route "/atm/ATM-N468290" "{\"RegisterAtm\": {\"atm_id\": \"ATM-N468290\", \"location\": \"Main St branch\"}}"
+call_lambda_route "/atm/ATM-N468290" "{\"LoadCash\": {\"amount\": 10000.0}}"
+
 echo "Opening an account"
 call_lambda "{\"OpenAccount\": {\"account_id\": \"$TEST_ACCT\"}}"
 
@@ -34,7 +42,7 @@ call_lambda "{\"WriteCheck\":{\"check_number\":\"1170\",\"amount\":256.28}}"
 
 echo "Checking account status (calling a query)"
 PAYLOAD=""
-sed -e "s/\$ACCOUNT/$TEST_ACCT/" -e "s/\$PAYLOAD/$PAYLOAD/" -e "s/\POST/GET/" lambda_payload.json > $TMP_FILE
+sed -e "s#\$ROUTE#/account/$TEST_ACCT#" -e "s/\$PAYLOAD/$PAYLOAD/" -e "s/\POST/GET/" lambda_payload.json > $TMP_FILE
 curl -i --request POST $TEST_URL --data @$TMP_FILE
 echo
 
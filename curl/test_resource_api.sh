@@ -0,0 +1,28 @@
+#!/bin/bash
+
+RANDOM=$$
+TEST_ACCT="test-acct-$RANDOM"
+TEST_URL="localhost:3030/account/$TEST_ACCT"
+echo "Using test account: $TEST_ACCT"
+ATM_URL="localhost:3030/atm/ATM-N468290"
+# ATMs are managed by the admin role, this is the `ADMIN_API_KEY` that the application runs with.
+ADMIN_HDR="X-Admin-Api-Key: $ADMIN_API_KEY"
+echo "Registering ATM-N468290 (an error is expected if it is already registered)"
+curl -i --location --request POST $ATM_URL --header "$ADMIN_HDR" --header 'Content-Type: application/json' --data-raw '{"RegisterAtm": {"atm_id": "ATM-N468290", "location": "Main St branch"}}'
+echo "Loading cash into the ATM"
+curl -i --location --request POST $ATM_URL --header "$ADMIN_HDR" --header 'Content-Type: application/json' --data-raw '{"LoadCash": {"amount": 10000.0}}'
+echo "Opening an account"
+curl -i --location --request POST localhost:3030/accounts --header 'Content-Type: application/json' --data-raw "{\"account_id\": \"$TEST_ACCT\", \"account_type\": \"Savings\", \"holders\": [\"Jane Smith\"]}"
+echo "Depositing money"
+curl -i --location --request POST $TEST_URL/deposits --header 'Content-Type: application/json' --data-raw '{"amount": 1000.0}'
+echo "Withdrawing money"
+curl -i --location --request POST $TEST_URL/withdrawals --header 'Content-Type: application/json' --data-raw '{"amount": 400.0, "atm_id": "ATM-N468290"}'
+echo "Writing a check"
+curl -i --location --request POST $TEST_URL/checks --header 'Content-Type: application/json' --data-raw '{"check_number": "1170", "amount": 256.25}'
+echo "Checking account status (calling a query)"
+curl -i --location $TEST_URL
+echo
+echo "Writing a check for the remaining balance, an account may only be closed with a zero balance"
+curl -i --location --request POST $TEST_URL/checks --header 'Content-Type: application/json' --data-raw '{"check_number": "1171", "amount": 343.75}'
+echo "Closing the account"
+curl -i --location --request DELETE $TEST_URL
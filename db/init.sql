@@ -7,8 +7,15 @@ CREATE TABLE events
     event_version  text                         NOT NULL,
     payload        json                         NOT NULL,
     metadata       json                         NOT NULL,
+    -- The position of the event across all aggregates, in the order that events were inserted.
+    global_position bigserial                   NOT NULL,
+    -- The transaction that committed the event, events are read in order of transaction and
+    -- then position so that a transaction committing late is not skipped.
+    transaction_id  xid8 DEFAULT pg_current_xact_id() NOT NULL,
     PRIMARY KEY (aggregate_type, aggregate_id, sequence)
 );
+CREATE UNIQUE INDEX events_global_position ON events (global_position);
+CREATE INDEX events_transaction_position ON events (aggregate_type, transaction_id, global_position);
 
 CREATE TABLE account_query
 (
@@ -18,5 +25,169 @@ CREATE TABLE account_query
     PRIMARY KEY (view_id)
 );
 
+CREATE TABLE atm_query
+(
+    view_id text                        NOT NULL,
+    version           bigint CHECK (version >= 0) NOT NULL,
+    payload           json                        NOT NULL,
+    PRIMARY KEY (view_id)
+);
+
+-- One typed row per ledger event, allowing an account's ledger to be paged and filtered
+-- and analyzed with SQL.
+CREATE TABLE ledger_entries
+(
+    account_id   text                         NOT NULL,
+    sequence     bigint CHECK (sequence >= 0) NOT NULL,
+    entry_type   text                         NOT NULL,
+    amount       double precision             NOT NULL,
+    balance      double precision             NOT NULL,
+    check_number text,
+    atm_id       text,
+    recorded_at  timestamptz                  NOT NULL,
+    PRIMARY KEY (account_id, sequence)
+);
+CREATE INDEX ledger_entries_recorded_at ON ledger_entries (recorded_at);
+CREATE INDEX ledger_entries_account_recorded_at ON ledger_entries (account_id, recorded_at);
+CREATE INDEX ledger_entries_entry_type ON ledger_entries (entry_type, recorded_at);
+CREATE INDEX ledger_entries_atm_id ON ledger_entries (atm_id) WHERE atm_id IS NOT NULL;
+
+-- A summary of each account, allowing accounts to be listed and searched.
+CREATE TABLE account_summary
+(
+    account_id   text                         NOT NULL,
+    account_type text                         NOT NULL,
+    status       text                         NOT NULL,
+    balance      double precision             NOT NULL,
+    holders      text[]                       NOT NULL,
+    opened_at    timestamptz                  NOT NULL,
+    sequence     bigint CHECK (sequence >= 0) NOT NULL,
+    PRIMARY KEY (account_id)
+);
+CREATE INDEX account_summary_status_balance ON account_summary (status, balance);
+
+-- The totals of each day (in UTC) and account type, along with the type of each account and
+-- the events that have been counted.
+CREATE TABLE daily_report
+(
+    day              date                        NOT NULL,
+    account_type     text                        NOT NULL,
+    deposit_count    bigint           DEFAULT 0  NOT NULL,
+    deposit_total    double precision DEFAULT 0  NOT NULL,
+    withdrawal_count bigint           DEFAULT 0  NOT NULL,
+    withdrawal_total double precision DEFAULT 0  NOT NULL,
+    check_count      bigint           DEFAULT 0  NOT NULL,
+    check_total      double precision DEFAULT 0  NOT NULL,
+    PRIMARY KEY (day, account_type)
+);
+
+CREATE TABLE report_accounts
+(
+    account_id   text NOT NULL,
+    account_type text NOT NULL,
+    PRIMARY KEY (account_id)
+);
+
+CREATE TABLE report_events
+(
+    account_id text                         NOT NULL,
+    sequence   bigint CHECK (sequence >= 0) NOT NULL,
+    PRIMARY KEY (account_id, sequence)
+);
+
+-- Transactions that were flagged for review by fraud and anti-money laundering screening.
+CREATE TABLE review_queue
+(
+    account_id  text                        NOT NULL,
+    sequence    bigint CHECK (sequence >= 0) NOT NULL,
+    transaction text                        NOT NULL,
+    amount      double precision            NOT NULL,
+    reason      text                        NOT NULL,
+    flagged_at  timestamptz                 NOT NULL,
+    PRIMARY KEY (account_id, sequence)
+);
+
+-- Side effects of committed events, written in the same transaction as the events
+-- and delivered afterward by the outbox dispatcher.
+CREATE TABLE outbox
+(
+    id              bigserial                   NOT NULL,
+    aggregate_type  text                        NOT NULL,
+    aggregate_id    text                        NOT NULL,
+    sequence        bigint                      NOT NULL,
+    message_type    text                        NOT NULL,
+    payload         json                        NOT NULL,
+    attempts        integer     DEFAULT 0       NOT NULL,
+    -- The number of steps of the side effect that have succeeded, e.g., cash dispensed.
+    completed_steps integer     DEFAULT 0       NOT NULL,
+    next_attempt_at timestamptz DEFAULT now()   NOT NULL,
+    last_error      text,
+    processed_at    timestamptz,
+    -- Set once the message has failed too many times, it is then recorded as a dead letter.
+    abandoned_at    timestamptz,
+    PRIMARY KEY (id)
+);
+
+-- Events that a projection failed to apply, these are kept until reprocessed successfully.
+CREATE TABLE dead_letters
+(
+    id             bigserial                   NOT NULL,
+    projection     text                        NOT NULL,
+    aggregate_type text                        NOT NULL,
+    aggregate_id   text                        NOT NULL,
+    sequence       bigint                      NOT NULL,
+    error          text                        NOT NULL,
+    attempts       integer     DEFAULT 1       NOT NULL,
+    failed_at      timestamptz DEFAULT now()   NOT NULL,
+    resolved_at    timestamptz,
+    PRIMARY KEY (id)
+);
+CREATE INDEX dead_letters_unresolved ON dead_letters (projection, aggregate_type, aggregate_id)
+    WHERE resolved_at IS NULL;
+
+-- The global position of the last event applied by each asynchronous projection.
+CREATE TABLE projection_checkpoints
+(
+    projection     text                        NOT NULL,
+    transaction_id xid8                        NOT NULL,
+    position       bigint                      NOT NULL,
+    updated_at timestamptz DEFAULT now()   NOT NULL,
+    PRIMARY KEY (projection)
+);
+
+-- Partner endpoints notified of account events, an empty list matches every event type or account.
+CREATE TABLE webhook_subscriptions
+(
+    id          bigserial                   NOT NULL,
+    url         text                        NOT NULL,
+    secret      text                        NOT NULL,
+    event_types text[]      DEFAULT '{}'    NOT NULL,
+    account_ids text[]      DEFAULT '{}'    NOT NULL,
+    created_at  timestamptz DEFAULT now()   NOT NULL,
+    PRIMARY KEY (id)
+);
+
+-- Each notification of a subscriber, written in the same transaction as its event.
+CREATE TABLE webhook_deliveries
+(
+    id              bigserial                   NOT NULL,
+    subscription_id bigint                      NOT NULL REFERENCES webhook_subscriptions (id) ON DELETE CASCADE,
+    aggregate_id    text                        NOT NULL,
+    sequence        bigint                      NOT NULL,
+    event_type      text                        NOT NULL,
+    payload         json                        NOT NULL,
+    attempts        integer     DEFAULT 0       NOT NULL,
+    next_attempt_at timestamptz DEFAULT now()   NOT NULL,
+    response_status integer,
+    last_error      text,
+    created_at      timestamptz DEFAULT now()   NOT NULL,
+    delivered_at    timestamptz,
+    -- Set once the delivery has failed too many times, it is then no longer retried.
+    abandoned_at    timestamptz,
+    PRIMARY KEY (id)
+);
+CREATE INDEX webhook_deliveries_subscription ON webhook_deliveries (subscription_id, id);
+CREATE INDEX webhook_deliveries_pending ON webhook_deliveries (next_attempt_at) WHERE delivered_at IS NULL AND abandoned_at IS NULL;
+
 CREATE USER demo_user WITH ENCRYPTED PASSWORD 'demo_pass';
 GRANT ALL PRIVILEGES ON DATABASE postgres TO demo_user;
\ No newline at end of file
@@ -0,0 +1,57 @@
+use cqrs_demo::grpc::proto::bank_account_command::Command;
+use cqrs_demo::grpc::proto::bank_account_service_client::BankAccountServiceClient;
+use cqrs_demo::grpc::proto::{
+    BankAccountCommand, DepositMoney, ExecuteRequest, GetAccountRequest, OpenAccount,
+    StreamEventsRequest,
+};
+
+// A gRPC client that opens an account, deposits into it and then follows its events, the account
+// is read with the version returned by the deposit so that the view includes it.
+//
+//     cargo run --example grpc_client -- <account_id>
+//
+// The application must be running, it serves gRPC at `http://localhost:50051`.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let account_id = std::env::args()
+        .nth(1)
+        .expect("usage: grpc_client <account_id>");
+    let mut client = BankAccountServiceClient::connect("http://localhost:50051").await?;
+    let mut version = None;
+    for command in [
+        Command::OpenAccount(OpenAccount {
+            account_id: account_id.clone(),
+            ..OpenAccount::default()
+        }),
+        Command::DepositMoney(DepositMoney { amount: 100.0 }),
+    ] {
+        let request = ExecuteRequest {
+            account_id: account_id.clone(),
+            command: Some(BankAccountCommand {
+                command: Some(command),
+            }),
+        };
+        match client.execute(request).await {
+            Ok(response) => version = response.into_inner().version.or(version),
+            Err(status) => println!("command rejected: {:?} {}", status.code(), status.message()),
+        }
+    }
+    let view = client
+        .get_account(GetAccountRequest {
+            account_id: account_id.clone(),
+            min_version: version,
+        })
+        .await?;
+    println!("account: {:?}", view.into_inner());
+    let mut updates = client
+        .stream_events(StreamEventsRequest {
+            account_id,
+            after: Some(0),
+        })
+        .await?
+        .into_inner();
+    while let Some(update) = updates.message().await? {
+        println!("update: {:?}", update);
+    }
+    Ok(())
+}
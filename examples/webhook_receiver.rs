@@ -0,0 +1,38 @@
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::Router;
+use chrono::Utc;
+use cqrs_demo::webhooks::{verify_signature, SIGNATURE_HDR};
+
+const WEBHOOK_SECRET_VAR: &str = "WEBHOOK_SECRET";
+
+// A local receiver for testing webhook deliveries, it verifies the signature of each delivery
+// with the secret returned when subscribing and prints the delivered event.
+//
+//     WEBHOOK_SECRET=<secret> cargo run --example webhook_receiver
+//
+// Deliveries are accepted at `http://localhost:3031/webhook`.
+#[tokio::main]
+async fn main() {
+    let secret = std::env::var(WEBHOOK_SECRET_VAR).expect("WEBHOOK_SECRET must be set");
+    let router = Router::new().route(
+        "/webhook",
+        post(move |headers: HeaderMap, body: String| async move {
+            let signature = headers
+                .get(SIGNATURE_HDR)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            if verify_signature(&secret, signature, &body, Utc::now().timestamp()) {
+                println!("delivery received: {}", body);
+                StatusCode::NO_CONTENT
+            } else {
+                println!("invalid signature: {}", body);
+                StatusCode::UNAUTHORIZED
+            }
+        }),
+    );
+    axum::Server::bind(&"0.0.0.0:3031".parse().unwrap())
+        .serve(router.into_make_service())
+        .await
+        .unwrap();
+}
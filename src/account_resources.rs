@@ -0,0 +1,133 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::domain::aggregate::AccountType;
+use crate::domain::commands::BankAccountCommand;
+
+// The flat request bodies of the resource-oriented account routes, e.g., `POST /accounts` or
+// `POST /account/:account_id/deposits`, each is mapped onto a `BankAccountCommand`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewAccount {
+    account_id: String,
+    #[serde(default)]
+    account_type: AccountType,
+    #[serde(default)]
+    holders: Vec<String>,
+}
+
+// The characters left unencoded in a path segment, the unreserved characters of RFC 3986.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+impl NewAccount {
+    pub fn account_id(&self) -> &str {
+        &self.account_id
+    }
+
+    // The path of the new account, with the account id percent-encoded.
+    pub fn location(&self) -> String {
+        format!(
+            "/account/{}",
+            utf8_percent_encode(&self.account_id, PATH_SEGMENT)
+        )
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewDeposit {
+    amount: f64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewWithdrawal {
+    amount: f64,
+    atm_id: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewCheck {
+    check_number: String,
+    amount: f64,
+}
+
+impl From<NewAccount> for BankAccountCommand {
+    fn from(account: NewAccount) -> Self {
+        BankAccountCommand::OpenAccount {
+            account_id: account.account_id,
+            account_type: account.account_type,
+            holders: account.holders,
+        }
+    }
+}
+
+impl From<NewDeposit> for BankAccountCommand {
+    fn from(deposit: NewDeposit) -> Self {
+        BankAccountCommand::DepositMoney {
+            amount: deposit.amount,
+        }
+    }
+}
+
+impl From<NewWithdrawal> for BankAccountCommand {
+    fn from(withdrawal: NewWithdrawal) -> Self {
+        BankAccountCommand::WithdrawMoney {
+            amount: withdrawal.amount,
+            atm_id: withdrawal.atm_id,
+        }
+    }
+}
+
+impl From<NewCheck> for BankAccountCommand {
+    fn from(check: NewCheck) -> Self {
+        BankAccountCommand::WriteCheck {
+            check_number: check.check_number,
+            amount: check.amount,
+        }
+    }
+}
+
+#[cfg(test)]
+mod account_resources_tests {
+    use crate::account_resources::{NewAccount, NewWithdrawal};
+    use crate::domain::aggregate::AccountType;
+    use crate::domain::commands::BankAccountCommand;
+
+    #[test]
+    fn test_flat_bodies() {
+        let account: NewAccount = serde_json::from_str(r#"{"account_id": "ACCT-1"}"#).unwrap();
+        match BankAccountCommand::from(account) {
+            BankAccountCommand::OpenAccount {
+                account_id,
+                account_type,
+                holders,
+            } => {
+                assert_eq!("ACCT-1", account_id);
+                assert_eq!(AccountType::Checking, account_type);
+                assert!(holders.is_empty());
+            }
+            command => panic!("unexpected command: {:?}", command),
+        }
+        let withdrawal: NewWithdrawal =
+            serde_json::from_str(r#"{"amount": 40.0, "atm_id": "ATM-1"}"#).unwrap();
+        match BankAccountCommand::from(withdrawal) {
+            BankAccountCommand::WithdrawMoney { amount, atm_id } => {
+                assert_eq!(40.0, amount);
+                assert_eq!("ATM-1", atm_id);
+            }
+            command => panic!("unexpected command: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_location() {
+        let account: NewAccount = serde_json::from_str(r#"{"account_id": "ACCT-1"}"#).unwrap();
+        assert_eq!("/account/ACCT-1", account.location());
+        let account: NewAccount =
+            serde_json::from_str(r#"{"account_id": "Zoë's/acct 2\n"}"#).unwrap();
+        assert_eq!("/account/Zo%C3%AB%27s%2Facct%202%0A", account.location());
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::VecDeque;
+
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use cqrs_es::{AggregateError, EventEnvelope, EventStore, Query, View};
+use futures::Stream;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::domain::aggregate::BankAccount;
+use crate::domain::events::BankAccountError;
+use crate::domain::events::BankAccountEvent;
+use crate::event_store::AppEventStore;
+use crate::queries::BankAccountView;
+
+const CHANNEL_CAPACITY: usize = 1024;
+
+// A query that publishes each committed account event to the subscribers of
+// `/account/:account_id/stream`. Events are dropped if there are no subscribers.
+//
+// Every subscriber receives the events of all accounts through a single channel holding up to
+// `CHANNEL_CAPACITY` events, and skips those of other accounts. A subscriber that falls further
+// behind than this, which heavy traffic on other accounts can cause, reloads the events of its
+// account from the event store, so with many subscribers this load grows with the traffic.
+#[derive(Clone)]
+pub struct AccountStreamQuery {
+    sender: broadcast::Sender<EventEnvelope<BankAccount>>,
+}
+
+impl Default for AccountStreamQuery {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self { sender }
+    }
+}
+
+#[async_trait]
+impl Query<BankAccount> for AccountStreamQuery {
+    async fn dispatch(&self, _aggregate_id: &str, events: &[EventEnvelope<BankAccount>]) {
+        for event in events {
+            let _ = self.sender.send(event.clone());
+        }
+    }
+}
+
+// An event pushed to a subscriber along with the view of the account after it was applied.
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountUpdate {
+    pub sequence: usize,
+    pub event: BankAccountEvent,
+    pub view: BankAccountView,
+}
+
+impl AccountStreamQuery {
+    // Streams the events of an account committed after `after_sequence`, or only new events
+    // if this is absent. The view of each update is built from the event store so that it
+    // does not depend on the projections.
+    // Any events missed by a slow subscriber are reloaded from the event store.
+    pub async fn subscribe(
+        &self,
+        events: Arc<AppEventStore<BankAccount>>,
+        account_id: String,
+        after_sequence: Option<usize>,
+    ) -> Result<impl Stream<Item = AccountUpdate>, AggregateError<BankAccountError>> {
+        // Subscribe before loading the past events so that none are missed in between.
+        let receiver = self.sender.subscribe();
+        let mut view = BankAccountView::default();
+        let mut pending = VecDeque::new();
+        let mut last_sequence = 0;
+        for event in events.load_events(&account_id).await? {
+            if event.sequence <= after_sequence.unwrap_or(usize::MAX) {
+                view.update(&event);
+                last_sequence = event.sequence;
+            } else {
+                pending.push_back(event);
+            }
+        }
+        let subscription = Subscription {
+            account_id,
+            events,
+            receiver,
+            view,
+            last_sequence,
+            pending,
+        };
+        Ok(futures::stream::unfold(
+            subscription,
+            |mut subscription| async move {
+                let update = subscription.next().await?;
+                Some((update, subscription))
+            },
+        ))
+    }
+}
+
+struct Subscription {
+    account_id: String,
+    events: Arc<AppEventStore<BankAccount>>,
+    receiver: broadcast::Receiver<EventEnvelope<BankAccount>>,
+    view: BankAccountView,
+    last_sequence: usize,
+    pending: VecDeque<EventEnvelope<BankAccount>>,
+}
+
+impl Subscription {
+    // The next update, or `None` once the stream has ended.
+    async fn next(&mut self) -> Option<AccountUpdate> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                if event.sequence <= self.last_sequence {
+                    continue;
+                }
+                self.view.update(&event);
+                self.last_sequence = event.sequence;
+                return Some(AccountUpdate {
+                    sequence: event.sequence,
+                    event: event.payload,
+                    view: self.view.clone(),
+                });
+            }
+            let missed = match self.receiver.recv().await {
+                Ok(event) if event.aggregate_id != self.account_id => false,
+                Ok(event) if event.sequence > self.last_sequence + 1 => true,
+                Ok(event) => {
+                    self.pending.push_back(event);
+                    false
+                }
+                Err(RecvError::Lagged(_)) => true,
+                Err(RecvError::Closed) => return None,
+            };
+            if missed {
+                if let Err(err) = self.reload().await {
+                    println!("Error: {:#?}\n", err);
+                    return None;
+                }
+            }
+        }
+    }
+
+    async fn reload(&mut self) -> Result<(), AggregateError<BankAccountError>> {
+        let events = self.events.load_events(&self.account_id).await?;
+        self.pending = events
+            .into_iter()
+            .filter(|event| event.sequence > self.last_sequence)
+            .collect();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod account_stream_tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use cqrs_es::{EventEnvelope, EventStore, Query};
+    use futures::{Stream, StreamExt};
+    use tokio::sync::broadcast;
+
+    use crate::account_stream::{AccountStreamQuery, AccountUpdate};
+    use crate::domain::aggregate::{AccountType, BankAccount};
+    use crate::domain::events::BankAccountEvent;
+    use crate::event_store::AppEventStore;
+
+    // Commits the opening of an account and a deposit for each amount.
+    async fn commit(
+        store: &AppEventStore<BankAccount>,
+        account_id: &str,
+        amounts: &[f64],
+    ) -> Vec<EventEnvelope<BankAccount>> {
+        let context = store.load_aggregate(account_id).await.unwrap();
+        let mut events = Vec::new();
+        if store.load_events(account_id).await.unwrap().is_empty() {
+            events.push(BankAccountEvent::AccountOpened {
+                account_id: account_id.to_string(),
+                account_type: AccountType::Checking,
+                holders: vec![],
+            });
+        }
+        for amount in amounts {
+            events.push(BankAccountEvent::CustomerDepositedMoney {
+                amount: *amount,
+                balance: *amount,
+            });
+        }
+        store.commit(events, context, HashMap::new()).await.unwrap()
+    }
+
+    async fn next_sequences(
+        updates: &mut (impl Stream<Item = AccountUpdate> + Unpin),
+        count: usize,
+    ) -> Vec<usize> {
+        let mut sequences = Vec::new();
+        for _ in 0..count {
+            sequences.push(updates.next().await.unwrap().sequence);
+        }
+        sequences
+    }
+
+    #[tokio::test]
+    async fn test_resume_after() {
+        let store = Arc::new(AppEventStore::memory());
+        commit(&store, "ACCT-1", &[10.0, 20.0]).await;
+        let query = AccountStreamQuery::default();
+        let updates = query
+            .subscribe(store.clone(), "ACCT-1".to_string(), Some(1))
+            .await
+            .unwrap();
+        let mut updates = Box::pin(updates);
+        let update = updates.next().await.unwrap();
+        assert_eq!(2, update.sequence);
+        assert_eq!(2, update.view.version);
+        assert_eq!(10.0, update.view.balance);
+        assert_eq!(vec![3], next_sequences(&mut updates, 1).await);
+
+        let events = commit(&store, "ACCT-1", &[30.0]).await;
+        query.dispatch("ACCT-1", &events).await;
+        let update = updates.next().await.unwrap();
+        assert_eq!(4, update.sequence);
+        assert_eq!(30.0, update.view.balance);
+    }
+
+    #[tokio::test]
+    async fn test_duplicates_and_other_accounts() {
+        let store = Arc::new(AppEventStore::memory());
+        let opened = commit(&store, "ACCT-1", &[10.0]).await;
+        let query = AccountStreamQuery::default();
+        let updates = query
+            .subscribe(store.clone(), "ACCT-1".to_string(), None)
+            .await
+            .unwrap();
+        let mut updates = Box::pin(updates);
+
+        query.dispatch("ACCT-1", &opened).await;
+        let other = commit(&store, "ACCT-2", &[50.0, 60.0, 70.0]).await;
+        query.dispatch("ACCT-2", &other).await;
+        let events = commit(&store, "ACCT-1", &[20.0]).await;
+        query.dispatch("ACCT-1", &events).await;
+        let update = updates.next().await.unwrap();
+        assert_eq!(3, update.sequence);
+        assert_eq!(Some("ACCT-1".to_string()), update.view.account_id);
+        assert_eq!(20.0, update.view.balance);
+    }
+
+    #[tokio::test]
+    async fn test_reload_on_gap() {
+        let store = Arc::new(AppEventStore::memory());
+        commit(&store, "ACCT-1", &[]).await;
+        let query = AccountStreamQuery::default();
+        let updates = query
+            .subscribe(store.clone(), "ACCT-1".to_string(), None)
+            .await
+            .unwrap();
+        let mut updates = Box::pin(updates);
+
+        // The first deposit is never published.
+        commit(&store, "ACCT-1", &[10.0]).await;
+        let events = commit(&store, "ACCT-1", &[20.0]).await;
+        query.dispatch("ACCT-1", &events).await;
+        assert_eq!(vec![2, 3], next_sequences(&mut updates, 2).await);
+    }
+
+    #[tokio::test]
+    async fn test_reload_on_lagged() {
+        let store = Arc::new(AppEventStore::memory());
+        commit(&store, "ACCT-1", &[]).await;
+        let (sender, _) = broadcast::channel(1);
+        let query = AccountStreamQuery { sender };
+        let updates = query
+            .subscribe(store.clone(), "ACCT-1".to_string(), None)
+            .await
+            .unwrap();
+        let mut updates = Box::pin(updates);
+
+        let events = commit(&store, "ACCT-1", &[10.0, 20.0, 30.0]).await;
+        query.dispatch("ACCT-1", &events).await;
+        assert_eq!(vec![2, 3, 4], next_sequences(&mut updates, 3).await);
+    }
+}
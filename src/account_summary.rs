@@ -0,0 +1,266 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use cqrs_es::persist::PersistenceError;
+use cqrs_es::EventEnvelope;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Row};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::dead_letter::FallibleQuery;
+use crate::domain::aggregate::{AccountStatus, AccountType, BankAccount};
+use crate::domain::events::BankAccountEvent;
+use crate::queries::{event_time, sql_error};
+
+const INSERT_ACCOUNT_SUMMARY: &str = "
+INSERT INTO account_summary (account_id, account_type, status, balance, holders, opened_at, sequence)
+VALUES ($1, $2, $3, 0, $4, $5, $6)
+ON CONFLICT (account_id) DO NOTHING";
+
+// The sequence guards against applying an event more than once when events are replayed.
+const UPDATE_BALANCE: &str = "
+UPDATE account_summary SET balance = $2, sequence = $3
+  WHERE account_id = $1 AND sequence < $3";
+
+const UPDATE_STATUS: &str = "
+UPDATE account_summary SET status = $2, sequence = $3
+  WHERE account_id = $1 AND sequence < $3";
+
+// Every filter is optional, a null parameter matches all accounts. The holder is matched with a
+// pattern from `contains_pattern`.
+const SELECT_ACCOUNT_SUMMARIES: &str = "
+SELECT account_id, account_type, status, balance, holders, opened_at
+  FROM account_summary
+  WHERE ($1::text IS NULL OR status = $1)
+    AND ($2::text IS NULL OR account_type = $2)
+    AND ($3::double precision IS NULL OR balance >= $3)
+    AND ($4::double precision IS NULL OR balance <= $4)
+    AND ($5::text IS NULL OR EXISTS (SELECT 1 FROM unnest(holders) holder WHERE holder ILIKE $5 ESCAPE '\\'))";
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+// A projection holding a summary of every account, this allows accounts to be listed
+// and searched without loading each `BankAccountView`.
+#[derive(Clone)]
+pub struct AccountSummaryQuery {
+    pool: Pool<Postgres>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AccountSummary {
+    account_id: String,
+    account_type: String,
+    status: String,
+    balance: f64,
+    holders: Vec<String>,
+    opened_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountSort {
+    #[default]
+    AccountId,
+    Balance,
+    OpenedAt,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+// The filters, sorting and paging of an account listing, these are taken from the query
+// string, e.g., `?status=open&min_balance=100&holder=smith&sort=balance&order=desc&limit=20`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AccountFilter {
+    /// Matches only accounts with this status.
+    pub status: Option<AccountStatus>,
+    /// Matches only accounts of this type.
+    pub account_type: Option<AccountType>,
+    /// Matches only accounts with a balance of at least this.
+    pub min_balance: Option<f64>,
+    /// Matches only accounts with a balance of at most this.
+    pub max_balance: Option<f64>,
+    /// Matches any account with a holder name containing this, ignoring case.
+    pub holder: Option<String>,
+    /// The field the accounts are sorted by, the account id by default.
+    #[serde(default)]
+    pub sort: AccountSort,
+    /// The order of the accounts, ascending by default.
+    #[serde(default)]
+    pub order: SortOrder,
+    /// The number of matching accounts to skip.
+    pub offset: Option<i64>,
+    /// The number of accounts in a page, 50 by default and at most 500.
+    pub limit: Option<i64>,
+}
+
+impl AccountFilter {
+    fn offset(&self) -> i64 {
+        self.offset.unwrap_or(0).max(0)
+    }
+
+    fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    // Only known columns are used for sorting, the account id breaks any ties so that
+    // pages are stable.
+    fn order_by(&self) -> String {
+        let order = match self.order {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        };
+        match self.sort {
+            AccountSort::AccountId => format!("ORDER BY account_id {}", order),
+            AccountSort::Balance => format!("ORDER BY balance {}, account_id", order),
+            AccountSort::OpenedAt => format!("ORDER BY opened_at {}, account_id", order),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AccountPage {
+    accounts: Vec<AccountSummary>,
+    // Pass as `offset` to fetch the following page, this is absent on the last page.
+    next_offset: Option<i64>,
+}
+
+impl AccountSummaryQuery {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        Self { pool }
+    }
+
+    pub async fn load(&self, filter: &AccountFilter) -> Result<AccountPage, sqlx::Error> {
+        let offset = filter.offset();
+        let limit = filter.limit();
+        let sql = format!(
+            "{}\n  {}\n  OFFSET $6 LIMIT $7",
+            SELECT_ACCOUNT_SUMMARIES,
+            filter.order_by()
+        );
+        // One additional row is requested to find whether another page follows.
+        let rows = sqlx::query(&sql)
+            .bind(filter.status.map(|status| status.as_str()))
+            .bind(
+                filter
+                    .account_type
+                    .map(|account_type| account_type.as_str()),
+            )
+            .bind(filter.min_balance)
+            .bind(filter.max_balance)
+            .bind(filter.holder.as_deref().map(contains_pattern))
+            .bind(offset)
+            .bind(limit + 1)
+            .fetch_all(&self.pool)
+            .await?;
+        let mut accounts: Vec<AccountSummary> = rows
+            .into_iter()
+            .map(|row| AccountSummary {
+                account_id: row.get("account_id"),
+                account_type: row.get("account_type"),
+                status: row.get("status"),
+                balance: row.get("balance"),
+                holders: row.get("holders"),
+                opened_at: row.get("opened_at"),
+            })
+            .collect();
+        let next_offset = if accounts.len() as i64 > limit {
+            accounts.truncate(limit as usize);
+            Some(offset + limit)
+        } else {
+            None
+        };
+        Ok(AccountPage {
+            accounts,
+            next_offset,
+        })
+    }
+}
+
+#[async_trait]
+impl FallibleQuery<BankAccount> for AccountSummaryQuery {
+    async fn apply(
+        &self,
+        aggregate_id: &str,
+        events: &[EventEnvelope<BankAccount>],
+    ) -> Result<(), PersistenceError> {
+        for event in events {
+            let sequence = event.sequence as i64;
+            let query = match &event.payload {
+                BankAccountEvent::AccountOpened {
+                    account_type,
+                    holders,
+                    ..
+                } => sqlx::query(INSERT_ACCOUNT_SUMMARY)
+                    .bind(aggregate_id)
+                    .bind(account_type.as_str())
+                    .bind(AccountStatus::Open.as_str())
+                    .bind(holders)
+                    .bind(event_time(event))
+                    .bind(sequence),
+                BankAccountEvent::CustomerDepositedMoney { balance, .. }
+                | BankAccountEvent::CustomerWithdrewCash { balance, .. }
+                | BankAccountEvent::CustomerWroteCheck { balance, .. } => {
+                    sqlx::query(UPDATE_BALANCE)
+                        .bind(aggregate_id)
+                        .bind(balance)
+                        .bind(sequence)
+                }
+                BankAccountEvent::AccountClosed => sqlx::query(UPDATE_STATUS)
+                    .bind(aggregate_id)
+                    .bind(AccountStatus::Closed.as_str())
+                    .bind(sequence),
+                BankAccountEvent::TransactionFlagged { .. } => continue,
+            };
+            query.execute(&self.pool).await.map_err(sql_error)?;
+        }
+        Ok(())
+    }
+}
+
+// A LIKE pattern matching any text containing `text`, its wildcards and escape character are
+// escaped so that they only match themselves.
+fn contains_pattern(text: &str) -> String {
+    let mut pattern = String::from("%");
+    for c in text.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+#[cfg(test)]
+mod account_summary_tests {
+    use crate::account_summary::{contains_pattern, AccountFilter, AccountSort, SortOrder};
+
+    #[test]
+    fn test_contains_pattern() {
+        assert_eq!("%smith%", contains_pattern("smith"));
+        assert_eq!(r"%100\%\_a\\b%", contains_pattern(r"100%_a\b"));
+    }
+
+    #[test]
+    fn test_order_by() {
+        assert_eq!(
+            "ORDER BY account_id ASC",
+            AccountFilter::default().order_by()
+        );
+        let filter = AccountFilter {
+            sort: AccountSort::Balance,
+            order: SortOrder::Desc,
+            ..AccountFilter::default()
+        };
+        assert_eq!("ORDER BY balance DESC, account_id", filter.order_by());
+    }
+}
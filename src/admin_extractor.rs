@@ -0,0 +1,56 @@
+use async_trait::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+
+const ADMIN_API_KEY_VAR: &str = "ADMIN_API_KEY";
+pub(crate) const ADMIN_API_KEY_HDR: &str = "X-Admin-Api-Key";
+
+// This is a custom Axum extension that restricts a route to the admin role.
+// A request is accepted only if its `X-Admin-Api-Key` header matches the `ADMIN_API_KEY`
+// environment variable, if no key is configured every admin request is refused.
+pub struct AdminExtractor;
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AdminExtractor
+where
+    S: Send + Sync,
+{
+    type Rejection = AdminExtractionError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let expected = match std::env::var(ADMIN_API_KEY_VAR) {
+            Ok(key) if !key.is_empty() => key,
+            _ => return Err(AdminExtractionError),
+        };
+        let provided = parts
+            .headers
+            .get(ADMIN_API_KEY_HDR)
+            .and_then(|value| value.to_str().ok())
+            .ok_or(AdminExtractionError)?;
+        if keys_match(provided.as_bytes(), expected.as_bytes()) {
+            Ok(AdminExtractor)
+        } else {
+            Err(AdminExtractionError)
+        }
+    }
+}
+
+// Compares every byte so that the time taken does not reveal how much of the key matched.
+fn keys_match(provided: &[u8], expected: &[u8]) -> bool {
+    provided.len() == expected.len()
+        && provided
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+pub struct AdminExtractionError;
+
+impl IntoResponse for AdminExtractionError {
+    fn into_response(self) -> Response {
+        (StatusCode::FORBIDDEN, "admin role required".to_string()).into_response()
+    }
+}
@@ -0,0 +1,221 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use cqrs_es::persist::{PersistenceError, SerializedEvent};
+use cqrs_es::{Aggregate, EventEnvelope, Query};
+use sqlx::{PgConnection, Pool, Postgres, Row};
+use tokio::task::JoinHandle;
+
+use crate::dead_letter::DeadLetterQuery;
+use crate::domain::aggregate::BankAccount;
+use crate::metrics::Metrics;
+use crate::queries::sql_error;
+
+// Only one runner applies events at a time, any others wait for their turn.
+const TRY_LOCK_RUNNER: &str = "SELECT pg_try_advisory_xact_lock($1) AS locked";
+pub(crate) const RUNNER_LOCK_KEY: i64 = 0x7072_6f6a_6563;
+
+// Transaction ids are read and written as bigint, sqlx does not support `xid8`.
+const SELECT_CHECKPOINT: &str = "
+SELECT transaction_id::text::bigint AS transaction_id, position
+  FROM projection_checkpoints
+  WHERE projection = $1";
+
+const UPDATE_CHECKPOINT: &str = "
+INSERT INTO projection_checkpoints (projection, transaction_id, position)
+VALUES ($1, $2::text::xid8, $3)
+ON CONFLICT (projection)
+  DO UPDATE SET transaction_id = $2::text::xid8, position = $3, updated_at = now()";
+
+// Only the events of transactions older than every transaction still in progress are read, any
+// transaction that has yet to commit will then follow the checkpoint.
+const SELECT_EVENTS_AFTER: &str = "
+SELECT aggregate_id, sequence, event_type, event_version, payload, metadata,
+       transaction_id::text::bigint AS transaction_id, global_position
+  FROM events
+  WHERE aggregate_type = $1
+    AND (transaction_id, global_position) > ($2::text::xid8, $3)
+    AND transaction_id < pg_snapshot_xmin(pg_current_snapshot())
+  ORDER BY transaction_id, global_position
+  LIMIT $4";
+
+const COUNT_EVENTS_AFTER: &str = "
+SELECT count(*) AS lag
+  FROM events
+  WHERE aggregate_type = $1 AND (transaction_id, global_position) > ($2::text::xid8, $3)";
+
+pub const PROJECTION_LAG: &str = "projection_lag_events";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+const BATCH_SIZE: i64 = 100;
+
+// Applies committed account events to the projections in a background task rather than while
+// executing each command, so that command latency does not depend on the projections.
+//
+// Events are read from the feed in order of the transaction that committed them and then of their
+// global position, and the transaction and position of the last event applied by each projection
+// are stored as its checkpoint. Positions are not committed in order by concurrent transactions,
+// so reading by position alone could skip an event that commits late. The number of events
+// following a checkpoint is reported as the lag of that projection. Delivery is at-least-once,
+// should the runner stop before its checkpoints are committed those events are applied again.
+pub struct AsyncProjections {
+    pool: Pool<Postgres>,
+    metrics: Arc<Metrics>,
+    account_projections: Vec<DeadLetterQuery<BankAccount>>,
+}
+
+impl AsyncProjections {
+    pub fn new(
+        pool: Pool<Postgres>,
+        metrics: Arc<Metrics>,
+        account_projections: Vec<DeadLetterQuery<BankAccount>>,
+    ) -> Self {
+        Self {
+            pool,
+            metrics,
+            account_projections,
+        }
+    }
+
+    // Runs the projections in a background task for the life of the application.
+    pub fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match self.run_once().await {
+                    // A full batch suggests that more events are waiting.
+                    Ok(true) => continue,
+                    Ok(false) => {}
+                    Err(err) => println!("Error: async projections failed: {}\n", err),
+                }
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        })
+    }
+
+    // Applies the next batch of events to each projection, returns true if any projection
+    // read a full batch.
+    async fn run_once(&self) -> Result<bool, PersistenceError> {
+        let mut tx = self.pool.begin().await.map_err(sql_error)?;
+        let locked: bool = sqlx::query(TRY_LOCK_RUNNER)
+            .bind(RUNNER_LOCK_KEY)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(sql_error)?
+            .get("locked");
+        if !locked {
+            return Ok(false);
+        }
+        let mut full_batch = false;
+        for projection in &self.account_projections {
+            full_batch |= self.catch_up(&mut tx, projection).await?;
+        }
+        tx.commit().await.map_err(sql_error)?;
+        Ok(full_batch)
+    }
+
+    // Failures are recorded as dead letters by the projection, so a failed event does not
+    // hold back the events that follow it.
+    async fn catch_up<A: Aggregate>(
+        &self,
+        conn: &mut PgConnection,
+        projection: &DeadLetterQuery<A>,
+    ) -> Result<bool, PersistenceError> {
+        let mut checkpoint = Checkpoint::load(&mut *conn, projection.projection()).await?;
+        let events = events_after::<A>(&mut *conn, &checkpoint, BATCH_SIZE).await?;
+        let full_batch = events.len() as i64 == BATCH_SIZE;
+        for (event_checkpoint, event) in &events {
+            projection
+                .dispatch(&event.aggregate_id, std::slice::from_ref(event))
+                .await;
+            checkpoint = *event_checkpoint;
+        }
+        if !events.is_empty() {
+            checkpoint.save(&mut *conn, projection.projection()).await?;
+        }
+        let lag: i64 = sqlx::query(COUNT_EVENTS_AFTER)
+            .bind(A::aggregate_type())
+            .bind(checkpoint.transaction_id)
+            .bind(checkpoint.position)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(sql_error)?
+            .get("lag");
+        self.metrics
+            .set(PROJECTION_LAG, projection.projection(), lag as u64);
+        Ok(full_batch)
+    }
+}
+
+// The place of a reader in the event feed, that of the last event that it has read.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Checkpoint {
+    transaction_id: i64,
+    position: i64,
+}
+
+impl Checkpoint {
+    pub(crate) async fn load(
+        conn: &mut PgConnection,
+        reader: &str,
+    ) -> Result<Self, PersistenceError> {
+        Ok(sqlx::query(SELECT_CHECKPOINT)
+            .bind(reader)
+            .fetch_optional(conn)
+            .await
+            .map_err(sql_error)?
+            .map(|row| Self {
+                transaction_id: row.get("transaction_id"),
+                position: row.get("position"),
+            })
+            .unwrap_or_default())
+    }
+
+    pub(crate) async fn save(
+        &self,
+        conn: &mut PgConnection,
+        reader: &str,
+    ) -> Result<(), PersistenceError> {
+        sqlx::query(UPDATE_CHECKPOINT)
+            .bind(reader)
+            .bind(self.transaction_id)
+            .bind(self.position)
+            .execute(conn)
+            .await
+            .map_err(sql_error)?;
+        Ok(())
+    }
+}
+
+// Reads the next events from the feed along with the checkpoint following each.
+pub(crate) async fn events_after<A: Aggregate>(
+    conn: &mut PgConnection,
+    checkpoint: &Checkpoint,
+    limit: i64,
+) -> Result<Vec<(Checkpoint, EventEnvelope<A>)>, PersistenceError> {
+    let rows = sqlx::query(SELECT_EVENTS_AFTER)
+        .bind(A::aggregate_type())
+        .bind(checkpoint.transaction_id)
+        .bind(checkpoint.position)
+        .bind(limit)
+        .fetch_all(conn)
+        .await
+        .map_err(sql_error)?;
+    let mut events = Vec::with_capacity(rows.len());
+    for row in rows {
+        let event = SerializedEvent::new(
+            row.get("aggregate_id"),
+            row.get::<i64, _>("sequence") as usize,
+            A::aggregate_type(),
+            row.get("event_type"),
+            row.get("event_version"),
+            row.get("payload"),
+            row.get("metadata"),
+        );
+        let checkpoint = Checkpoint {
+            transaction_id: row.get("transaction_id"),
+            position: row.get("global_position"),
+        };
+        events.push((checkpoint, EventEnvelope::<A>::try_from(event)?));
+    }
+    Ok(events)
+}
@@ -0,0 +1,60 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use cqrs_es::AggregateError;
+
+use crate::domain::atm::aggregate::{Atm, ATM_INSUFFICIENT_CASH, ATM_NOT_REGISTERED};
+use crate::domain::atm::commands::AtmCommand;
+use crate::event_store::AppCqrs;
+use crate::services::{AtmError, AtmRegistry};
+
+// A reservation holds the cash for this long, a committed withdrawal is normally dispensed
+// within seconds.
+const RESERVATION_MINUTES: i64 = 60;
+// Concurrent reservations at the same ATM conflict, the losers are retried.
+const MAX_RESERVATION_ATTEMPTS: usize = 5;
+
+// Reserves the cash of withdrawals with the `Atm` aggregate, an ATM that is not registered is
+// unknown. Each reservation is committed to the ATM's events, so concurrent withdrawals can not
+// reserve more than the cash on hand.
+pub struct AtmAggregateRegistry {
+    atm_cqrs: Arc<AppCqrs<Atm>>,
+}
+
+impl AtmAggregateRegistry {
+    pub fn new(atm_cqrs: Arc<AppCqrs<Atm>>) -> Self {
+        Self { atm_cqrs }
+    }
+}
+
+#[async_trait]
+impl AtmRegistry for AtmAggregateRegistry {
+    async fn reserve_cash(&self, atm_id: &str, amount: f64) -> Result<String, AtmError> {
+        let withdrawal_id = format!("withdrawal-{}", hex::encode(rand::random::<[u8; 16]>()));
+        let expires_at = Utc::now() + Duration::minutes(RESERVATION_MINUTES);
+        for _ in 0..MAX_RESERVATION_ATTEMPTS {
+            let command = AtmCommand::ReserveCash {
+                amount,
+                withdrawal_id: withdrawal_id.clone(),
+                expires_at,
+            };
+            match self.atm_cqrs.execute(atm_id, command).await {
+                Ok(_) => return Ok(withdrawal_id),
+                Err(AggregateError::AggregateConflict) => continue,
+                Err(AggregateError::UserError(err)) => {
+                    return Err(match err.to_string().as_str() {
+                        ATM_NOT_REGISTERED => AtmError::UnknownAtm,
+                        ATM_INSUFFICIENT_CASH => AtmError::InsufficientCash,
+                        _ => AtmError::AtmOffline,
+                    })
+                }
+                Err(err) => {
+                    println!("Error: {:#?}\n", err);
+                    return Err(AtmError::AtmOffline);
+                }
+            }
+        }
+        Err(AtmError::AtmOffline)
+    }
+}
@@ -2,20 +2,61 @@ use crate::domain::commands::BankAccountCommand;
 use async_trait::async_trait;
 use axum::body::{Bytes, HttpBody};
 use axum::extract::FromRequest;
-use axum::http::{Request, StatusCode};
+use axum::http::{HeaderMap, Request, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::BoxError;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
 
 // This is a custom Axum extension that builds metadata from the inbound request
 // and parses and deserializes the body as the command payload.
-pub struct CommandExtractor(pub HashMap<String, String>, pub BankAccountCommand);
+pub struct CommandExtractor<C = BankAccountCommand>(pub HashMap<String, String>, pub C);
 
 const USER_AGENT_HDR: &str = "User-Agent";
+const TIME_METADATA: &str = "time";
+
+// The time the request was received, as recorded in the command metadata, this falls back to
+// the current time if the metadata has no valid time.
+pub fn request_time(metadata: &HashMap<String, String>) -> DateTime<Utc> {
+    recorded_time(metadata).unwrap_or_else(Utc::now)
+}
+
+// The time recorded in the metadata, if it has a valid time.
+pub fn recorded_time(metadata: &HashMap<String, String>) -> Option<DateTime<Utc>> {
+    metadata
+        .get(TIME_METADATA)
+        .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+        .map(|time| time.with_timezone(&Utc))
+}
+
+// Records the current time in metadata that has none, e.g., of commands that were not received
+// as a request, so that every committed event has a time.
+pub fn with_time(mut metadata: HashMap<String, String>) -> HashMap<String, String> {
+    metadata
+        .entry(TIME_METADATA.to_string())
+        .or_insert_with(|| Utc::now().to_rfc3339());
+    metadata
+}
+
+// Builds the metadata submitted with a command from the inbound request, this includes the
+// current date/time, the uri that was called and the user-agent.
+pub fn command_metadata(uri: &str, headers: &HeaderMap) -> HashMap<String, String> {
+    let mut metadata = HashMap::default();
+    metadata.insert(TIME_METADATA.to_string(), Utc::now().to_rfc3339());
+    metadata.insert("uri".to_string(), uri.to_string());
+    if let Some(user_agent) = headers.get(USER_AGENT_HDR) {
+        if let Ok(value) = user_agent.to_str() {
+            metadata.insert(USER_AGENT_HDR.to_string(), value.to_string());
+        }
+    }
+    metadata
+}
 
 #[async_trait]
-impl<S, B> FromRequest<S, B> for CommandExtractor
+impl<S, B, C> FromRequest<S, B> for CommandExtractor<C>
 where
+    C: DeserializeOwned,
     B: HttpBody + Send + 'static,
     B::Data: Send,
     B::Error: Into<BoxError>,
@@ -24,20 +65,11 @@ where
     type Rejection = CommandExtractionError;
 
     async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
-        // Here we are including the current date/time, the uri that was called and the user-agent
-        // in a HashMap that we will submit as metadata with the command.
-        let mut metadata = HashMap::default();
-        metadata.insert("time".to_string(), chrono::Utc::now().to_rfc3339());
-        metadata.insert("uri".to_string(), req.uri().to_string());
-        if let Some(user_agent) = req.headers().get(USER_AGENT_HDR) {
-            if let Ok(value) = user_agent.to_str() {
-                metadata.insert(USER_AGENT_HDR.to_string(), value.to_string());
-            }
-        }
+        let metadata = command_metadata(&req.uri().to_string(), req.headers());
 
         // Parse and deserialize the request body as the command payload.
         let body = Bytes::from_request(req, state).await?;
-        let command: BankAccountCommand = serde_json::from_slice(body.as_ref())?;
+        let command: C = serde_json::from_slice(body.as_ref())?;
         Ok(CommandExtractor(metadata, command))
     }
 }
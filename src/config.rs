@@ -1,37 +1,294 @@
 use std::sync::Arc;
 
-use cqrs_es::Query;
-use postgres_es::{PostgresCqrs, PostgresViewRepository};
+use cqrs_es::mem_store::MemStore;
+use cqrs_es::{Aggregate, Query};
+use postgres_es::PostgresViewRepository;
 use sqlx::{Pool, Postgres};
 
+use crate::account_stream::AccountStreamQuery;
+use crate::account_summary::AccountSummaryQuery;
+use crate::atm_registry::AtmAggregateRegistry;
+use crate::consistency::CommittedVersionQuery;
+use crate::dead_letter::{DeadLetterQuery, DeadLetterRetry, DeadLetters};
 use crate::domain::aggregate::BankAccount;
-use crate::queries::{AccountQuery, BankAccountView, SimpleLoggingQuery};
-use crate::services::{BankAccountServices, HappyPathBankAccountServices};
+use crate::domain::atm::aggregate::Atm;
+use crate::event_logging::{EventLogVerbosity, EventLoggingQuery};
+use crate::event_publisher::{EventPublisher, EventPublishingQuery, FilePublisher, HttpPublisher};
+use crate::event_store::{app_cqrs, AppCqrs, AppEventStore};
+use crate::fault_injection::FaultInjectingBankAccountServices;
+use crate::ledger_entries::LedgerEntriesQuery;
+use crate::mem_view_repository::MemViewRepository;
+use crate::outbox::OutboxDispatcher;
+use crate::queries::{AccountQuery, AccountViewRepository, AtmQuery, AtmViewRepository, AtmViews};
+use crate::reports::ReportQuery;
+use crate::review_queue::ReviewQueue;
+use crate::screening::{RecentWithdrawals, RuleBasedScreening};
+use crate::services::{BankAccountApi, BankAccountServices, HappyPathBankAccountServices};
 
-pub fn cqrs_framework(
-    pool: Pool<Postgres>,
-) -> (
-    Arc<PostgresCqrs<BankAccount>>,
-    Arc<PostgresViewRepository<BankAccountView, BankAccount>>,
-) {
-    // A very simple query that writes each event to stdout.
-    let simple_query = SimpleLoggingQuery {};
+const BANK_ACCOUNT_SERVICES_VAR: &str = "BANK_ACCOUNT_SERVICES";
+const FAULT_INJECTION_RULES_VAR: &str = "FAULT_INJECTION_RULES";
+const DEFAULT_FAULT_INJECTION_RULES: &str = "config/fault_injection.json";
+const PROJECTIONS_VAR: &str = "PROJECTIONS";
+const STORAGE_VAR: &str = "STORAGE";
+const EVENT_LOG_VAR: &str = "EVENT_LOG";
+const EVENT_LOG_REDACT_VAR: &str = "EVENT_LOG_REDACT";
+const DEFAULT_EVENT_LOG_REDACT: &str = "holders";
+const EVENT_PUBLISHER_VAR: &str = "EVENT_PUBLISHER";
+const EVENT_PUBLISHER_FILE_VAR: &str = "EVENT_PUBLISHER_FILE";
+const EVENT_PUBLISHER_URL_VAR: &str = "EVENT_PUBLISHER_URL";
+const DEFAULT_EVENT_PUBLISHER_FILE: &str = "events.jsonl";
+
+// Whether the projections are applied while executing each command, or afterward
+// by `AsyncProjections`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProjectionMode {
+    Inline,
+    Async,
+}
+
+// Set `PROJECTIONS=async` to apply the projections in a background task, by default
+// these are applied inline.
+pub fn projection_mode() -> ProjectionMode {
+    match std::env::var(PROJECTIONS_VAR).as_deref() {
+        Ok("async") => ProjectionMode::Async,
+        Ok("inline") | Err(_) => ProjectionMode::Inline,
+        Ok(other) => panic!("unknown {}: {}", PROJECTIONS_VAR, other),
+    }
+}
+
+// Whether background work (e.g., dispatching the outbox) runs in tasks for the life of the
+// application, or is performed after each request by `BackgroundWork` on hosts that suspend the
+// application between requests, such as AWS Lambda.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hosting {
+    LongRunning,
+    PerRequest,
+}
+
+// Whether the events and views are stored in Postgres, or held in memory for local development.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StorageMode {
+    Postgres,
+    Memory,
+}
+
+// Set `STORAGE=memory` to run the application without a database, the events and the account
+// and ATM views are then held in memory. By default these are stored in Postgres.
+pub fn storage_mode() -> StorageMode {
+    match std::env::var(STORAGE_VAR).as_deref() {
+        Ok("memory") => StorageMode::Memory,
+        Ok("postgres") | Err(_) => StorageMode::Postgres,
+        Ok(other) => panic!("unknown {}: {}", STORAGE_VAR, other),
+    }
+}
+
+// The external services used by the application, these are shared by the aggregate
+// and the outbox dispatcher.
+//
+// Set `BANK_ACCOUNT_SERVICES=fault_injection` to use services that inject latency and errors
+// according to the rules found in `FAULT_INJECTION_RULES` (`config/fault_injection.json` by default).
+pub fn bank_account_api() -> Arc<dyn BankAccountApi> {
+    match std::env::var(BANK_ACCOUNT_SERVICES_VAR).as_deref() {
+        Ok("fault_injection") => {
+            let rules = std::env::var(FAULT_INJECTION_RULES_VAR)
+                .unwrap_or_else(|_| DEFAULT_FAULT_INJECTION_RULES.to_string());
+            let services = FaultInjectingBankAccountServices::from_file(
+                rules,
+                Box::new(HappyPathBankAccountServices),
+            )
+            .expect("unable to configure fault injection");
+            Arc::new(services)
+        }
+        Ok("happy_path") | Err(_) => Arc::new(HappyPathBankAccountServices),
+        Ok(other) => panic!("unknown {}: {}", BANK_ACCOUNT_SERVICES_VAR, other),
+    }
+}
+
+// A query that logs each committed event, or none if `EVENT_LOG=off`.
+//
+// Set `EVENT_LOG=full` to also log the event payloads, by default only a summary of each event
+// is logged. Fields named in `EVENT_LOG_REDACT`, a comma-separated list (`holders` by default),
+// are redacted from the metadata and payloads.
+pub fn event_logging<A: Aggregate>() -> Option<EventLoggingQuery<A>> {
+    let verbosity = match std::env::var(EVENT_LOG_VAR).as_deref() {
+        Ok("off") => return None,
+        Ok("summary") | Err(_) => EventLogVerbosity::Summary,
+        Ok("full") => EventLogVerbosity::Full,
+        Ok(other) => panic!("unknown {}: {}", EVENT_LOG_VAR, other),
+    };
+    let redacted_fields = std::env::var(EVENT_LOG_REDACT_VAR)
+        .unwrap_or_else(|_| DEFAULT_EVENT_LOG_REDACT.to_string())
+        .split(',')
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+        .map(String::from)
+        .collect();
+    Some(EventLoggingQuery::new(verbosity).with_redacted_fields(redacted_fields))
+}
 
+// The sink to which each committed account event is published as a CloudEvent, or none if no
+// `EVENT_PUBLISHER` is configured.
+//
+// Set `EVENT_PUBLISHER=file` to append the events to `EVENT_PUBLISHER_FILE` (`events.jsonl` by
+// default), or `EVENT_PUBLISHER=http` to post them to `EVENT_PUBLISHER_URL`. Events are not
+// written to stdout, which is shared with the application's logs.
+pub fn event_publisher() -> Option<Arc<dyn EventPublisher>> {
+    let publisher: Arc<dyn EventPublisher> = match std::env::var(EVENT_PUBLISHER_VAR).as_deref() {
+        Ok("none") | Err(_) => return None,
+        Ok("stdout") => panic!(
+            "{}=stdout is not supported, stdout is shared with the logs, use {}=file",
+            EVENT_PUBLISHER_VAR, EVENT_PUBLISHER_VAR
+        ),
+        Ok("file") => {
+            let path = std::env::var(EVENT_PUBLISHER_FILE_VAR)
+                .unwrap_or_else(|_| DEFAULT_EVENT_PUBLISHER_FILE.to_string());
+            Arc::new(FilePublisher::open(path).expect("unable to open the event publisher file"))
+        }
+        Ok("http") => {
+            let url = std::env::var(EVENT_PUBLISHER_URL_VAR)
+                .unwrap_or_else(|_| panic!("{} must be set", EVENT_PUBLISHER_URL_VAR));
+            Arc::new(HttpPublisher::new(url))
+        }
+        Ok(other) => panic!("unknown {}: {}", EVENT_PUBLISHER_VAR, other),
+    };
+    Some(publisher)
+}
+
+// The projections of each account, any failure is recorded as a dead letter to be retried.
+pub fn account_projections(
+    pool: &Pool<Postgres>,
+    dead_letters: &DeadLetters,
+) -> Vec<DeadLetterQuery<BankAccount>> {
     // A query that stores the current state of an individual account.
     let account_view_repo = Arc::new(PostgresViewRepository::new("account_query", pool.clone()));
-    let mut account_query = AccountQuery::new(account_view_repo.clone());
+    vec![
+        dead_letters.query("account_query", AccountQuery::new(account_view_repo)),
+        dead_letters.query("ledger_entries", LedgerEntriesQuery::new(pool.clone())),
+        dead_letters.query("account_summary", AccountSummaryQuery::new(pool.clone())),
+        dead_letters.query("review_queue", ReviewQueue::new(pool.clone())),
+        dead_letters.query("daily_report", ReportQuery::new(pool.clone())),
+    ]
+}
 
-    // Without a query error handler there will be no indication if an
-    // error occurs (e.g., database connection failure, missing columns or table).
-    // Consider logging an error or panicking in your own application.
-    account_query.use_error_handler(Box::new(|e| println!("{}", e)));
+// The projections of each ATM, any failure is recorded as a dead letter to be retried.
+pub fn atm_projections(
+    pool: &Pool<Postgres>,
+    dead_letters: &DeadLetters,
+) -> Vec<DeadLetterQuery<Atm>> {
+    // A query that stores the current state and cash inventory of each ATM.
+    let atm_view_repo = Arc::new(PostgresViewRepository::new("atm_query", pool.clone()));
+    vec![dead_letters.query("atm_query", AtmQuery::new(atm_view_repo))]
+}
 
-    // Create and return an event-sourced `CqrsFramework`.
-    let queries: Vec<Box<dyn Query<BankAccount>>> =
-        vec![Box::new(simple_query), Box::new(account_query)];
-    let services = BankAccountServices::new(Box::new(HappyPathBankAccountServices));
+// Without Postgres only the account and ATM views are projected, these are held in memory.
+pub fn memory_projections(
+    dead_letters: &DeadLetters,
+    account_view_repo: Arc<AccountViewRepository>,
+    atm_view_repo: Arc<AtmViewRepository>,
+) -> (Vec<DeadLetterQuery<BankAccount>>, Vec<DeadLetterQuery<Atm>>) {
     (
-        Arc::new(postgres_es::postgres_cqrs(pool, queries, services)),
-        account_view_repo,
+        vec![dead_letters.query("account_query", AccountQuery::new(account_view_repo))],
+        vec![dead_letters.query("atm_query", AtmQuery::new(atm_view_repo))],
     )
 }
+
+// The event stores and the account and ATM views used by the application.
+pub struct Storage {
+    pub store: AppEventStore<BankAccount>,
+    pub atm_store: AppEventStore<Atm>,
+    // Reads the same account events as `store`, e.g., for point in time views and streams.
+    pub account_events: Arc<AppEventStore<BankAccount>>,
+    pub account_query: Arc<AccountViewRepository>,
+    pub atm_query: Arc<AtmViewRepository>,
+    pub atm_views: AtmViews,
+}
+
+pub fn storage(storage_mode: StorageMode, pool: &Pool<Postgres>) -> Storage {
+    match storage_mode {
+        StorageMode::Postgres => Storage {
+            store: AppEventStore::postgres(pool.clone()),
+            atm_store: AppEventStore::postgres(pool.clone()),
+            account_events: Arc::new(AppEventStore::postgres(pool.clone())),
+            account_query: Arc::new(PostgresViewRepository::new("account_query", pool.clone())),
+            atm_query: Arc::new(PostgresViewRepository::new("atm_query", pool.clone())),
+            atm_views: AtmViews::Postgres(pool.clone()),
+        },
+        StorageMode::Memory => {
+            let store = MemStore::default();
+            let atm_views = Arc::new(MemViewRepository::default());
+            Storage {
+                store: AppEventStore::Memory(store.clone()),
+                atm_store: AppEventStore::memory(),
+                account_events: Arc::new(AppEventStore::Memory(store)),
+                account_query: Arc::new(MemViewRepository::default()),
+                atm_query: atm_views.clone(),
+                atm_views: AtmViews::Memory(atm_views),
+            }
+        }
+    }
+}
+
+// The `side_effects` are performed as each event is dispatched, these are only given if there
+// is no outbox.
+pub fn cqrs_framework(
+    store: AppEventStore<BankAccount>,
+    bank_account_api: Arc<dyn BankAccountApi>,
+    projections: Vec<DeadLetterQuery<BankAccount>>,
+    side_effects: Option<OutboxDispatcher>,
+    event_publisher: Option<EventPublishingQuery>,
+    account_stream: AccountStreamQuery,
+    atm_cqrs: Arc<AppCqrs<Atm>>,
+) -> Arc<AppCqrs<BankAccount>> {
+    // Without handling query errors there would be no indication if an error occurs
+    // (e.g., database connection failure, missing columns or table). Each failure is instead
+    // recorded in the `dead_letters` table, counted, and may be retried once resolved.
+    // These projections are absent if they are applied asynchronously.
+    let mut queries: Vec<Box<dyn Query<BankAccount>>> = vec![];
+    if let Some(event_logging) = event_logging() {
+        queries.push(Box::new(event_logging));
+    }
+    for projection in projections {
+        queries.push(Box::new(projection));
+    }
+    if let Some(side_effects) = side_effects {
+        queries.push(Box::new(side_effects));
+    }
+    // Each event is pushed to the subscribers of its account after the projections are updated.
+    queries.push(Box::new(account_stream));
+    queries.push(Box::new(CommittedVersionQuery));
+    if let Some(event_publisher) = event_publisher {
+        queries.push(Box::new(event_publisher));
+    }
+    // Withdrawals are screened against those already committed.
+    let recent_withdrawals = RecentWithdrawals::default();
+    queries.push(Box::new(recent_withdrawals.clone()));
+
+    // Create and return an event-sourced `CqrsFramework`, with Postgres any side effects of the
+    // committed events are recorded in the outbox within the same transaction.
+    // Withdrawals reserve their cash at the registered ATMs.
+    let services = BankAccountServices::new(Box::new(bank_account_api))
+        .with_screening(Box::new(RuleBasedScreening::new(recent_withdrawals)))
+        .with_atm_registry(Box::new(AtmAggregateRegistry::new(atm_cqrs)));
+    Arc::new(app_cqrs(store, queries, services))
+}
+
+pub fn atm_cqrs_framework(
+    store: AppEventStore<Atm>,
+    projections: Vec<DeadLetterQuery<Atm>>,
+) -> Arc<AppCqrs<Atm>> {
+    let mut queries: Vec<Box<dyn Query<Atm>>> = vec![];
+    if let Some(event_logging) = event_logging() {
+        queries.push(Box::new(event_logging));
+    }
+    for projection in projections {
+        queries.push(Box::new(projection));
+    }
+    Arc::new(app_cqrs(store, queries, ()))
+}
+
+// Reprocesses the dead letters of every projection.
+pub fn dead_letter_retry(pool: Pool<Postgres>, dead_letters: DeadLetters) -> DeadLetterRetry {
+    let account_projections = account_projections(&pool, &dead_letters);
+    let atm_projections = atm_projections(&pool, &dead_letters);
+    DeadLetterRetry::new(pool, dead_letters, account_projections, atm_projections)
+}
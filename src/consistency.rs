@@ -0,0 +1,109 @@
+use std::cell::Cell;
+use std::future::Future;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use cqrs_es::persist::PersistenceError;
+use cqrs_es::{EventEnvelope, Query};
+use tokio::time::Instant;
+
+use crate::domain::aggregate::BankAccount;
+use crate::queries::{AccountViewRepository, BankAccountView};
+
+// The header carrying the version of an account, the sequence of its last committed event.
+pub const VERSION_HDR: &str = "X-Aggregate-Version";
+
+const MIN_VERSION_WAIT: Duration = Duration::from_secs(2);
+const MIN_VERSION_POLL: Duration = Duration::from_millis(50);
+
+tokio::task_local! {
+    static COMMITTED_VERSION: Cell<Option<usize>>;
+}
+
+// A query that records the sequence of the last event committed by a command, queries are
+// dispatched within the task executing the command so this is read by `committed_version`.
+pub struct CommittedVersionQuery;
+
+#[async_trait]
+impl Query<BankAccount> for CommittedVersionQuery {
+    async fn dispatch(&self, _aggregate_id: &str, events: &[EventEnvelope<BankAccount>]) {
+        if let Some(event) = events.last() {
+            let _ = COMMITTED_VERSION.try_with(|version| version.set(Some(event.sequence)));
+        }
+    }
+}
+
+// Executes a command and returns the resulting version of the account, or `None` if the
+// command committed no events.
+pub async fn committed_version<F, E>(execute: F) -> Result<Option<usize>, E>
+where
+    F: Future<Output = Result<(), E>>,
+{
+    COMMITTED_VERSION
+        .scope(Cell::new(None), async move {
+            execute.await?;
+            Ok(COMMITTED_VERSION.with(Cell::get))
+        })
+        .await
+}
+
+// A view loaded with a minimum version, the view may be absent if the account does not exist.
+pub enum VersionedView {
+    Current(Option<BankAccountView>),
+    // The projection had not caught up with the requested version in time.
+    Stale(Option<BankAccountView>),
+}
+
+// Loads the view of an account, waiting briefly for it to reach `min_version` if given.
+pub async fn load_min_version(
+    view_repo: &AccountViewRepository,
+    account_id: &str,
+    min_version: Option<usize>,
+) -> Result<VersionedView, PersistenceError> {
+    let min_version = match min_version {
+        Some(min_version) => min_version,
+        None => return Ok(VersionedView::Current(view_repo.load(account_id).await?)),
+    };
+    let deadline = Instant::now() + MIN_VERSION_WAIT;
+    loop {
+        let view = view_repo.load(account_id).await?;
+        if view
+            .as_ref()
+            .is_some_and(|view| view.version >= min_version)
+        {
+            return Ok(VersionedView::Current(view));
+        }
+        if Instant::now() >= deadline {
+            return Ok(VersionedView::Stale(view));
+        }
+        tokio::time::sleep(MIN_VERSION_POLL).await;
+    }
+}
+
+#[cfg(test)]
+mod consistency_tests {
+    use std::collections::HashMap;
+
+    use cqrs_es::{EventEnvelope, Query};
+
+    use crate::consistency::{committed_version, CommittedVersionQuery};
+    use crate::domain::events::BankAccountEvent;
+
+    #[tokio::test]
+    async fn test_committed_version() {
+        let events = [2, 3].map(|sequence| EventEnvelope {
+            aggregate_id: "ACCT-1".to_string(),
+            sequence,
+            payload: BankAccountEvent::AccountClosed,
+            metadata: HashMap::default(),
+        });
+        let version = committed_version(async {
+            CommittedVersionQuery.dispatch("ACCT-1", &events).await;
+            Ok::<_, ()>(())
+        })
+        .await;
+        assert_eq!(Ok(Some(3)), version);
+        let version = committed_version(async { Ok::<_, ()>(()) }).await;
+        assert_eq!(Ok(None), version);
+    }
+}
@@ -0,0 +1,329 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use cqrs_es::persist::{PersistedEventRepository, PersistenceError};
+use cqrs_es::{Aggregate, EventEnvelope, Query};
+use postgres_es::PostgresEventRepository;
+use serde::Serialize;
+use sqlx::{Pool, Postgres, Row};
+use utoipa::ToSchema;
+
+use crate::domain::aggregate::BankAccount;
+use crate::domain::atm::aggregate::Atm;
+use crate::metrics::Metrics;
+use crate::outbox::{requeue_abandoned, OUTBOX_DEAD_LETTERS};
+use crate::queries::sql_error;
+
+const INSERT_DEAD_LETTER: &str = "
+INSERT INTO dead_letters (projection, aggregate_type, aggregate_id, sequence, error)
+VALUES ($1, $2, $3, $4, $5)";
+
+const SELECT_DEAD_LETTERS: &str = "
+SELECT id, projection, aggregate_type, aggregate_id, sequence, error, attempts, failed_at
+  FROM dead_letters
+  WHERE resolved_at IS NULL
+  ORDER BY id";
+
+const SELECT_FAILED_AGGREGATES: &str = "
+SELECT DISTINCT aggregate_id
+  FROM dead_letters
+  WHERE projection = $1 AND aggregate_type = $2 AND resolved_at IS NULL";
+
+const MARK_RESOLVED: &str = "
+UPDATE dead_letters SET resolved_at = now()
+  WHERE projection = $1 AND aggregate_type = $2 AND aggregate_id = $3 AND resolved_at IS NULL";
+
+const MARK_RETRY_FAILED: &str = "
+UPDATE dead_letters SET attempts = attempts + 1, error = $4
+  WHERE projection = $1 AND aggregate_type = $2 AND aggregate_id = $3 AND resolved_at IS NULL";
+
+pub const PROJECTION_FAILURES: &str = "projection_failures_total";
+pub const DEAD_LETTERS_RESOLVED: &str = "dead_letters_resolved_total";
+
+// A projection that reports its failures rather than handling them, so that the failed
+// events can be recorded and reprocessed later.
+#[async_trait]
+pub trait FallibleQuery<A: Aggregate>: Send + Sync {
+    async fn apply(
+        &self,
+        aggregate_id: &str,
+        events: &[EventEnvelope<A>],
+    ) -> Result<(), PersistenceError>;
+
+    // Reprocesses every event of an aggregate after a failure, by default these are simply
+    // applied again so the projection must ignore any events it has already applied.
+    async fn reprocess(
+        &self,
+        aggregate_id: &str,
+        events: &[EventEnvelope<A>],
+    ) -> Result<(), PersistenceError> {
+        self.apply(aggregate_id, events).await
+    }
+}
+
+// Records projection failures in the `dead_letters` table and counts them,
+// along with the aggregate id, sequence and error of each failed event.
+#[derive(Clone)]
+pub struct DeadLetters {
+    pool: Pool<Postgres>,
+    metrics: Arc<Metrics>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeadLetter {
+    id: i64,
+    projection: String,
+    aggregate_type: String,
+    aggregate_id: String,
+    sequence: i64,
+    error: String,
+    attempts: i32,
+    failed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct RetryReport {
+    // The number of aggregates that were reprocessed successfully, and that failed again.
+    resolved: usize,
+    failed: usize,
+    // The number of abandoned outbox messages that were requeued.
+    requeued_messages: u64,
+}
+
+impl DeadLetters {
+    pub fn new(pool: Pool<Postgres>, metrics: Arc<Metrics>) -> Self {
+        Self { pool, metrics }
+    }
+
+    // Wraps a projection so that its failures are recorded.
+    pub fn query<A, Q>(&self, projection: &'static str, query: Q) -> DeadLetterQuery<A>
+    where
+        A: Aggregate,
+        Q: FallibleQuery<A> + 'static,
+    {
+        // Report each projection even before it has failed.
+        self.metrics.add(PROJECTION_FAILURES, projection, 0);
+        DeadLetterQuery {
+            projection,
+            query: Arc::new(query),
+            dead_letters: self.clone(),
+        }
+    }
+
+    pub async fn load(&self) -> Result<Vec<DeadLetter>, sqlx::Error> {
+        let rows = sqlx::query(SELECT_DEAD_LETTERS)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| DeadLetter {
+                id: row.get("id"),
+                projection: row.get("projection"),
+                aggregate_type: row.get("aggregate_type"),
+                aggregate_id: row.get("aggregate_id"),
+                sequence: row.get("sequence"),
+                error: row.get("error"),
+                attempts: row.get("attempts"),
+                failed_at: row.get("failed_at"),
+            })
+            .collect())
+    }
+
+    async fn record<A: Aggregate>(
+        &self,
+        projection: &str,
+        aggregate_id: &str,
+        events: &[EventEnvelope<A>],
+        error: &PersistenceError,
+    ) {
+        println!(
+            "Error: projection {} failed for {}: {}\n",
+            projection, aggregate_id, error
+        );
+        self.metrics.increment(PROJECTION_FAILURES, projection);
+        for event in events {
+            self.insert(
+                projection,
+                &A::aggregate_type(),
+                aggregate_id,
+                event.sequence as i64,
+                &error.to_string(),
+            )
+            .await;
+        }
+    }
+
+    // Records a failed side effect of an event, e.g., an abandoned outbox message.
+    pub async fn record_failure(
+        &self,
+        projection: &str,
+        aggregate_type: &str,
+        aggregate_id: &str,
+        sequence: i64,
+        error: &str,
+    ) {
+        println!(
+            "Error: {} failed for {}: {}\n",
+            projection, aggregate_id, error
+        );
+        self.metrics.increment(PROJECTION_FAILURES, projection);
+        self.insert(projection, aggregate_type, aggregate_id, sequence, error)
+            .await;
+    }
+
+    async fn insert(
+        &self,
+        projection: &str,
+        aggregate_type: &str,
+        aggregate_id: &str,
+        sequence: i64,
+        error: &str,
+    ) {
+        let result = sqlx::query(INSERT_DEAD_LETTER)
+            .bind(projection)
+            .bind(aggregate_type)
+            .bind(aggregate_id)
+            .bind(sequence)
+            .bind(error)
+            .execute(&self.pool)
+            .await;
+        if let Err(err) = result {
+            println!("Error: unable to record dead letter: {}\n", err);
+        }
+    }
+
+    // Reprocesses every aggregate with unresolved dead letters for these projections.
+    async fn retry<A, R>(
+        &self,
+        repo: &R,
+        projections: &[DeadLetterQuery<A>],
+        report: &mut RetryReport,
+    ) -> Result<(), PersistenceError>
+    where
+        A: Aggregate,
+        R: PersistedEventRepository,
+    {
+        for projection in projections {
+            let rows = sqlx::query(SELECT_FAILED_AGGREGATES)
+                .bind(projection.projection)
+                .bind(A::aggregate_type())
+                .fetch_all(&self.pool)
+                .await
+                .map_err(sql_error)?;
+            for row in rows {
+                let aggregate_id: String = row.get("aggregate_id");
+                let mut events = Vec::new();
+                for event in repo.get_events::<A>(&aggregate_id).await? {
+                    events.push(EventEnvelope::<A>::try_from(event)?);
+                }
+                match projection.query.reprocess(&aggregate_id, &events).await {
+                    Ok(_) => {
+                        let resolved = sqlx::query(MARK_RESOLVED)
+                            .bind(projection.projection)
+                            .bind(A::aggregate_type())
+                            .bind(&aggregate_id)
+                            .execute(&self.pool)
+                            .await
+                            .map_err(sql_error)?;
+                        self.metrics.add(
+                            DEAD_LETTERS_RESOLVED,
+                            projection.projection,
+                            resolved.rows_affected(),
+                        );
+                        report.resolved += 1;
+                    }
+                    Err(err) => {
+                        sqlx::query(MARK_RETRY_FAILED)
+                            .bind(projection.projection)
+                            .bind(A::aggregate_type())
+                            .bind(&aggregate_id)
+                            .bind(err.to_string())
+                            .execute(&self.pool)
+                            .await
+                            .map_err(sql_error)?;
+                        report.failed += 1;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// A `Query` that dispatches events to a projection, recording any failure as a dead letter.
+pub struct DeadLetterQuery<A: Aggregate> {
+    projection: &'static str,
+    query: Arc<dyn FallibleQuery<A>>,
+    dead_letters: DeadLetters,
+}
+
+impl<A: Aggregate> DeadLetterQuery<A> {
+    pub fn projection(&self) -> &'static str {
+        self.projection
+    }
+}
+
+impl<A: Aggregate> Clone for DeadLetterQuery<A> {
+    fn clone(&self) -> Self {
+        Self {
+            projection: self.projection,
+            query: self.query.clone(),
+            dead_letters: self.dead_letters.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl<A: Aggregate> Query<A> for DeadLetterQuery<A> {
+    async fn dispatch(&self, aggregate_id: &str, events: &[EventEnvelope<A>]) {
+        if let Err(err) = self.query.apply(aggregate_id, events).await {
+            self.dead_letters
+                .record(self.projection, aggregate_id, events, &err)
+                .await;
+        }
+    }
+}
+
+// Reprocesses the dead letters of every projection.
+#[derive(Clone)]
+pub struct DeadLetterRetry {
+    pool: Pool<Postgres>,
+    dead_letters: DeadLetters,
+    account_projections: Vec<DeadLetterQuery<BankAccount>>,
+    atm_projections: Vec<DeadLetterQuery<Atm>>,
+}
+
+impl DeadLetterRetry {
+    pub fn new(
+        pool: Pool<Postgres>,
+        dead_letters: DeadLetters,
+        account_projections: Vec<DeadLetterQuery<BankAccount>>,
+        atm_projections: Vec<DeadLetterQuery<Atm>>,
+    ) -> Self {
+        Self {
+            pool,
+            dead_letters,
+            account_projections,
+            atm_projections,
+        }
+    }
+
+    pub async fn retry(&self) -> Result<RetryReport, PersistenceError> {
+        let repo = PostgresEventRepository::new(self.pool.clone());
+        let mut report = RetryReport::default();
+        self.dead_letters
+            .retry(&repo, &self.account_projections, &mut report)
+            .await?;
+        self.dead_letters
+            .retry(&repo, &self.atm_projections, &mut report)
+            .await?;
+        report.requeued_messages = requeue_abandoned(&self.pool).await.map_err(sql_error)?;
+        self.dead_letters.metrics.add(
+            DEAD_LETTERS_RESOLVED,
+            OUTBOX_DEAD_LETTERS,
+            report.requeued_messages,
+        );
+        Ok(report)
+    }
+}
@@ -1,20 +1,61 @@
 use async_trait::async_trait;
 use cqrs_es::Aggregate;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-use crate::domain::commands::BankAccountCommand;
+use crate::domain::commands::{BankAccountCommand, CommandEnvelope};
 use crate::domain::events::{BankAccountError, BankAccountEvent};
-use crate::services::BankAccountServices;
+use crate::services::{AtmError, BankAccountServices, ScreeningDecision};
 
-#[derive(Serialize, Deserialize)]
+// The error returned when opening an account that already exists.
+pub const ACCOUNT_ALREADY_OPEN: &str = "account already open";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+pub enum AccountType {
+    #[default]
+    Checking,
+    Savings,
+}
+
+impl AccountType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountType::Checking => "Checking",
+            AccountType::Savings => "Savings",
+        }
+    }
+}
+
+// An account accepts only `OpenAccount` until it is opened, and no command once it is closed.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountStatus {
+    #[default]
+    Unopened,
+    Open,
+    Closed,
+}
+
+impl AccountStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountStatus::Unopened => "unopened",
+            AccountStatus::Open => "open",
+            AccountStatus::Closed => "closed",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct BankAccount {
     account_id: String,
     balance: f64,
+    status: AccountStatus,
 }
 
 #[async_trait]
 impl Aggregate for BankAccount {
-    type Command = BankAccountCommand;
+    type Command = CommandEnvelope;
     type Event = BankAccountEvent;
     type Error = BankAccountError;
     type Services = BankAccountServices;
@@ -31,9 +72,96 @@ impl Aggregate for BankAccount {
         command: Self::Command,
         services: &Self::Services,
     ) -> Result<Vec<Self::Event>, Self::Error> {
+        let CommandEnvelope { command, metadata } = command;
+        self.check_status(&command)?;
+        // Every command is screened for fraud and money laundering before it is processed,
+        // a flagged transaction is accepted but is followed by a `TransactionFlagged` event.
+        let flag = match services.screening.screen(self, &command, &metadata).await {
+            ScreeningDecision::Approve => None,
+            ScreeningDecision::Reject(reason) => {
+                return Err(format!("transaction rejected: {}", reason).as_str().into());
+            }
+            ScreeningDecision::FlagForReview(reason) => Some(reason),
+        };
+        let mut events = self.handle_command(command, services).await?;
+        if let Some(reason) = flag {
+            if let Some(flagged) = events.first().and_then(|event| flagged(event, reason)) {
+                events.push(flagged);
+            }
+        }
+        Ok(events)
+    }
+
+    fn apply(&mut self, event: Self::Event) {
+        match event {
+            BankAccountEvent::AccountOpened { account_id, .. } => {
+                self.account_id = account_id;
+                self.status = AccountStatus::Open;
+            }
+            BankAccountEvent::AccountClosed => {
+                self.status = AccountStatus::Closed;
+            }
+            BankAccountEvent::CustomerDepositedMoney { amount: _, balance } => {
+                self.balance = balance;
+            }
+            BankAccountEvent::CustomerWithdrewCash { balance, .. } => {
+                self.balance = balance;
+            }
+            BankAccountEvent::CustomerWroteCheck {
+                check_number: _,
+                amount: _,
+                balance,
+            } => {
+                self.balance = balance;
+            }
+            BankAccountEvent::TransactionFlagged { .. } => {}
+        }
+    }
+}
+
+impl BankAccount {
+    pub fn account_id(&self) -> &str {
+        &self.account_id
+    }
+
+    pub fn balance(&self) -> f64 {
+        self.balance
+    }
+
+    // Rejects any command other than `OpenAccount` until the account is opened, and any
+    // command at all once it is closed.
+    fn check_status(&self, command: &BankAccountCommand) -> Result<(), BankAccountError> {
+        match (self.status, command) {
+            (AccountStatus::Unopened, BankAccountCommand::OpenAccount { .. }) => Ok(()),
+            (AccountStatus::Unopened, _) => Err("account not open".into()),
+            (AccountStatus::Open, BankAccountCommand::OpenAccount { .. }) => {
+                Err(ACCOUNT_ALREADY_OPEN.into())
+            }
+            (AccountStatus::Open, _) => Ok(()),
+            (AccountStatus::Closed, _) => Err("account closed".into()),
+        }
+    }
+
+    async fn handle_command(
+        &self,
+        command: BankAccountCommand,
+        services: &BankAccountServices,
+    ) -> Result<Vec<BankAccountEvent>, BankAccountError> {
         match command {
-            BankAccountCommand::OpenAccount { account_id } => {
-                Ok(vec![BankAccountEvent::AccountOpened { account_id }])
+            BankAccountCommand::OpenAccount {
+                account_id,
+                account_type,
+                holders,
+            } => Ok(vec![BankAccountEvent::AccountOpened {
+                account_id,
+                account_type,
+                holders,
+            }]),
+            BankAccountCommand::CloseAccount => {
+                if self.balance != 0_f64 {
+                    return Err("account balance must be zero to close".into());
+                }
+                Ok(vec![BankAccountEvent::AccountClosed])
             }
             BankAccountCommand::DepositMoney { amount } => {
                 let balance = self.balance + amount;
@@ -47,17 +175,21 @@ impl Aggregate for BankAccount {
                 if balance < 0_f64 {
                     return Err("funds not available".into());
                 }
-                if services
-                    .services
-                    .atm_withdrawal(&atm_id, amount)
-                    .await
-                    .is_err()
-                {
-                    return Err("atm rule violation".into());
+                let withdrawal_id = match services.atms.reserve_cash(&atm_id, amount).await {
+                    Ok(withdrawal_id) => withdrawal_id,
+                    Err(AtmError::UnknownAtm) => return Err("unknown atm".into()),
+                    Err(AtmError::InsufficientCash) => {
+                        return Err("atm has insufficient cash".into())
+                    }
+                    Err(_) => return Err("atm not active".into()),
                 };
+                // The cash is not dispensed here, the committed event is recorded in the
+                // outbox and the ATM is called only after the commit succeeds.
                 Ok(vec![BankAccountEvent::CustomerWithdrewCash {
                     amount,
+                    atm_id,
                     balance,
+                    withdrawal_id,
                 }])
             }
             BankAccountCommand::WriteCheck {
@@ -70,7 +202,7 @@ impl Aggregate for BankAccount {
                 }
                 if services
                     .services
-                    .validate_check(&self.account_id, &check_number)
+                    .validate_check(&self.account_id, &check_number, amount)
                     .await
                     .is_err()
                 {
@@ -84,27 +216,25 @@ impl Aggregate for BankAccount {
             }
         }
     }
+}
 
-    fn apply(&mut self, event: Self::Event) {
-        match event {
-            BankAccountEvent::AccountOpened { account_id } => {
-                self.account_id = account_id;
-            }
-            BankAccountEvent::CustomerDepositedMoney { amount: _, balance } => {
-                self.balance = balance;
-            }
-            BankAccountEvent::CustomerWithdrewCash { amount: _, balance } => {
-                self.balance = balance;
-            }
-            BankAccountEvent::CustomerWroteCheck {
-                check_number: _,
-                amount: _,
-                balance,
-            } => {
-                self.balance = balance;
-            }
-        }
-    }
+// The event recording that the transaction in `event` was flagged for review.
+fn flagged(event: &BankAccountEvent, reason: String) -> Option<BankAccountEvent> {
+    let (transaction, amount) = match event {
+        BankAccountEvent::CustomerDepositedMoney { amount, .. } => ("deposit", amount),
+        BankAccountEvent::CustomerWithdrewCash { amount, .. } => ("atm withdrawal", amount),
+        BankAccountEvent::CustomerWroteCheck {
+            check_number,
+            amount,
+            ..
+        } => (check_number.as_str(), amount),
+        _ => return None,
+    };
+    Some(BankAccountEvent::TransactionFlagged {
+        transaction: transaction.to_string(),
+        amount: *amount,
+        reason,
+    })
 }
 
 impl Default for BankAccount {
@@ -112,6 +242,7 @@ impl Default for BankAccount {
         BankAccount {
             account_id: "".to_string(),
             balance: 0_f64,
+            status: AccountStatus::Unopened,
         }
     }
 }
@@ -122,19 +253,107 @@ impl Default for BankAccount {
 #[cfg(test)]
 mod aggregate_tests {
     use async_trait::async_trait;
+    use std::collections::HashMap;
     use std::sync::Mutex;
 
     use cqrs_es::test::TestFramework;
 
-    use crate::domain::aggregate::BankAccount;
+    use crate::domain::aggregate::{AccountType, BankAccount, ACCOUNT_ALREADY_OPEN};
     use crate::domain::commands::BankAccountCommand;
     use crate::domain::events::BankAccountEvent;
-    use crate::services::{AtmError, BankAccountApi, BankAccountServices, CheckingError};
+    use crate::services::{
+        AtmError, BankAccountApi, BankAccountServices, CheckingError, ScreeningDecision,
+        TransactionScreening,
+    };
 
     // A test framework that will apply our events and command
     // and verify that the logic works as expected.
     type AccountTestFramework = TestFramework<BankAccount>;
 
+    // The events of an opened account followed by `events`.
+    fn opened_with(events: Vec<BankAccountEvent>) -> Vec<BankAccountEvent> {
+        let opened = BankAccountEvent::AccountOpened {
+            account_id: "ACCT-1".to_string(),
+            account_type: AccountType::Checking,
+            holders: vec![],
+        };
+        [vec![opened], events].concat()
+    }
+
+    fn services() -> BankAccountServices {
+        BankAccountServices::new(Box::new(MockBankAccountServices::default()))
+    }
+
+    #[test]
+    fn test_open_account() {
+        let command = BankAccountCommand::OpenAccount {
+            account_id: "ACCT-1".to_string(),
+            account_type: AccountType::Savings,
+            holders: vec!["A Smith".to_string()],
+        };
+        let expected = BankAccountEvent::AccountOpened {
+            account_id: "ACCT-1".to_string(),
+            account_type: AccountType::Savings,
+            holders: vec!["A Smith".to_string()],
+        };
+        AccountTestFramework::with(services())
+            .given_no_previous_events()
+            .when(command.into())
+            .then_expect_events(vec![expected]);
+    }
+
+    #[test]
+    fn test_open_account_already_open() {
+        let command = BankAccountCommand::OpenAccount {
+            account_id: "ACCT-1".to_string(),
+            account_type: AccountType::Checking,
+            holders: vec![],
+        };
+        AccountTestFramework::with(services())
+            .given(opened_with(vec![]))
+            .when(command.into())
+            .then_expect_error_message(ACCOUNT_ALREADY_OPEN);
+    }
+
+    #[test]
+    fn test_commands_before_open() {
+        let command = BankAccountCommand::DepositMoney { amount: 200.0 };
+        AccountTestFramework::with(services())
+            .given_no_previous_events()
+            .when(command.into())
+            .then_expect_error_message("account not open");
+    }
+
+    #[test]
+    fn test_close_unopened_account() {
+        AccountTestFramework::with(services())
+            .given_no_previous_events()
+            .when(BankAccountCommand::CloseAccount.into())
+            .then_expect_error_message("account not open");
+    }
+
+    #[test]
+    fn test_commands_after_close() {
+        let commands = vec![
+            BankAccountCommand::CloseAccount,
+            BankAccountCommand::WithdrawMoney {
+                amount: 0.0,
+                atm_id: "ATM34f1ba3c".to_string(),
+            },
+            BankAccountCommand::OpenAccount {
+                account_id: "ACCT-1".to_string(),
+                account_type: AccountType::Checking,
+                holders: vec![],
+            },
+        ];
+        for command in commands {
+            AccountTestFramework::with(services())
+                .given(opened_with(vec![BankAccountEvent::AccountClosed]))
+                .when(command.into())
+                .then_expect_error_message("account closed");
+        }
+    }
+
     #[test]
     fn test_deposit_money() {
         let expected = BankAccountEvent::CustomerDepositedMoney {
@@ -146,9 +365,9 @@ mod aggregate_tests {
         // Obtain a new test framework
         AccountTestFramework::with(services)
             // In a test case with no previous events
-            .given_no_previous_events()
+            .given(opened_with(vec![]))
             // Wnen we fire this command
-            .when(command)
+            .when(command.into())
             // then we expect these results
             .then_expect_events(vec![expected]);
     }
@@ -168,13 +387,99 @@ mod aggregate_tests {
 
         AccountTestFramework::with(services)
             // Given this previously applied event
-            .given(vec![previous])
+            .given(opened_with(vec![previous]))
             // When we fire this command
-            .when(command)
+            .when(command.into())
             // Then we expect this resultant event
             .then_expect_events(vec![expected]);
     }
 
+    #[test]
+    fn test_deposit_money_flagged_for_review() {
+        let expected = vec![
+            BankAccountEvent::CustomerDepositedMoney {
+                amount: 20000.0,
+                balance: 20000.0,
+            },
+            BankAccountEvent::TransactionFlagged {
+                transaction: "deposit".to_string(),
+                amount: 20000.0,
+                reason: "large deposit".to_string(),
+            },
+        ];
+        let command = BankAccountCommand::DepositMoney { amount: 20000.0 };
+        let screening = MockScreening(ScreeningDecision::FlagForReview(
+            "large deposit".to_string(),
+        ));
+        let services = BankAccountServices::new(Box::new(MockBankAccountServices::default()))
+            .with_screening(Box::new(screening));
+
+        AccountTestFramework::with(services)
+            .given(opened_with(vec![]))
+            .when(command.into())
+            // A flagged transaction is accepted, but followed by a `TransactionFlagged` event
+            .then_expect_events(expected);
+    }
+
+    #[test]
+    fn test_deposit_money_rejected_by_screening() {
+        let command = BankAccountCommand::DepositMoney { amount: 90000.0 };
+        let screening = MockScreening(ScreeningDecision::Reject("deposit too large".to_string()));
+        let services = BankAccountServices::new(Box::new(MockBankAccountServices::default()))
+            .with_screening(Box::new(screening));
+
+        AccountTestFramework::with(services)
+            .given(opened_with(vec![]))
+            .when(command.into())
+            .then_expect_error_message("transaction rejected: deposit too large");
+    }
+
+    #[test]
+    fn test_close_account() {
+        let previous = vec![
+            BankAccountEvent::CustomerDepositedMoney {
+                amount: 200.0,
+                balance: 200.0,
+            },
+            BankAccountEvent::CustomerWroteCheck {
+                check_number: "1170".to_string(),
+                amount: 200.0,
+                balance: 0.0,
+            },
+        ];
+        let services = BankAccountServices::new(Box::new(MockBankAccountServices::default()));
+
+        AccountTestFramework::with(services)
+            .given(opened_with(previous))
+            .when(BankAccountCommand::CloseAccount.into())
+            .then_expect_events(vec![BankAccountEvent::AccountClosed]);
+    }
+
+    #[test]
+    fn test_close_account_with_balance() {
+        let previous = BankAccountEvent::CustomerDepositedMoney {
+            amount: 200.0,
+            balance: 200.0,
+        };
+        let services = BankAccountServices::new(Box::new(MockBankAccountServices::default()));
+
+        AccountTestFramework::with(services)
+            .given(opened_with(vec![previous]))
+            .when(BankAccountCommand::CloseAccount.into())
+            .then_expect_error_message("account balance must be zero to close");
+    }
+
+    #[test]
+    fn test_deposit_money_account_closed() {
+        let command = BankAccountCommand::DepositMoney { amount: 200.0 };
+        let services = BankAccountServices::new(Box::new(MockBankAccountServices::default()));
+
+        AccountTestFramework::with(services)
+            .given(opened_with(vec![BankAccountEvent::AccountClosed]))
+            .when(command.into())
+            .then_expect_error_message("account closed");
+    }
+
     #[test]
     fn test_withdraw_money() {
         let previous = BankAccountEvent::CustomerDepositedMoney {
@@ -183,29 +488,38 @@ mod aggregate_tests {
         };
         let expected = BankAccountEvent::CustomerWithdrewCash {
             amount: 100.0,
+            atm_id: "ATM34f1ba3c".to_string(),
             balance: 100.0,
+            withdrawal_id: String::new(),
         };
         let services = MockBankAccountServices::default();
-        services.set_atm_withdrawal_response(Ok(()));
         let command = BankAccountCommand::WithdrawMoney {
             amount: 100.0,
             atm_id: "ATM34f1ba3c".to_string(),
         };
 
         AccountTestFramework::with(BankAccountServices::new(Box::new(services)))
-            .given(vec![previous])
-            .when(command)
+            .given(opened_with(vec![previous]))
+            .when(command.into())
             .then_expect_events(vec![expected]);
     }
 
     #[test]
-    fn test_withdraw_money_client_error() {
+    fn test_withdraw_money_atm_not_called() {
         let previous = BankAccountEvent::CustomerDepositedMoney {
             amount: 200.0,
             balance: 200.0,
         };
+        let expected = BankAccountEvent::CustomerWithdrewCash {
+            amount: 100.0,
+            atm_id: "ATM34f1ba3c".to_string(),
+            balance: 100.0,
+            withdrawal_id: String::new(),
+        };
+        // The ATM is only called by the outbox dispatcher after the events are committed,
+        // so an ATM failure can not affect the command.
         let services = MockBankAccountServices::default();
-        services.set_atm_withdrawal_response(Err(AtmError));
+        services.set_atm_withdrawal_response(Err(AtmError::AtmOffline));
         let command = BankAccountCommand::WithdrawMoney {
             amount: 100.0,
             atm_id: "ATM34f1ba3c".to_string(),
@@ -213,9 +527,9 @@ mod aggregate_tests {
 
         let services = BankAccountServices::new(Box::new(services));
         AccountTestFramework::with(services)
-            .given(vec![previous])
-            .when(command)
-            .then_expect_error_message("atm rule violation");
+            .given(opened_with(vec![previous]))
+            .when(command.into())
+            .then_expect_events(vec![expected]);
     }
 
     #[test]
@@ -227,8 +541,8 @@ mod aggregate_tests {
 
         let services = BankAccountServices::new(Box::new(MockBankAccountServices::default()));
         AccountTestFramework::with(services)
-            .given_no_previous_events()
-            .when(command)
+            .given(opened_with(vec![]))
+            .when(command.into())
             // Here we expect an error rather than any events
             .then_expect_error_message("funds not available")
     }
@@ -253,8 +567,8 @@ mod aggregate_tests {
         };
 
         AccountTestFramework::with(services)
-            .given(vec![previous])
-            .when(command)
+            .given(opened_with(vec![previous]))
+            .when(command.into())
             .then_expect_events(vec![expected]);
     }
 
@@ -265,7 +579,7 @@ mod aggregate_tests {
             balance: 200.0,
         };
         let services = MockBankAccountServices::default();
-        services.set_validate_check_response(Err(CheckingError));
+        services.set_validate_check_response(Err(CheckingError::InvalidCheckNumber));
         let services = BankAccountServices::new(Box::new(services));
         let command = BankAccountCommand::WriteCheck {
             check_number: "1170".to_string(),
@@ -273,8 +587,8 @@ mod aggregate_tests {
         };
 
         AccountTestFramework::with(services)
-            .given(vec![previous])
-            .when(command)
+            .given(opened_with(vec![previous]))
+            .when(command.into())
             .then_expect_error_message("check invalid");
     }
 
@@ -287,8 +601,8 @@ mod aggregate_tests {
 
         let services = BankAccountServices::new(Box::new(MockBankAccountServices::default()));
         AccountTestFramework::with(services)
-            .given_no_previous_events()
-            .when(command)
+            .given(opened_with(vec![]))
+            .when(command.into())
             .then_expect_error_message("funds not available")
     }
 
@@ -317,7 +631,12 @@ mod aggregate_tests {
 
     #[async_trait]
     impl BankAccountApi for MockBankAccountServices {
-        async fn atm_withdrawal(&self, _atm_id: &str, _amount: f64) -> Result<(), AtmError> {
+        async fn atm_withdrawal(
+            &self,
+            _atm_id: &str,
+            _amount: f64,
+            _idempotency_key: &str,
+        ) -> Result<(), AtmError> {
             self.atm_withdrawal_response.lock().unwrap().take().unwrap()
         }
 
@@ -325,8 +644,23 @@ mod aggregate_tests {
             &self,
             _account_id: &str,
             _check_number: &str,
+            _amount: f64,
         ) -> Result<(), CheckingError> {
             self.validate_check_response.lock().unwrap().take().unwrap()
         }
     }
+
+    struct MockScreening(ScreeningDecision);
+
+    #[async_trait]
+    impl TransactionScreening for MockScreening {
+        async fn screen(
+            &self,
+            _account: &BankAccount,
+            _command: &BankAccountCommand,
+            _metadata: &HashMap<String, String>,
+        ) -> ScreeningDecision {
+            self.0.clone()
+        }
+    }
 }
@@ -0,0 +1,430 @@
+use std::collections::{HashMap, HashSet};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use cqrs_es::Aggregate;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::domain::atm::commands::AtmCommand;
+use crate::domain::atm::events::{AtmEvent, AtmRegistryError};
+
+// The errors that a withdrawal reports as an unknown ATM or an ATM without enough cash.
+pub const ATM_NOT_REGISTERED: &str = "atm not registered";
+pub const ATM_INSUFFICIENT_CASH: &str = "atm has insufficient cash";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, ToSchema)]
+pub enum AtmStatus {
+    #[default]
+    Unregistered,
+    Active,
+    Decommissioned,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Atm {
+    atm_id: String,
+    status: AtmStatus,
+    cash_on_hand: f64,
+    // The withdrawals that have been recorded, so that a retried withdrawal is recorded once.
+    dispensed_withdrawals: HashSet<String>,
+    // The cash reserved for withdrawals that have not yet been dispensed, by withdrawal.
+    reservations: HashMap<String, CashReservation>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CashReservation {
+    amount: f64,
+    expires_at: DateTime<Utc>,
+}
+
+#[async_trait]
+impl Aggregate for Atm {
+    type Command = AtmCommand;
+    type Event = AtmEvent;
+    type Error = AtmRegistryError;
+    type Services = ();
+
+    fn aggregate_type() -> String {
+        "atm".to_string()
+    }
+
+    async fn handle(
+        &self,
+        command: Self::Command,
+        _services: &Self::Services,
+    ) -> Result<Vec<Self::Event>, Self::Error> {
+        match command {
+            AtmCommand::RegisterAtm { atm_id, location } => {
+                if self.status != AtmStatus::Unregistered {
+                    return Err("atm already registered".into());
+                }
+                Ok(vec![AtmEvent::AtmRegistered { atm_id, location }])
+            }
+            AtmCommand::DecommissionAtm => {
+                if self.status != AtmStatus::Active {
+                    return Err("atm not active".into());
+                }
+                Ok(vec![AtmEvent::AtmDecommissioned])
+            }
+            AtmCommand::LoadCash { amount } => {
+                if self.status != AtmStatus::Active {
+                    return Err("atm not active".into());
+                }
+                if amount <= 0_f64 {
+                    return Err("invalid amount".into());
+                }
+                Ok(vec![AtmEvent::CashLoaded {
+                    amount,
+                    cash_on_hand: self.cash_on_hand + amount,
+                }])
+            }
+            AtmCommand::ReserveCash {
+                amount,
+                withdrawal_id,
+                expires_at,
+            } => {
+                if self.status == AtmStatus::Unregistered {
+                    return Err(ATM_NOT_REGISTERED.into());
+                }
+                if self.status != AtmStatus::Active {
+                    return Err("atm not active".into());
+                }
+                if self.reservations.contains_key(&withdrawal_id) {
+                    return Ok(vec![]);
+                }
+                if amount <= 0_f64 {
+                    return Err("invalid amount".into());
+                }
+                if self.available_cash(Utc::now()) < amount {
+                    return Err(ATM_INSUFFICIENT_CASH.into());
+                }
+                Ok(vec![AtmEvent::CashReserved {
+                    amount,
+                    withdrawal_id,
+                    expires_at,
+                }])
+            }
+            // Records cash that has already left the machine, so this is accepted even if the
+            // ATM has since been decommissioned. Withdrawals are validated before this point,
+            // and cash reserved for the withdrawal is always dispensed.
+            AtmCommand::DispenseCash {
+                amount,
+                withdrawal_id,
+            } => {
+                if self.status == AtmStatus::Unregistered {
+                    return Err(ATM_NOT_REGISTERED.into());
+                }
+                if self.dispensed_withdrawals.contains(&withdrawal_id) {
+                    return Ok(vec![]);
+                }
+                if amount <= 0_f64 {
+                    return Err("invalid amount".into());
+                }
+                if !self.reservations.contains_key(&withdrawal_id)
+                    && self.available_cash(Utc::now()) < amount
+                {
+                    return Err(ATM_INSUFFICIENT_CASH.into());
+                }
+                Ok(vec![AtmEvent::CashDispensed {
+                    amount,
+                    cash_on_hand: self.cash_on_hand - amount,
+                    withdrawal_id,
+                }])
+            }
+        }
+    }
+
+    fn apply(&mut self, event: Self::Event) {
+        match event {
+            AtmEvent::AtmRegistered {
+                atm_id,
+                location: _,
+            } => {
+                self.atm_id = atm_id;
+                self.status = AtmStatus::Active;
+            }
+            AtmEvent::AtmDecommissioned => {
+                self.status = AtmStatus::Decommissioned;
+            }
+            AtmEvent::CashLoaded {
+                amount: _,
+                cash_on_hand,
+            } => {
+                self.cash_on_hand = cash_on_hand;
+            }
+            AtmEvent::CashReserved {
+                amount,
+                withdrawal_id,
+                expires_at,
+            } => {
+                let reservation = CashReservation { amount, expires_at };
+                self.reservations.insert(withdrawal_id, reservation);
+            }
+            AtmEvent::CashDispensed {
+                amount: _,
+                cash_on_hand,
+                withdrawal_id,
+            } => {
+                self.cash_on_hand = cash_on_hand;
+                self.reservations.remove(&withdrawal_id);
+                self.dispensed_withdrawals.insert(withdrawal_id);
+            }
+        }
+    }
+}
+
+impl Atm {
+    // The cash on hand that is not held by an unexpired reservation.
+    fn available_cash(&self, now: DateTime<Utc>) -> f64 {
+        let reserved: f64 = self
+            .reservations
+            .values()
+            .filter(|reservation| reservation.expires_at > now)
+            .map(|reservation| reservation.amount)
+            .sum();
+        self.cash_on_hand - reserved
+    }
+}
+
+impl Default for Atm {
+    fn default() -> Self {
+        Atm {
+            atm_id: "".to_string(),
+            status: AtmStatus::Unregistered,
+            cash_on_hand: 0_f64,
+            dispensed_withdrawals: HashSet::new(),
+            reservations: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod atm_tests {
+    use chrono::{DateTime, Duration, Utc};
+    use cqrs_es::test::TestFramework;
+
+    use crate::domain::atm::aggregate::Atm;
+    use crate::domain::atm::commands::AtmCommand;
+    use crate::domain::atm::events::AtmEvent;
+
+    type AtmTestFramework = TestFramework<Atm>;
+
+    fn registered() -> AtmEvent {
+        AtmEvent::AtmRegistered {
+            atm_id: "ATM-N468290".to_string(),
+            location: "Main St".to_string(),
+        }
+    }
+
+    fn dispense_cash(amount: f64) -> AtmCommand {
+        AtmCommand::DispenseCash {
+            amount,
+            withdrawal_id: "outbox-1".to_string(),
+        }
+    }
+
+    fn loaded(amount: f64) -> AtmEvent {
+        AtmEvent::CashLoaded {
+            amount,
+            cash_on_hand: amount,
+        }
+    }
+
+    fn reserved(amount: f64, withdrawal_id: &str, expires_at: DateTime<Utc>) -> AtmEvent {
+        AtmEvent::CashReserved {
+            amount,
+            withdrawal_id: withdrawal_id.to_string(),
+            expires_at,
+        }
+    }
+
+    fn reserve_cash(amount: f64, expires_at: DateTime<Utc>) -> AtmCommand {
+        AtmCommand::ReserveCash {
+            amount,
+            withdrawal_id: "withdrawal-2".to_string(),
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn test_register_atm() {
+        let command = AtmCommand::RegisterAtm {
+            atm_id: "ATM-N468290".to_string(),
+            location: "Main St".to_string(),
+        };
+        AtmTestFramework::with(())
+            .given_no_previous_events()
+            .when(command)
+            .then_expect_events(vec![registered()]);
+    }
+
+    #[test]
+    fn test_register_atm_twice() {
+        let command = AtmCommand::RegisterAtm {
+            atm_id: "ATM-N468290".to_string(),
+            location: "Main St".to_string(),
+        };
+        AtmTestFramework::with(())
+            .given(vec![registered()])
+            .when(command)
+            .then_expect_error_message("atm already registered");
+    }
+
+    #[test]
+    fn test_load_cash() {
+        let previous = AtmEvent::CashLoaded {
+            amount: 1000.0,
+            cash_on_hand: 1000.0,
+        };
+        let expected = AtmEvent::CashLoaded {
+            amount: 500.0,
+            cash_on_hand: 1500.0,
+        };
+        AtmTestFramework::with(())
+            .given(vec![registered(), previous])
+            .when(AtmCommand::LoadCash { amount: 500.0 })
+            .then_expect_events(vec![expected]);
+    }
+
+    #[test]
+    fn test_load_cash_decommissioned() {
+        AtmTestFramework::with(())
+            .given(vec![registered(), AtmEvent::AtmDecommissioned])
+            .when(AtmCommand::LoadCash { amount: 500.0 })
+            .then_expect_error_message("atm not active");
+    }
+
+    #[test]
+    fn test_dispense_cash() {
+        let previous = AtmEvent::CashLoaded {
+            amount: 1000.0,
+            cash_on_hand: 1000.0,
+        };
+        let expected = AtmEvent::CashDispensed {
+            amount: 400.0,
+            cash_on_hand: 600.0,
+            withdrawal_id: "outbox-1".to_string(),
+        };
+        AtmTestFramework::with(())
+            .given(vec![registered(), previous])
+            .when(dispense_cash(400.0))
+            .then_expect_events(vec![expected]);
+    }
+
+    #[test]
+    fn test_dispense_cash_retried() {
+        let previous = vec![
+            registered(),
+            AtmEvent::CashLoaded {
+                amount: 1000.0,
+                cash_on_hand: 1000.0,
+            },
+            AtmEvent::CashDispensed {
+                amount: 400.0,
+                cash_on_hand: 600.0,
+                withdrawal_id: "outbox-1".to_string(),
+            },
+        ];
+        AtmTestFramework::with(())
+            .given(previous)
+            .when(dispense_cash(400.0))
+            .then_expect_events(vec![]);
+    }
+
+    #[test]
+    fn test_dispense_cash_invalid() {
+        let previous = AtmEvent::CashLoaded {
+            amount: 100.0,
+            cash_on_hand: 100.0,
+        };
+        AtmTestFramework::with(())
+            .given(vec![registered(), previous.clone()])
+            .when(dispense_cash(-40.0))
+            .then_expect_error_message("invalid amount");
+        AtmTestFramework::with(())
+            .given(vec![registered(), previous])
+            .when(dispense_cash(400.0))
+            .then_expect_error_message("atm has insufficient cash");
+    }
+
+    #[test]
+    fn test_reserve_cash() {
+        let expires_at = Utc::now() + Duration::minutes(10);
+        let previous = vec![
+            registered(),
+            loaded(1000.0),
+            reserved(700.0, "withdrawal-1", expires_at),
+        ];
+        AtmTestFramework::with(())
+            .given(previous)
+            .when(reserve_cash(300.0, expires_at))
+            .then_expect_events(vec![reserved(300.0, "withdrawal-2", expires_at)]);
+    }
+
+    #[test]
+    fn test_reserve_reserved_cash() {
+        let expires_at = Utc::now() + Duration::minutes(10);
+        let previous = vec![
+            registered(),
+            loaded(1000.0),
+            reserved(700.0, "withdrawal-1", expires_at),
+        ];
+        AtmTestFramework::with(())
+            .given(previous)
+            .when(reserve_cash(400.0, expires_at))
+            .then_expect_error_message("atm has insufficient cash");
+    }
+
+    #[test]
+    fn test_reserve_cash_after_expiry() {
+        let expires_at = Utc::now() + Duration::minutes(10);
+        let expired = Utc::now() - Duration::minutes(1);
+        let previous = vec![
+            registered(),
+            loaded(1000.0),
+            reserved(700.0, "withdrawal-1", expired),
+        ];
+        AtmTestFramework::with(())
+            .given(previous)
+            .when(reserve_cash(400.0, expires_at))
+            .then_expect_events(vec![reserved(400.0, "withdrawal-2", expires_at)]);
+    }
+
+    #[test]
+    fn test_dispense_reserved_cash() {
+        let expires_at = Utc::now() + Duration::minutes(10);
+        let previous = vec![
+            registered(),
+            loaded(1000.0),
+            reserved(400.0, "outbox-1", expires_at),
+            reserved(600.0, "withdrawal-2", expires_at),
+        ];
+        let expected = AtmEvent::CashDispensed {
+            amount: 400.0,
+            cash_on_hand: 600.0,
+            withdrawal_id: "outbox-1".to_string(),
+        };
+        AtmTestFramework::with(())
+            .given(previous.clone())
+            .when(dispense_cash(400.0))
+            .then_expect_events(vec![expected]);
+        // Cash that is not reserved may not be taken from the reservations of others.
+        let command = AtmCommand::DispenseCash {
+            amount: 400.0,
+            withdrawal_id: "outbox-3".to_string(),
+        };
+        AtmTestFramework::with(())
+            .given(previous)
+            .when(command)
+            .then_expect_error_message("atm has insufficient cash");
+    }
+
+    #[test]
+    fn test_dispense_cash_unregistered() {
+        AtmTestFramework::with(())
+            .given_no_previous_events()
+            .when(dispense_cash(400.0))
+            .then_expect_error_message("atm not registered");
+    }
+}
@@ -0,0 +1,29 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub enum AtmCommand {
+    RegisterAtm {
+        atm_id: String,
+        location: String,
+    },
+    DecommissionAtm,
+    LoadCash {
+        amount: f64,
+    },
+    // Issued only by account withdrawals before they are committed, so that concurrent
+    // withdrawals can not take the same cash. A reservation that is not dispensed by
+    // `expires_at` (e.g., if the withdrawal was never committed) no longer holds the cash.
+    ReserveCash {
+        amount: f64,
+        withdrawal_id: String,
+        expires_at: DateTime<Utc>,
+    },
+    // Issued only by the outbox dispatcher once a withdrawal has left the machine, a repeated
+    // `withdrawal_id` is ignored.
+    DispenseCash {
+        amount: f64,
+        withdrawal_id: String,
+    },
+}
@@ -0,0 +1,61 @@
+use chrono::{DateTime, Utc};
+use cqrs_es::DomainEvent;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Debug, Display, Formatter};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum AtmEvent {
+    AtmRegistered {
+        atm_id: String,
+        location: String,
+    },
+    AtmDecommissioned,
+    CashLoaded {
+        amount: f64,
+        cash_on_hand: f64,
+    },
+    CashReserved {
+        amount: f64,
+        withdrawal_id: String,
+        expires_at: DateTime<Utc>,
+    },
+    CashDispensed {
+        amount: f64,
+        cash_on_hand: f64,
+        #[serde(default)]
+        withdrawal_id: String,
+    },
+}
+
+impl DomainEvent for AtmEvent {
+    fn event_type(&self) -> String {
+        match self {
+            AtmEvent::AtmRegistered { .. } => "AtmRegistered".to_string(),
+            AtmEvent::AtmDecommissioned => "AtmDecommissioned".to_string(),
+            AtmEvent::CashLoaded { .. } => "CashLoaded".to_string(),
+            AtmEvent::CashReserved { .. } => "CashReserved".to_string(),
+            AtmEvent::CashDispensed { .. } => "CashDispensed".to_string(),
+        }
+    }
+
+    fn event_version(&self) -> String {
+        "1.0".to_string()
+    }
+}
+
+#[derive(Debug)]
+pub struct AtmRegistryError(String);
+
+impl From<&str> for AtmRegistryError {
+    fn from(msg: &str) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+impl Display for AtmRegistryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for AtmRegistryError {}
@@ -1,9 +1,48 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::domain::aggregate::AccountType;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub enum BankAccountCommand {
-    OpenAccount { account_id: String },
-    DepositMoney { amount: f64 },
-    WithdrawMoney { amount: f64, atm_id: String },
-    WriteCheck { check_number: String, amount: f64 },
+    OpenAccount {
+        account_id: String,
+        #[serde(default)]
+        account_type: AccountType,
+        #[serde(default)]
+        holders: Vec<String>,
+    },
+    CloseAccount,
+    DepositMoney {
+        amount: f64,
+    },
+    WithdrawMoney {
+        amount: f64,
+        atm_id: String,
+    },
+    WriteCheck {
+        check_number: String,
+        amount: f64,
+    },
+}
+
+// A command along with the metadata of the request that issued it, this allows the
+// aggregate (and the services it calls) to consider who or what is making the request.
+#[derive(Debug)]
+pub struct CommandEnvelope {
+    pub command: BankAccountCommand,
+    pub metadata: HashMap<String, String>,
+}
+
+impl CommandEnvelope {
+    pub fn new(command: BankAccountCommand, metadata: HashMap<String, String>) -> Self {
+        Self { command, metadata }
+    }
+}
+
+impl From<BankAccountCommand> for CommandEnvelope {
+    fn from(command: BankAccountCommand) -> Self {
+        Self::new(command, HashMap::default())
+    }
 }
@@ -2,33 +2,52 @@ use cqrs_es::DomainEvent;
 use serde::{Deserialize, Serialize};
 use std::fmt::{Debug, Display, Formatter};
 
+use crate::domain::aggregate::AccountType;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum BankAccountEvent {
     AccountOpened {
         account_id: String,
+        #[serde(default)]
+        account_type: AccountType,
+        #[serde(default)]
+        holders: Vec<String>,
     },
+    AccountClosed,
     CustomerDepositedMoney {
         amount: f64,
         balance: f64,
     },
     CustomerWithdrewCash {
         amount: f64,
+        #[serde(default)]
+        atm_id: String,
         balance: f64,
+        // The reservation of the cash at the ATM, this is empty if no cash was reserved.
+        #[serde(default)]
+        withdrawal_id: String,
     },
     CustomerWroteCheck {
         check_number: String,
         amount: f64,
         balance: f64,
     },
+    TransactionFlagged {
+        transaction: String,
+        amount: f64,
+        reason: String,
+    },
 }
 
 impl DomainEvent for BankAccountEvent {
     fn event_type(&self) -> String {
         match self {
             BankAccountEvent::AccountOpened { .. } => "AccountOpened".to_string(),
+            BankAccountEvent::AccountClosed => "AccountClosed".to_string(),
             BankAccountEvent::CustomerDepositedMoney { .. } => "CustomerDepositedMoney".to_string(),
             BankAccountEvent::CustomerWithdrewCash { .. } => "CustomerWithdrewCash".to_string(),
             BankAccountEvent::CustomerWroteCheck { .. } => "CustomerWroteCheck".to_string(),
+            BankAccountEvent::TransactionFlagged { .. } => "TransactionFlagged".to_string(),
         }
     }
 
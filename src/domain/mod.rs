@@ -1,3 +1,4 @@
 pub mod aggregate;
+pub mod atm;
 pub mod commands;
 pub mod events;
@@ -0,0 +1,83 @@
+use cqrs_es::persist::SerializedEvent;
+use cqrs_es::{AggregateError, EventStore};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::domain::aggregate::BankAccount;
+use crate::domain::events::BankAccountError;
+use crate::event_store::AppEventStore;
+
+// An event as it is stored in the event store, for support engineers that need to see
+// exactly what was committed rather than a projection.
+#[derive(Debug, PartialEq, Serialize, ToSchema)]
+pub struct StoredEvent {
+    sequence: usize,
+    event_type: String,
+    event_version: String,
+    payload: Value,
+    metadata: Value,
+}
+
+impl From<SerializedEvent> for StoredEvent {
+    fn from(event: SerializedEvent) -> Self {
+        Self {
+            sequence: event.sequence,
+            event_type: event.event_type,
+            event_version: event.event_version,
+            payload: event.payload,
+            metadata: event.metadata,
+        }
+    }
+}
+
+// An inclusive range of event sequences, taken from the query string,
+// e.g., `?from_sequence=3&to_sequence=10`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventRange {
+    /// The sequence of the first event.
+    pub from_sequence: Option<usize>,
+    /// The sequence of the last event.
+    pub to_sequence: Option<usize>,
+}
+
+impl EventRange {
+    fn includes(&self, sequence: usize) -> bool {
+        self.from_sequence.is_none_or(|from| sequence >= from)
+            && self.to_sequence.is_none_or(|to| sequence <= to)
+    }
+}
+
+// Loads the committed events of an account within the range, in sequence order.
+pub async fn load_account_events(
+    store: &AppEventStore<BankAccount>,
+    account_id: &str,
+    range: &EventRange,
+) -> Result<Vec<StoredEvent>, AggregateError<BankAccountError>> {
+    let mut events = Vec::new();
+    for event in store.load_events(account_id).await? {
+        if range.includes(event.sequence) {
+            events.push(SerializedEvent::try_from(&event)?.into());
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod event_log_tests {
+    use crate::event_log::EventRange;
+
+    #[test]
+    fn test_range() {
+        let range = EventRange {
+            from_sequence: Some(3),
+            to_sequence: Some(5),
+        };
+        assert!(!range.includes(2));
+        assert!(range.includes(3));
+        assert!(range.includes(5));
+        assert!(!range.includes(6));
+        assert!(EventRange::default().includes(1));
+    }
+}
@@ -0,0 +1,150 @@
+use std::marker::PhantomData;
+
+use async_trait::async_trait;
+use cqrs_es::{Aggregate, DomainEvent, EventEnvelope, Query};
+use serde_json::Value;
+use tracing_subscriber::EnvFilter;
+
+const REDACTED: &str = "[redacted]";
+
+// The log level of the application may be set with `RUST_LOG`, e.g., `RUST_LOG=events=warn`
+// silences the event log. Database statements are only logged at `warn` by default.
+const DEFAULT_LOG_FILTER: &str = "info,sqlx=warn";
+
+// Configures the application to log one JSON object per line.
+pub fn init_tracing() {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER));
+    tracing_subscriber::fmt()
+        .json()
+        .flatten_event(true)
+        .with_env_filter(filter)
+        .init();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventLogVerbosity {
+    // The aggregate id, sequence, event type and metadata of each event.
+    Summary,
+    // The event payload is also logged.
+    Full,
+}
+
+// A query that logs each committed event as a structured JSON line under the `events` target.
+// Any metadata or payload field with a redacted name is replaced, at any depth. Names are
+// compared ignoring case, as the metadata holds HTTP headers such as `User-Agent`.
+pub struct EventLoggingQuery<A> {
+    verbosity: EventLogVerbosity,
+    redacted_fields: Vec<String>,
+    phantom: PhantomData<A>,
+}
+
+impl<A> EventLoggingQuery<A> {
+    pub fn new(verbosity: EventLogVerbosity) -> Self {
+        Self {
+            verbosity,
+            redacted_fields: vec![],
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn with_redacted_fields(mut self, redacted_fields: Vec<String>) -> Self {
+        self.redacted_fields = redacted_fields;
+        self
+    }
+
+    fn redact(&self, value: &mut Value) {
+        match value {
+            Value::Object(fields) => {
+                for (name, field) in fields.iter_mut() {
+                    if self
+                        .redacted_fields
+                        .iter()
+                        .any(|redacted| redacted.eq_ignore_ascii_case(name))
+                    {
+                        *field = Value::String(REDACTED.to_string());
+                    } else {
+                        self.redact(field);
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|value| self.redact(value)),
+            _ => {}
+        }
+    }
+
+    fn redacted_json<T: serde::Serialize>(&self, value: &T) -> Result<String, serde_json::Error> {
+        let mut value = serde_json::to_value(value)?;
+        self.redact(&mut value);
+        serde_json::to_string(&value)
+    }
+}
+
+fn warn_unable_to_log(aggregate_id: &str, sequence: usize, err: serde_json::Error) {
+    tracing::warn!(target: "events", aggregate_id, sequence, %err, "unable to log event");
+}
+
+#[async_trait]
+impl<A: Aggregate> Query<A> for EventLoggingQuery<A> {
+    async fn dispatch(&self, aggregate_id: &str, events: &[EventEnvelope<A>]) {
+        for event in events {
+            let event_type = event.payload.event_type();
+            let metadata = match self.redacted_json(&event.metadata) {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    warn_unable_to_log(aggregate_id, event.sequence, err);
+                    continue;
+                }
+            };
+            match self.verbosity {
+                EventLogVerbosity::Summary => tracing::info!(
+                    target: "events",
+                    aggregate_type = A::aggregate_type(),
+                    aggregate_id,
+                    sequence = event.sequence,
+                    event_type,
+                    metadata,
+                    "event committed"
+                ),
+                EventLogVerbosity::Full => match self.redacted_json(&event.payload) {
+                    Ok(payload) => tracing::info!(
+                        target: "events",
+                        aggregate_type = A::aggregate_type(),
+                        aggregate_id,
+                        sequence = event.sequence,
+                        event_type,
+                        metadata,
+                        payload,
+                        "event committed"
+                    ),
+                    Err(err) => warn_unable_to_log(aggregate_id, event.sequence, err),
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod event_logging_tests {
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    use crate::domain::aggregate::BankAccount;
+    use crate::event_logging::{EventLogVerbosity, EventLoggingQuery};
+
+    #[test]
+    fn test_redaction() {
+        let query = EventLoggingQuery::<BankAccount>::new(EventLogVerbosity::Full)
+            .with_redacted_fields(vec!["holders".to_string(), "user-agent".to_string()]);
+        let payload = json!({"AccountOpened": {"account_id": "ACCT-1", "holders": ["A Smith"]}});
+        assert_eq!(
+            r#"{"AccountOpened":{"account_id":"ACCT-1","holders":"[redacted]"}}"#,
+            query.redacted_json(&payload).unwrap()
+        );
+        let metadata = HashMap::from([("User-Agent".to_string(), "curl/7.81.0".to_string())]);
+        assert_eq!(
+            r#"{"User-Agent":"[redacted]"}"#,
+            query.redacted_json(&metadata).unwrap()
+        );
+    }
+}
@@ -0,0 +1,367 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use cqrs_es::{DomainEvent, EventEnvelope, Query};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{Pool, Postgres, Row};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::async_projections::{events_after, Checkpoint};
+use crate::domain::aggregate::BankAccount;
+use crate::queries::event_time;
+
+const SPEC_VERSION: &str = "1.0";
+const EVENT_SOURCE: &str = "/cqrs-demo/accounts";
+const EVENT_TYPE_PREFIX: &str = "cqrs_demo.account.";
+const CLOUD_EVENT_CONTENT_TYPE: &str = "application/cloudevents+json";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Only one runner publishes events at a time, any others wait for their turn.
+const TRY_LOCK_PUBLISHER: &str = "SELECT pg_try_advisory_xact_lock($1) AS locked";
+const PUBLISHER_LOCK_KEY: i64 = 0x7075_626c_6973;
+const PUBLISHER_CHECKPOINT: &str = "event_publisher";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const BATCH_SIZE: i64 = 100;
+
+// A committed account event in the CloudEvents 1.0 JSON format, the `eventversion` and `sequence`
+// extensions carry the version of the event payload and its sequence within the account.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CloudEvent {
+    specversion: &'static str,
+    id: String,
+    source: &'static str,
+    #[serde(rename = "type")]
+    event_type: String,
+    subject: String,
+    time: DateTime<Utc>,
+    datacontenttype: &'static str,
+    eventversion: String,
+    sequence: usize,
+    data: Value,
+}
+
+impl CloudEvent {
+    pub fn new(event: &EventEnvelope<BankAccount>) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            specversion: SPEC_VERSION,
+            id: format!("{}-{}", event.aggregate_id, event.sequence),
+            source: EVENT_SOURCE,
+            event_type: format!("{}{}", EVENT_TYPE_PREFIX, event.payload.event_type()),
+            subject: event.aggregate_id.clone(),
+            time: event_time(event),
+            datacontenttype: "application/json",
+            eventversion: event.payload.event_version(),
+            sequence: event.sequence,
+            data: serde_json::to_value(&event.payload)?,
+        })
+    }
+}
+
+// A destination for the account event stream, so that other services may consume it.
+#[async_trait]
+pub trait EventPublisher: Send + Sync {
+    async fn publish(&self, events: &[CloudEvent]) -> Result<(), String>;
+}
+
+// Publishes the committed account events from the event feed in a background task, so that a
+// slow or unavailable sink never delays a command.
+//
+// The place of the last event published is stored as the `event_publisher` checkpoint, which
+// is only advanced once a batch has been published. Delivery is at-least-once: a batch that
+// fails is published again with an exponential backoff, so consumers should ignore any event
+// whose `id` they have already received.
+pub struct EventPublisherRunner {
+    pool: Pool<Postgres>,
+    publisher: Arc<dyn EventPublisher>,
+}
+
+impl EventPublisherRunner {
+    pub fn new(pool: Pool<Postgres>, publisher: Arc<dyn EventPublisher>) -> Self {
+        Self { pool, publisher }
+    }
+
+    // Publishes events in a background task for the life of the application.
+    pub fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut backoff = POLL_INTERVAL;
+            loop {
+                match self.publish_next().await {
+                    // A full batch suggests that more events are waiting.
+                    Ok(true) => continue,
+                    Ok(false) => backoff = POLL_INTERVAL,
+                    Err(err) => {
+                        println!("Error: unable to publish events: {}\n", err);
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                    }
+                }
+                tokio::time::sleep(backoff).await;
+            }
+        })
+    }
+
+    // Publishes events until none are waiting, a batch that fails is published again later.
+    pub async fn drain(&self) {
+        loop {
+            match self.publish_next().await {
+                Ok(true) => continue,
+                Ok(false) => return,
+                Err(err) => {
+                    println!("Error: unable to publish events: {}\n", err);
+                    return;
+                }
+            }
+        }
+    }
+
+    // Publishes the next batch of events, returns true if a full batch was published.
+    async fn publish_next(&self) -> Result<bool, String> {
+        let mut tx = self.pool.begin().await.map_err(|err| err.to_string())?;
+        let locked: bool = sqlx::query(TRY_LOCK_PUBLISHER)
+            .bind(PUBLISHER_LOCK_KEY)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|err| err.to_string())?
+            .get("locked");
+        if !locked {
+            return Ok(false);
+        }
+        let checkpoint = Checkpoint::load(&mut tx, PUBLISHER_CHECKPOINT)
+            .await
+            .map_err(|err| err.to_string())?;
+        let events = events_after::<BankAccount>(&mut tx, &checkpoint, BATCH_SIZE)
+            .await
+            .map_err(|err| err.to_string())?;
+        let Some((last_checkpoint, _)) = events.last() else {
+            return Ok(false);
+        };
+        let cloud_events = events
+            .iter()
+            .map(|(_, event)| CloudEvent::new(event))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| err.to_string())?;
+        self.publisher.publish(&cloud_events).await?;
+        last_checkpoint
+            .save(&mut tx, PUBLISHER_CHECKPOINT)
+            .await
+            .map_err(|err| err.to_string())?;
+        tx.commit().await.map_err(|err| err.to_string())?;
+        Ok(events.len() as i64 == BATCH_SIZE)
+    }
+}
+
+// A query that publishes each committed account event when the events are held in memory and
+// there is no event feed. The events of each commit are queued as they are dispatched and
+// published in that order by a single background task, any that fail to publish are logged but
+// not retried.
+pub struct EventPublishingQuery {
+    sender: mpsc::UnboundedSender<(String, Vec<CloudEvent>)>,
+}
+
+impl EventPublishingQuery {
+    pub fn new(publisher: Arc<dyn EventPublisher>) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<(String, Vec<CloudEvent>)>();
+        tokio::spawn(async move {
+            while let Some((aggregate_id, cloud_events)) = receiver.recv().await {
+                if let Err(err) = publisher.publish(&cloud_events).await {
+                    println!(
+                        "Error: unable to publish events for {}: {}\n",
+                        aggregate_id, err
+                    );
+                }
+            }
+        });
+        Self { sender }
+    }
+}
+
+#[async_trait]
+impl Query<BankAccount> for EventPublishingQuery {
+    async fn dispatch(&self, aggregate_id: &str, events: &[EventEnvelope<BankAccount>]) {
+        let cloud_events = match events.iter().map(CloudEvent::new).collect() {
+            Ok(cloud_events) => cloud_events,
+            Err(err) => {
+                println!(
+                    "Error: unable to publish events for {}: {}\n",
+                    aggregate_id, err
+                );
+                return;
+            }
+        };
+        if self
+            .sender
+            .send((aggregate_id.to_string(), cloud_events))
+            .is_err()
+        {
+            println!("Error: the event publisher has stopped\n");
+        }
+    }
+}
+
+// Appends each event to a local file as a JSON line.
+pub struct FilePublisher {
+    file: Mutex<tokio::fs::File>,
+}
+
+impl FilePublisher {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self {
+            file: Mutex::new(tokio::fs::File::from_std(file)),
+        })
+    }
+}
+
+#[async_trait]
+impl EventPublisher for FilePublisher {
+    async fn publish(&self, events: &[CloudEvent]) -> Result<(), String> {
+        let mut lines = String::new();
+        for event in events {
+            lines.push_str(&serde_json::to_string(event).map_err(|err| err.to_string())?);
+            lines.push('\n');
+        }
+        // Events are written together so that concurrent commits are not interleaved.
+        let mut file = self.file.lock().await;
+        file.write_all(lines.as_bytes())
+            .await
+            .map_err(|err| err.to_string())?;
+        file.flush().await.map_err(|err| err.to_string())
+    }
+}
+
+// Posts each event to an HTTP endpoint in the CloudEvents structured content mode.
+pub struct HttpPublisher {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl HttpPublisher {
+    pub fn new(url: String) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .expect("unable to configure the event publisher client");
+        Self { client, url }
+    }
+}
+
+#[async_trait]
+impl EventPublisher for HttpPublisher {
+    async fn publish(&self, events: &[CloudEvent]) -> Result<(), String> {
+        for event in events {
+            let body = serde_json::to_string(event).map_err(|err| err.to_string())?;
+            let response = self
+                .client
+                .post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, CLOUD_EVENT_CONTENT_TYPE)
+                .body(body)
+                .send()
+                .await
+                .map_err(|err| err.to_string())?;
+            if !response.status().is_success() {
+                return Err(format!("unexpected response: {}", response.status()));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod event_publisher_tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use cqrs_es::{EventEnvelope, Query};
+    use serde_json::json;
+
+    use crate::domain::aggregate::BankAccount;
+    use crate::domain::events::BankAccountEvent;
+    use crate::event_publisher::{CloudEvent, EventPublisher, EventPublishingQuery};
+
+    // Records the sequence of each event published, the first publish is slow.
+    #[derive(Default)]
+    struct RecordingPublisher {
+        sequences: Mutex<Vec<usize>>,
+    }
+
+    #[async_trait]
+    impl EventPublisher for RecordingPublisher {
+        async fn publish(&self, events: &[CloudEvent]) -> Result<(), String> {
+            if self.sequences.lock().unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            let mut sequences = self.sequences.lock().unwrap();
+            sequences.extend(events.iter().map(|event| event.sequence));
+            Ok(())
+        }
+    }
+
+    fn deposit(sequence: usize) -> EventEnvelope<BankAccount> {
+        EventEnvelope {
+            aggregate_id: "ACCT-1".to_string(),
+            sequence,
+            payload: BankAccountEvent::CustomerDepositedMoney {
+                amount: 200.0,
+                balance: 200.0 * sequence as f64,
+            },
+            metadata: HashMap::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_events_published_in_order() {
+        let publisher = Arc::new(RecordingPublisher::default());
+        let query = EventPublishingQuery::new(publisher.clone());
+        for sequence in 1..=3 {
+            query.dispatch("ACCT-1", &[deposit(sequence)]).await;
+        }
+        for _ in 0..100 {
+            if publisher.sequences.lock().unwrap().len() == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(vec![1, 2, 3], *publisher.sequences.lock().unwrap());
+    }
+
+    #[test]
+    fn test_cloud_event() {
+        let event = EventEnvelope {
+            aggregate_id: "ACCT-1".to_string(),
+            sequence: 2,
+            payload: BankAccountEvent::CustomerDepositedMoney {
+                amount: 200.0,
+                balance: 200.0,
+            },
+            metadata: HashMap::from([("time".to_string(), "2022-03-01T12:00:00Z".to_string())]),
+        };
+        let cloud_event = serde_json::to_value(CloudEvent::new(&event).unwrap()).unwrap();
+        assert_eq!(
+            json!({
+                "specversion": "1.0",
+                "id": "ACCT-1-2",
+                "source": "/cqrs-demo/accounts",
+                "type": "cqrs_demo.account.CustomerDepositedMoney",
+                "subject": "ACCT-1",
+                "time": "2022-03-01T12:00:00Z",
+                "datacontenttype": "application/json",
+                "eventversion": "1.0",
+                "sequence": 2,
+                "data": {"CustomerDepositedMoney": {"amount": 200.0, "balance": 200.0}}
+            }),
+            cloud_event
+        );
+    }
+}
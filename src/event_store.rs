@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use cqrs_es::mem_store::{MemStore, MemStoreAggregateContext};
+use cqrs_es::persist::{EventStoreAggregateContext, PersistedEventStore};
+use cqrs_es::{
+    Aggregate, AggregateContext, AggregateError, CqrsFramework, EventEnvelope, EventStore, Query,
+};
+use sqlx::{Pool, Postgres};
+
+use crate::command_extractor::with_time;
+use crate::outbox::OutboxEventRepository;
+
+// A `CqrsFramework` backed by either of the event stores.
+pub type AppCqrs<A> = CqrsFramework<A, AppEventStore<A>>;
+
+pub fn app_cqrs<A>(
+    store: AppEventStore<A>,
+    query_processor: Vec<Box<dyn Query<A>>>,
+    services: A::Services,
+) -> AppCqrs<A>
+where
+    A: Aggregate,
+{
+    CqrsFramework::new(store, query_processor, services)
+}
+
+// The events are stored in Postgres, recording outbox messages in the same transaction, or for
+// local development in memory, in which case they are lost when the application stops.
+pub enum AppEventStore<A: Aggregate> {
+    Postgres(Box<PersistedEventStore<OutboxEventRepository, A>>),
+    Memory(MemStore<A>),
+}
+
+impl<A: Aggregate> AppEventStore<A> {
+    pub fn postgres(pool: Pool<Postgres>) -> Self {
+        let repo = OutboxEventRepository::new(pool);
+        Self::Postgres(Box::new(PersistedEventStore::new_event_store(repo)))
+    }
+
+    pub fn memory() -> Self {
+        Self::Memory(MemStore::default())
+    }
+}
+
+// The aggregate context of the event store that loaded it.
+pub enum AppAggregateContext<A: Aggregate> {
+    Postgres(EventStoreAggregateContext<A>),
+    Memory(MemStoreAggregateContext<A>),
+}
+
+impl<A: Aggregate> AggregateContext<A> for AppAggregateContext<A> {
+    fn aggregate(&self) -> &A {
+        match self {
+            AppAggregateContext::Postgres(context) => context.aggregate(),
+            AppAggregateContext::Memory(context) => context.aggregate(),
+        }
+    }
+}
+
+#[async_trait]
+impl<A: Aggregate> EventStore<A> for AppEventStore<A> {
+    type AC = AppAggregateContext<A>;
+
+    async fn load_events(
+        &self,
+        aggregate_id: &str,
+    ) -> Result<Vec<EventEnvelope<A>>, AggregateError<A::Error>> {
+        match self {
+            AppEventStore::Postgres(store) => store.load_events(aggregate_id).await,
+            AppEventStore::Memory(store) => store.load_events(aggregate_id).await,
+        }
+    }
+
+    async fn load_aggregate(
+        &self,
+        aggregate_id: &str,
+    ) -> Result<Self::AC, AggregateError<A::Error>> {
+        match self {
+            AppEventStore::Postgres(store) => store
+                .load_aggregate(aggregate_id)
+                .await
+                .map(AppAggregateContext::Postgres),
+            AppEventStore::Memory(store) => store
+                .load_aggregate(aggregate_id)
+                .await
+                .map(AppAggregateContext::Memory),
+        }
+    }
+
+    async fn commit(
+        &self,
+        events: Vec<A::Event>,
+        context: Self::AC,
+        metadata: HashMap<String, String>,
+    ) -> Result<Vec<EventEnvelope<A>>, AggregateError<A::Error>> {
+        let metadata = with_time(metadata);
+        match (self, context) {
+            (AppEventStore::Postgres(store), AppAggregateContext::Postgres(context)) => {
+                store.commit(events, context, metadata).await
+            }
+            (AppEventStore::Memory(store), AppAggregateContext::Memory(context)) => {
+                store.commit(events, context, metadata).await
+            }
+            _ => Err(AggregateError::UnexpectedError(
+                "aggregate context was loaded by another event store".into(),
+            )),
+        }
+    }
+}
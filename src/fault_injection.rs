@@ -0,0 +1,249 @@
+use std::path::Path;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::services::{AtmError, BankAccountApi, CheckingError};
+
+// A set of `BankAccountApi` services for chaos testing, this injects latency and errors
+// according to a list of rules before delegating to another implementation.
+//
+// Rules are loaded from a JSON file, e.g.,
+// ```json
+// {
+//   "rules": [
+//     { "operation": "atm_withdrawal", "atm_id": "ATM-N468290", "atm_error": "AtmOffline" },
+//     { "operation": "validate_check", "percentage": 25.0, "check_error": "StopPayment" },
+//     { "min_amount": 500.0, "latency_ms": 2000 }
+//   ]
+// }
+// ```
+//
+// Check faults fail the `WriteCheck` command. Cash is dispensed by the outbox dispatcher once a
+// withdrawal has been committed, so ATM faults never fail the `WithdrawMoney` command: with
+// Postgres they are recorded as the `last_error` of the outbox message and retried, then recorded
+// as a dead letter once the message is abandoned. With memory storage they are only logged.
+pub struct FaultInjectingBankAccountServices {
+    rules: Vec<FaultRule>,
+    services: Box<dyn BankAccountApi>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct FaultInjectionConfig {
+    pub rules: Vec<FaultRule>,
+}
+
+// Every condition that is provided must match for the rule to be applied.
+#[derive(Debug, Default, Deserialize)]
+pub struct FaultRule {
+    // Limits the rule to a single operation, if not set the rule applies to all operations.
+    #[serde(default)]
+    pub operation: Option<Operation>,
+    #[serde(default)]
+    pub atm_id: Option<String>,
+    // Applies the rule only to amounts at or above this threshold.
+    #[serde(default)]
+    pub min_amount: Option<f64>,
+    // The chance, from 0 to 100, that a matching call is affected. Defaults to always.
+    #[serde(default)]
+    pub percentage: Option<f64>,
+    #[serde(default)]
+    pub latency_ms: Option<u64>,
+    #[serde(default)]
+    pub atm_error: Option<AtmError>,
+    #[serde(default)]
+    pub check_error: Option<CheckingError>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    AtmWithdrawal,
+    ValidateCheck,
+}
+
+// The details of a single call that the rules are checked against.
+struct Call<'a> {
+    operation: Operation,
+    atm_id: Option<&'a str>,
+    amount: Option<f64>,
+}
+
+impl FaultRule {
+    fn matches(&self, call: &Call<'_>) -> bool {
+        if let Some(operation) = self.operation {
+            if operation != call.operation {
+                return false;
+            }
+        }
+        if let Some(atm_id) = &self.atm_id {
+            if call.atm_id != Some(atm_id.as_str()) {
+                return false;
+            }
+        }
+        if let Some(min_amount) = self.min_amount {
+            match call.amount {
+                Some(amount) if amount >= min_amount => {}
+                _ => return false,
+            }
+        }
+        match self.percentage {
+            None => true,
+            Some(percentage) => rand::random::<f64>() * 100.0 < percentage,
+        }
+    }
+}
+
+impl FaultInjectingBankAccountServices {
+    pub fn new(config: FaultInjectionConfig, services: Box<dyn BankAccountApi>) -> Self {
+        Self {
+            rules: config.rules,
+            services,
+        }
+    }
+
+    pub fn from_file(
+        path: impl AsRef<Path>,
+        services: Box<dyn BankAccountApi>,
+    ) -> Result<Self, String> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("unable to read {}: {}", path.display(), err))?;
+        let config: FaultInjectionConfig = serde_json::from_str(&contents)
+            .map_err(|err| format!("invalid fault injection rules: {}", err))?;
+        Ok(Self::new(config, services))
+    }
+
+    // Applies the latency of every matching rule, returning the first matching rule
+    // that should fail the call.
+    async fn inject(&self, call: &Call<'_>) -> Option<&FaultRule> {
+        for rule in self.rules.iter().filter(|rule| rule.matches(call)) {
+            if let Some(latency_ms) = rule.latency_ms {
+                tokio::time::sleep(Duration::from_millis(latency_ms)).await;
+            }
+            let fails = match call.operation {
+                Operation::AtmWithdrawal => rule.atm_error.is_some(),
+                Operation::ValidateCheck => rule.check_error.is_some(),
+            };
+            if fails {
+                return Some(rule);
+            }
+        }
+        None
+    }
+}
+
+#[async_trait]
+impl BankAccountApi for FaultInjectingBankAccountServices {
+    async fn atm_withdrawal(
+        &self,
+        atm_id: &str,
+        amount: f64,
+        idempotency_key: &str,
+    ) -> Result<(), AtmError> {
+        let call = Call {
+            operation: Operation::AtmWithdrawal,
+            atm_id: Some(atm_id),
+            amount: Some(amount),
+        };
+        if let Some(error) = self.inject(&call).await.and_then(|rule| rule.atm_error) {
+            return Err(error);
+        }
+        self.services
+            .atm_withdrawal(atm_id, amount, idempotency_key)
+            .await
+    }
+
+    async fn validate_check(
+        &self,
+        account_id: &str,
+        check: &str,
+        amount: f64,
+    ) -> Result<(), CheckingError> {
+        let call = Call {
+            operation: Operation::ValidateCheck,
+            atm_id: None,
+            amount: Some(amount),
+        };
+        if let Some(error) = self.inject(&call).await.and_then(|rule| rule.check_error) {
+            return Err(error);
+        }
+        self.services
+            .validate_check(account_id, check, amount)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod fault_injection_tests {
+    use crate::fault_injection::{FaultInjectingBankAccountServices, FaultInjectionConfig};
+    use crate::services::{AtmError, BankAccountApi, CheckingError, HappyPathBankAccountServices};
+
+    fn services(rules: &str) -> FaultInjectingBankAccountServices {
+        let config: FaultInjectionConfig = serde_json::from_str(rules).unwrap();
+        FaultInjectingBankAccountServices::new(config, Box::new(HappyPathBankAccountServices))
+    }
+
+    #[tokio::test]
+    async fn test_atm_id_rule() {
+        let services = services(
+            r#"{"rules": [{"operation": "atm_withdrawal", "atm_id": "ATM-1", "atm_error": "AtmOffline"}]}"#,
+        );
+        assert_eq!(
+            Err(AtmError::AtmOffline),
+            services.atm_withdrawal("ATM-1", 20.0, "outbox-1").await
+        );
+        assert_eq!(
+            Ok(()),
+            services.atm_withdrawal("ATM-2", 20.0, "outbox-1").await
+        );
+        assert_eq!(
+            Ok(()),
+            services.validate_check("ACCT-1", "1170", 20.0).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_amount_threshold_rule() {
+        let services =
+            services(r#"{"rules": [{"min_amount": 500.0, "atm_error": "LimitExceeded"}]}"#);
+        assert_eq!(
+            Ok(()),
+            services.atm_withdrawal("ATM-1", 499.99, "outbox-1").await
+        );
+        assert_eq!(
+            Err(AtmError::LimitExceeded),
+            services.atm_withdrawal("ATM-1", 500.0, "outbox-1").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_amount_rule() {
+        let services = services(
+            r#"{"rules": [{"operation": "validate_check", "min_amount": 1000.0, "check_error": "StopPayment"}]}"#,
+        );
+        assert_eq!(
+            Ok(()),
+            services.validate_check("ACCT-1", "1170", 999.99).await
+        );
+        assert_eq!(
+            Err(CheckingError::StopPayment),
+            services.validate_check("ACCT-1", "1170", 1000.0).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_percentage_rule() {
+        let services = services(
+            r#"{"rules": [
+                {"percentage": 0.0, "check_error": "StopPayment"},
+                {"percentage": 100.0, "check_error": "DuplicateCheck"}
+            ]}"#,
+        );
+        assert_eq!(
+            Err(CheckingError::DuplicateCheck),
+            services.validate_check("ACCT-1", "1170", 20.0).await
+        );
+    }
+}
@@ -0,0 +1,500 @@
+use async_graphql::{
+    Context, Enum, Error, ErrorExtensions, InputObject, Json, Object, Result, Schema, SimpleObject,
+    Subscription, ID,
+};
+use axum::http::HeaderMap;
+use chrono::{DateTime, NaiveDate, Utc};
+use cqrs_es::AggregateError;
+use futures::{Stream, StreamExt};
+
+use crate::account_stream::AccountUpdate;
+use crate::command_extractor::command_metadata;
+use crate::config::StorageMode;
+use crate::consistency::{committed_version, load_min_version, VersionedView};
+use crate::domain::aggregate::ACCOUNT_ALREADY_OPEN;
+use crate::domain::commands::{BankAccountCommand, CommandEnvelope};
+use crate::domain::events::{BankAccountError, BankAccountEvent};
+use crate::ledger::{LedgerFilter, LedgerPage, LedgerRow};
+use crate::queries::{BankAccountView, LedgerEntry};
+use crate::state::ApplicationState;
+
+const GRAPHQL_URI: &str = "/graphql";
+
+// A GraphQL API over the same framework and projections as the REST routes. The
+// `ApplicationState` and the request headers are attached to each request as data.
+pub type BankSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
+
+pub fn graphql_schema() -> BankSchema {
+    Schema::build(QueryRoot, MutationRoot, SubscriptionRoot).finish()
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    // The current view of an account, or null if there is no such account.
+    async fn account(&self, ctx: &Context<'_>, account_id: ID) -> Result<Option<AccountView>> {
+        let state = ctx.data::<ApplicationState>()?;
+        let view = state.account_query.load(&account_id).await?;
+        Ok(view.map(AccountView::from))
+    }
+
+    // A page of the ledger of an account, see `LedgerFilter`, or null if there is no such account.
+    async fn ledger(
+        &self,
+        ctx: &Context<'_>,
+        account_id: ID,
+        #[graphql(default)] filter: LedgerFilterInput,
+    ) -> Result<Option<LedgerPageObject>> {
+        let state = ctx.data::<ApplicationState>()?;
+        if state.storage_mode == StorageMode::Memory {
+            return Err("the ledger requires Postgres storage".into());
+        }
+        let page = state.ledger.load(&account_id, &filter.into()).await?;
+        Ok(page.map(LedgerPageObject::from))
+    }
+}
+
+// Each mutation executes one `BankAccountCommand` and returns the updated view of the account.
+// With `PROJECTIONS=async` this waits briefly for the view to include the command's events, but
+// the view is returned regardless, its `version` shows whether it does.
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    async fn open_account(
+        &self,
+        ctx: &Context<'_>,
+        account_id: ID,
+        #[graphql(default)] account_type: AccountTypeEnum,
+        #[graphql(default)] holders: Vec<String>,
+    ) -> Result<Option<AccountView>> {
+        let command = BankAccountCommand::OpenAccount {
+            account_id: account_id.to_string(),
+            account_type: account_type.into(),
+            holders,
+        };
+        execute(ctx, &account_id, command).await
+    }
+
+    async fn close_account(
+        &self,
+        ctx: &Context<'_>,
+        account_id: ID,
+    ) -> Result<Option<AccountView>> {
+        execute(ctx, &account_id, BankAccountCommand::CloseAccount).await
+    }
+
+    async fn deposit_money(
+        &self,
+        ctx: &Context<'_>,
+        account_id: ID,
+        amount: f64,
+    ) -> Result<Option<AccountView>> {
+        execute(
+            ctx,
+            &account_id,
+            BankAccountCommand::DepositMoney { amount },
+        )
+        .await
+    }
+
+    async fn withdraw_money(
+        &self,
+        ctx: &Context<'_>,
+        account_id: ID,
+        amount: f64,
+        atm_id: String,
+    ) -> Result<Option<AccountView>> {
+        let command = BankAccountCommand::WithdrawMoney { amount, atm_id };
+        execute(ctx, &account_id, command).await
+    }
+
+    async fn write_check(
+        &self,
+        ctx: &Context<'_>,
+        account_id: ID,
+        check_number: String,
+        amount: f64,
+    ) -> Result<Option<AccountView>> {
+        let command = BankAccountCommand::WriteCheck {
+            check_number,
+            amount,
+        };
+        execute(ctx, &account_id, command).await
+    }
+}
+
+async fn execute(
+    ctx: &Context<'_>,
+    account_id: &str,
+    command: BankAccountCommand,
+) -> Result<Option<AccountView>> {
+    let state = ctx.data::<ApplicationState>()?;
+    let metadata = command_metadata(GRAPHQL_URI, ctx.data::<HeaderMap>()?);
+    let command = CommandEnvelope::new(command, metadata.clone());
+    let execute = state
+        .cqrs
+        .execute_with_metadata(account_id, command, metadata);
+    let version = match committed_version(execute).await {
+        Ok(version) => version,
+        Err(err) => {
+            println!("Error: {:#?}\n", err);
+            return Err(command_error(&err));
+        }
+    };
+    match load_min_version(state.account_query.as_ref(), account_id, version).await? {
+        VersionedView::Current(view) | VersionedView::Stale(view) => {
+            Ok(view.map(AccountView::from))
+        }
+    }
+}
+
+// Opening an account that exists fails with the code `ALREADY_EXISTS`, as `command_handler`
+// responds with a conflict, any other rejection is `BAD_USER_INPUT`.
+fn command_error(err: &AggregateError<BankAccountError>) -> Error {
+    let code = match err {
+        AggregateError::UserError(user_error) if user_error.to_string() == ACCOUNT_ALREADY_OPEN => {
+            "ALREADY_EXISTS"
+        }
+        _ => "BAD_USER_INPUT",
+    };
+    Error::new(err.to_string()).extend_with(|_, extensions| extensions.set("code", code))
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    // The events of an account committed after `after`, or only new events if this is absent.
+    async fn account_updates(
+        &self,
+        ctx: &Context<'_>,
+        account_id: ID,
+        after: Option<usize>,
+    ) -> Result<impl Stream<Item = AccountUpdateObject>> {
+        let state = ctx.data::<ApplicationState>()?;
+        let updates = state
+            .account_stream
+            .subscribe(state.account_events.clone(), account_id.to_string(), after)
+            .await?;
+        Ok(updates.map(AccountUpdateObject::from))
+    }
+}
+
+// The GraphQL types of the views and of the domain types used as arguments, these are converted
+// here so that `async_graphql` stays out of the domain, as `grpc` does with its proto messages.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Enum)]
+#[graphql(name = "AccountType", remote = "crate::domain::aggregate::AccountType")]
+enum AccountTypeEnum {
+    #[default]
+    Checking,
+    Savings,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Enum)]
+#[graphql(name = "LedgerEntryType", remote = "crate::ledger::LedgerEntryType")]
+enum LedgerEntryTypeEnum {
+    Deposit,
+    AtmWithdrawal,
+    Check,
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "BankAccountView")]
+struct AccountView {
+    version: usize,
+    account_id: Option<String>,
+    balance: f64,
+    written_checks: Vec<String>,
+    ledger: Vec<LedgerEntryObject>,
+}
+
+impl From<BankAccountView> for AccountView {
+    fn from(view: BankAccountView) -> Self {
+        Self {
+            version: view.version,
+            account_id: view.account_id,
+            balance: view.balance,
+            written_checks: view.written_checks,
+            ledger: view
+                .ledger
+                .into_iter()
+                .map(LedgerEntryObject::from)
+                .collect(),
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "LedgerEntry")]
+struct LedgerEntryObject {
+    sequence: usize,
+    recorded_at: DateTime<Utc>,
+    entry_type: LedgerEntryTypeEnum,
+    description: String,
+    amount: f64,
+    balance: f64,
+}
+
+impl From<LedgerEntry> for LedgerEntryObject {
+    fn from(entry: LedgerEntry) -> Self {
+        Self {
+            sequence: entry.sequence,
+            recorded_at: entry.recorded_at,
+            entry_type: entry.entry_type.into(),
+            description: entry.description,
+            amount: entry.amount,
+            balance: entry.balance,
+        }
+    }
+}
+
+#[derive(Default, InputObject)]
+#[graphql(name = "LedgerFilter")]
+struct LedgerFilterInput {
+    entry_type: Option<LedgerEntryTypeEnum>,
+    min_amount: Option<f64>,
+    max_amount: Option<f64>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    after: Option<i64>,
+    limit: Option<i64>,
+}
+
+impl From<LedgerFilterInput> for LedgerFilter {
+    fn from(filter: LedgerFilterInput) -> Self {
+        Self {
+            entry_type: filter.entry_type.map(Into::into),
+            min_amount: filter.min_amount,
+            max_amount: filter.max_amount,
+            from: filter.from,
+            to: filter.to,
+            after: filter.after,
+            limit: filter.limit,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "LedgerPage")]
+struct LedgerPageObject {
+    entries: Vec<LedgerRowObject>,
+    next_cursor: Option<i64>,
+}
+
+impl From<LedgerPage> for LedgerPageObject {
+    fn from(page: LedgerPage) -> Self {
+        Self {
+            entries: page
+                .entries
+                .into_iter()
+                .map(LedgerRowObject::from)
+                .collect(),
+            next_cursor: page.next_cursor,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "LedgerRow")]
+struct LedgerRowObject {
+    sequence: i64,
+    entry_type: LedgerEntryTypeEnum,
+    description: String,
+    amount: f64,
+    balance: f64,
+    atm_id: Option<String>,
+    recorded_at: DateTime<Utc>,
+}
+
+impl From<LedgerRow> for LedgerRowObject {
+    fn from(row: LedgerRow) -> Self {
+        Self {
+            sequence: row.sequence,
+            entry_type: row.entry_type.into(),
+            description: row.description,
+            amount: row.amount,
+            balance: row.balance,
+            atm_id: row.atm_id,
+            recorded_at: row.recorded_at,
+        }
+    }
+}
+
+// An event pushed to a subscriber along with the view of the account after it was applied,
+// the event is given as JSON.
+#[derive(SimpleObject)]
+#[graphql(name = "AccountUpdate")]
+struct AccountUpdateObject {
+    sequence: usize,
+    event: Json<BankAccountEvent>,
+    view: AccountView,
+}
+
+impl From<AccountUpdate> for AccountUpdateObject {
+    fn from(update: AccountUpdate) -> Self {
+        Self {
+            sequence: update.sequence,
+            event: Json(update.event),
+            view: AccountView::from(update.view),
+        }
+    }
+}
+
+#[cfg(test)]
+mod graphql_tests {
+    use async_graphql::{value, Request, Response, Value};
+    use axum::http::HeaderMap;
+    use futures::StreamExt;
+
+    use crate::config::StorageMode;
+    use crate::graphql::{graphql_schema, BankSchema};
+    use crate::state::{application_state_with_storage, ApplicationState};
+
+    async fn execute(schema: &BankSchema, state: &ApplicationState, query: &str) -> Response {
+        let request = Request::new(query)
+            .data(state.clone())
+            .data(HeaderMap::new());
+        schema.execute(request).await
+    }
+
+    #[test]
+    fn test_command_mutations() {
+        let sdl = graphql_schema().sdl();
+        for mutation in [
+            "openAccount(accountId: ID!, accountType: AccountType! = CHECKING, holders: [String!]! = []): BankAccountView",
+            "closeAccount(accountId: ID!): BankAccountView",
+            "depositMoney(accountId: ID!, amount: Float!): BankAccountView",
+            "withdrawMoney(accountId: ID!, amount: Float!, atmId: String!): BankAccountView",
+            "writeCheck(accountId: ID!, checkNumber: String!, amount: Float!): BankAccountView",
+        ] {
+            assert!(sdl.contains(mutation), "missing mutation {}", mutation);
+        }
+    }
+
+    #[test]
+    fn test_queries_and_subscriptions() {
+        let sdl = graphql_schema().sdl();
+        for field in [
+            "account(accountId: ID!): BankAccountView",
+            "ledger(accountId: ID!, filter: LedgerFilter! = {entryType: null, minAmount: null, maxAmount: null, from: null, to: null, after: null, limit: null}): LedgerPage",
+            "accountUpdates(accountId: ID!, after: Int): AccountUpdate!",
+            "entryType: LedgerEntryType!",
+        ] {
+            assert!(sdl.contains(field), "missing field {}", field);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute_mutations_and_queries() {
+        let schema = graphql_schema();
+        let state = application_state_with_storage(StorageMode::Memory).await;
+
+        let response = execute(
+            &schema,
+            &state,
+            r#"mutation { openAccount(accountId: "ACCT-1", holders: ["Ada"]) { version accountId balance } }"#,
+        )
+        .await;
+        assert_eq!(Vec::<async_graphql::ServerError>::new(), response.errors);
+        assert_eq!(
+            value!({"openAccount": {"version": 1, "accountId": "ACCT-1", "balance": 0.0}}),
+            response.data
+        );
+
+        let response = execute(
+            &schema,
+            &state,
+            r#"mutation { depositMoney(accountId: "ACCT-1", amount: 200.0) { version balance ledger { entryType amount balance } } }"#,
+        )
+        .await;
+        assert_eq!(
+            value!({"depositMoney": {"version": 2, "balance": 200.0, "ledger": [
+                {"entryType": "DEPOSIT", "amount": 200.0, "balance": 200.0}
+            ]}}),
+            response.data
+        );
+
+        let response = execute(
+            &schema,
+            &state,
+            r#"{ account(accountId: "ACCT-1") { version balance } missing: account(accountId: "ACCT-2") { version } }"#,
+        )
+        .await;
+        assert_eq!(
+            value!({"account": {"version": 2, "balance": 200.0}, "missing": null}),
+            response.data
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_errors() {
+        let schema = graphql_schema();
+        let state = application_state_with_storage(StorageMode::Memory).await;
+        let open_account = r#"mutation { openAccount(accountId: "ACCT-1") { version } }"#;
+        assert!(execute(&schema, &state, open_account)
+            .await
+            .errors
+            .is_empty());
+
+        let response = execute(&schema, &state, open_account).await;
+        assert_eq!(Value::Null, response.data);
+        let error = &response.errors[0];
+        assert_eq!("account already open", error.message);
+        let code = error.extensions.as_ref().and_then(|ext| ext.get("code"));
+        assert_eq!(Some(&Value::from("ALREADY_EXISTS")), code);
+
+        let response = execute(
+            &schema,
+            &state,
+            r#"mutation { depositMoney(accountId: "ACCT-2", amount: 10.0) { version } }"#,
+        )
+        .await;
+        let error = &response.errors[0];
+        let code = error.extensions.as_ref().and_then(|ext| ext.get("code"));
+        assert_eq!(Some(&Value::from("BAD_USER_INPUT")), code);
+
+        let response = execute(
+            &schema,
+            &state,
+            r#"{ ledger(accountId: "ACCT-1") { nextCursor } }"#,
+        )
+        .await;
+        assert_eq!(
+            "the ledger requires Postgres storage",
+            response.errors[0].message
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_subscription() {
+        let schema = graphql_schema();
+        let state = application_state_with_storage(StorageMode::Memory).await;
+        let open_account = r#"mutation { openAccount(accountId: "ACCT-1") { version } }"#;
+        assert!(execute(&schema, &state, open_account)
+            .await
+            .errors
+            .is_empty());
+
+        let request = Request::new(
+            r#"subscription { accountUpdates(accountId: "ACCT-1", after: 0) { sequence view { version balance } } }"#,
+        )
+        .data(state.clone())
+        .data(HeaderMap::new());
+        let mut updates = schema.execute_stream(request);
+        let response = updates.next().await.unwrap();
+        assert_eq!(
+            value!({"accountUpdates": {"sequence": 1, "view": {"version": 1, "balance": 0.0}}}),
+            response.data
+        );
+
+        let deposit = r#"mutation { depositMoney(accountId: "ACCT-1", amount: 25.0) { version } }"#;
+        assert!(execute(&schema, &state, deposit).await.errors.is_empty());
+        let response = updates.next().await.unwrap();
+        assert_eq!(
+            value!({"accountUpdates": {"sequence": 2, "view": {"version": 2, "balance": 25.0}}}),
+            response.data
+        );
+    }
+}
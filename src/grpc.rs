@@ -0,0 +1,346 @@
+use std::pin::Pin;
+
+use chrono::{DateTime, Utc};
+use cqrs_es::AggregateError;
+use futures::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+use crate::account_stream::AccountUpdate;
+use crate::command_extractor::command_metadata;
+use crate::consistency::{committed_version, load_min_version, VersionedView};
+use crate::domain::aggregate::{AccountType, ACCOUNT_ALREADY_OPEN};
+use crate::domain::commands::{BankAccountCommand, CommandEnvelope};
+use crate::domain::events::{BankAccountError, BankAccountEvent};
+use crate::ledger::LedgerEntryType;
+use crate::queries::{BankAccountView, LedgerEntry};
+use crate::state::ApplicationState;
+
+use proto::bank_account_command::Command;
+use proto::bank_account_event::Event;
+use proto::bank_account_service_server::{BankAccountService, BankAccountServiceServer};
+
+#[allow(clippy::all)]
+pub mod proto {
+    tonic::include_proto!("bank_account");
+}
+
+const EXECUTE_URI: &str = "/bank_account.BankAccountService/Execute";
+
+// Serves account commands and queries over gRPC, see `proto/bank_account.proto`. Errors are
+// mapped as by the REST handlers, a rejected command fails with `INVALID_ARGUMENT`.
+pub struct BankAccountGrpc {
+    state: ApplicationState,
+}
+
+impl BankAccountGrpc {
+    pub fn server(state: ApplicationState) -> BankAccountServiceServer<Self> {
+        BankAccountServiceServer::new(Self { state })
+    }
+}
+
+type AccountUpdateStream = Pin<Box<dyn Stream<Item = Result<proto::AccountUpdate, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl BankAccountService for BankAccountGrpc {
+    async fn execute(
+        &self,
+        request: Request<proto::ExecuteRequest>,
+    ) -> Result<Response<proto::ExecuteResponse>, Status> {
+        let metadata = command_metadata(EXECUTE_URI, &request.metadata().clone().into_headers());
+        let request = request.into_inner();
+        let command = match request
+            .command
+            .and_then(|command| command.command)
+            .and_then(bank_account_command)
+        {
+            Some(command) => command,
+            None => return Err(Status::invalid_argument("command could not be read")),
+        };
+        let command = CommandEnvelope::new(command, metadata.clone());
+        let execute = self
+            .state
+            .cqrs
+            .execute_with_metadata(&request.account_id, command, metadata);
+        match committed_version(execute).await {
+            Ok(version) => Ok(Response::new(proto::ExecuteResponse {
+                version: version.map(|version| version as u64),
+            })),
+            Err(err) => {
+                println!("Error: {:#?}\n", err);
+                Err(command_status(&err))
+            }
+        }
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<proto::GetAccountRequest>,
+    ) -> Result<Response<proto::BankAccountView>, Status> {
+        let request = request.into_inner();
+        let min_version = request.min_version.map(|min_version| min_version as usize);
+        let account_id = request.account_id;
+        match load_min_version(self.state.account_query.as_ref(), &account_id, min_version).await {
+            Ok(VersionedView::Current(Some(view))) => {
+                Ok(Response::new(proto::BankAccountView::from(&view)))
+            }
+            Ok(VersionedView::Current(None)) => Err(Status::not_found(account_id)),
+            Ok(VersionedView::Stale(view)) => Err(Status::unavailable(format!(
+                "view is stale, version {} of {} requested",
+                view.map_or(0, |view| view.version),
+                min_version.unwrap_or_default()
+            ))),
+            Err(err) => {
+                println!("Error: {:#?}\n", err);
+                Err(Status::internal(err.to_string()))
+            }
+        }
+    }
+
+    type StreamEventsStream = AccountUpdateStream;
+
+    async fn stream_events(
+        &self,
+        request: Request<proto::StreamEventsRequest>,
+    ) -> Result<Response<Self::StreamEventsStream>, Status> {
+        let request = request.into_inner();
+        let after = request.after.map(|after| after as usize);
+        let updates = match self
+            .state
+            .account_stream
+            .subscribe(self.state.account_events.clone(), request.account_id, after)
+            .await
+        {
+            Ok(updates) => updates,
+            Err(err) => {
+                println!("Error: {:#?}\n", err);
+                return Err(Status::internal(err.to_string()));
+            }
+        };
+        let updates = updates
+            .map(|update| proto::AccountUpdate::from(&update))
+            .map(Ok);
+        Ok(Response::new(Box::pin(updates)))
+    }
+}
+
+// Opening an account that exists fails with `ALREADY_EXISTS`, as `command_handler` responds
+// with a conflict, any other rejection is an invalid argument.
+fn command_status(err: &AggregateError<BankAccountError>) -> Status {
+    match err {
+        AggregateError::UserError(user_error) if user_error.to_string() == ACCOUNT_ALREADY_OPEN => {
+            Status::already_exists(err.to_string())
+        }
+        _ => Status::invalid_argument(err.to_string()),
+    }
+}
+
+// The command to execute, or `None` if the account type is unknown.
+fn bank_account_command(command: Command) -> Option<BankAccountCommand> {
+    Some(match command {
+        Command::OpenAccount(open) => {
+            let account_type = match proto::AccountType::try_from(open.account_type) {
+                Ok(proto::AccountType::Checking) => AccountType::Checking,
+                Ok(proto::AccountType::Savings) => AccountType::Savings,
+                Err(_) => return None,
+            };
+            BankAccountCommand::OpenAccount {
+                account_id: open.account_id,
+                account_type,
+                holders: open.holders,
+            }
+        }
+        Command::CloseAccount(_) => BankAccountCommand::CloseAccount,
+        Command::DepositMoney(deposit) => BankAccountCommand::DepositMoney {
+            amount: deposit.amount,
+        },
+        Command::WithdrawMoney(withdraw) => BankAccountCommand::WithdrawMoney {
+            amount: withdraw.amount,
+            atm_id: withdraw.atm_id,
+        },
+        Command::WriteCheck(check) => BankAccountCommand::WriteCheck {
+            check_number: check.check_number,
+            amount: check.amount,
+        },
+    })
+}
+
+fn account_type(account_type: AccountType) -> proto::AccountType {
+    match account_type {
+        AccountType::Checking => proto::AccountType::Checking,
+        AccountType::Savings => proto::AccountType::Savings,
+    }
+}
+
+fn timestamp(time: DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: time.timestamp(),
+        nanos: time.timestamp_subsec_nanos() as i32,
+    }
+}
+
+impl From<&BankAccountEvent> for proto::BankAccountEvent {
+    fn from(event: &BankAccountEvent) -> Self {
+        let event = match event.clone() {
+            BankAccountEvent::AccountOpened {
+                account_id,
+                account_type: opened_type,
+                holders,
+            } => Event::AccountOpened(proto::AccountOpened {
+                account_id,
+                account_type: account_type(opened_type).into(),
+                holders,
+            }),
+            BankAccountEvent::AccountClosed => Event::AccountClosed(proto::AccountClosed {}),
+            BankAccountEvent::CustomerDepositedMoney { amount, balance } => {
+                Event::CustomerDepositedMoney(proto::CustomerDepositedMoney { amount, balance })
+            }
+            BankAccountEvent::CustomerWithdrewCash {
+                amount,
+                atm_id,
+                balance,
+                ..
+            } => Event::CustomerWithdrewCash(proto::CustomerWithdrewCash {
+                amount,
+                atm_id,
+                balance,
+            }),
+            BankAccountEvent::CustomerWroteCheck {
+                check_number,
+                amount,
+                balance,
+            } => Event::CustomerWroteCheck(proto::CustomerWroteCheck {
+                check_number,
+                amount,
+                balance,
+            }),
+            BankAccountEvent::TransactionFlagged {
+                transaction,
+                amount,
+                reason,
+            } => Event::TransactionFlagged(proto::TransactionFlagged {
+                transaction,
+                amount,
+                reason,
+            }),
+        };
+        Self { event: Some(event) }
+    }
+}
+
+impl From<&LedgerEntry> for proto::LedgerEntry {
+    fn from(entry: &LedgerEntry) -> Self {
+        let entry_type = match entry.entry_type {
+            LedgerEntryType::Deposit => proto::LedgerEntryType::Deposit,
+            LedgerEntryType::AtmWithdrawal => proto::LedgerEntryType::AtmWithdrawal,
+            LedgerEntryType::Check => proto::LedgerEntryType::Check,
+        };
+        Self {
+            sequence: entry.sequence as u64,
+            recorded_at: Some(timestamp(entry.recorded_at)),
+            entry_type: entry_type.into(),
+            description: entry.description.clone(),
+            amount: entry.amount,
+            balance: entry.balance,
+        }
+    }
+}
+
+impl From<&BankAccountView> for proto::BankAccountView {
+    fn from(view: &BankAccountView) -> Self {
+        Self {
+            account_id: view.account_id.clone(),
+            balance: view.balance,
+            written_checks: view.written_checks.clone(),
+            ledger: view.ledger.iter().map(proto::LedgerEntry::from).collect(),
+            version: view.version as u64,
+        }
+    }
+}
+
+impl From<&AccountUpdate> for proto::AccountUpdate {
+    fn from(update: &AccountUpdate) -> Self {
+        Self {
+            sequence: update.sequence as u64,
+            event: Some(proto::BankAccountEvent::from(&update.event)),
+            view: Some(proto::BankAccountView::from(&update.view)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod grpc_tests {
+    use tonic::{Code, Request};
+
+    use crate::config::StorageMode;
+    use crate::domain::aggregate::AccountType;
+    use crate::domain::commands::BankAccountCommand;
+    use crate::domain::events::BankAccountEvent;
+    use crate::grpc::proto::bank_account_command::Command;
+    use crate::grpc::proto::bank_account_event::Event;
+    use crate::grpc::proto::bank_account_service_server::BankAccountService;
+    use crate::grpc::{bank_account_command, proto, BankAccountGrpc};
+    use crate::state::application_state_with_storage;
+
+    #[test]
+    fn test_proto_conversion() {
+        let command = bank_account_command(Command::OpenAccount(proto::OpenAccount {
+            account_id: "ACCT-1".to_string(),
+            account_type: proto::AccountType::Savings.into(),
+            holders: vec!["Ann".to_string()],
+        }))
+        .unwrap();
+        match command {
+            BankAccountCommand::OpenAccount { account_type, .. } => {
+                assert_eq!(AccountType::Savings, account_type)
+            }
+            _ => panic!("unexpected command: {:?}", command),
+        }
+        let event = BankAccountEvent::CustomerWithdrewCash {
+            amount: 40.0,
+            atm_id: "ATM-1".to_string(),
+            balance: 160.0,
+            withdrawal_id: "withdrawal-1".to_string(),
+        };
+        assert_eq!(
+            Some(Event::CustomerWithdrewCash(proto::CustomerWithdrewCash {
+                amount: 40.0,
+                atm_id: "ATM-1".to_string(),
+                balance: 160.0,
+            })),
+            proto::BankAccountEvent::from(&event).event
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_errors() {
+        let service = BankAccountGrpc {
+            state: application_state_with_storage(StorageMode::Memory).await,
+        };
+        let open_account = |account_id: &str| proto::ExecuteRequest {
+            account_id: account_id.to_string(),
+            command: Some(proto::BankAccountCommand {
+                command: Some(Command::OpenAccount(proto::OpenAccount {
+                    account_id: account_id.to_string(),
+                    account_type: proto::AccountType::Checking.into(),
+                    holders: vec![],
+                })),
+            }),
+        };
+        let response = service.execute(Request::new(open_account("ACCT-1"))).await;
+        assert_eq!(Some(1), response.unwrap().into_inner().version);
+        let status = service
+            .execute(Request::new(open_account("ACCT-1")))
+            .await
+            .unwrap_err();
+        assert_eq!(Code::AlreadyExists, status.code());
+
+        let deposit = proto::ExecuteRequest {
+            account_id: "ACCT-2".to_string(),
+            command: Some(proto::BankAccountCommand {
+                command: Some(Command::DepositMoney(proto::DepositMoney { amount: 10.0 })),
+            }),
+        };
+        let status = service.execute(Request::new(deposit)).await.unwrap_err();
+        assert_eq!(Code::InvalidArgument, status.code());
+    }
+}
@@ -1,29 +1,59 @@
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use axum::extract::{Path, Query, State};
+use axum::http::{Request, StatusCode};
+use axum::middleware::{from_fn_with_state, Next};
 use axum::response::Response;
 use axum::routing::get;
 use axum::Router;
 use cqrs_demo::command_extractor::CommandExtractor;
-use cqrs_demo::route_handler::{command_handler, query_handler};
-use cqrs_demo::state::{new_application_state, ApplicationState};
-use lambda_http::{run, Error};
+use cqrs_demo::event_logging::init_tracing;
+use cqrs_demo::route_handler::{
+    atm_command_handler, atm_query_handler, command_handler, ledger_handler, query_handler,
+    ViewParams,
+};
+use cqrs_demo::state::{new_lambda_application_state, ApplicationState};
+use lambda_http::{run, Body, Error};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let state = new_application_state().await;
-    let routes = Router::new().route(
-        "/account/:account_id",
-        get(lambda_query_handler).post(lambda_command_handler),
-    );
-    let app = Router::new().merge(routes).with_state(state);
+    init_tracing();
+    let state = new_lambda_application_state().await;
+    let routes = Router::new()
+        .route(
+            "/account/:account_id",
+            get(lambda_query_handler).post(lambda_command_handler),
+        )
+        .route("/account/:account_id/ledger", get(ledger_handler))
+        .route(
+            "/atm/:atm_id",
+            get(atm_query_handler).post(atm_command_handler),
+        );
+    let app = Router::new()
+        .merge(routes)
+        .layer(from_fn_with_state(state.clone(), drain_background_work))
+        .with_state(state);
     run(app).await?;
     Ok(())
 }
+// Lambda suspends the application once a response is returned, so the outbox and the other
+// background work is drained before responding.
+async fn drain_background_work(
+    State(state): State<ApplicationState>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let response = next.run(request).await;
+    if let Some(background_work) = &state.background_work {
+        background_work.drain().await;
+    }
+    response
+}
+
 pub async fn lambda_query_handler(
     Path(account_id): Path<String>,
+    Query(params): Query<ViewParams>,
     State(state): State<ApplicationState>,
 ) -> Result<Response, (StatusCode, String)> {
-    Ok(query_handler(Path(account_id), State(state)).await)
+    Ok(query_handler(Path(account_id), Query(params), State(state)).await)
 }
 async fn lambda_command_handler(
     Path(account_id): Path<String>,
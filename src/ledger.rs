@@ -0,0 +1,229 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Row};
+use utoipa::{IntoParams, ToSchema};
+
+// Every filter is optional, a null parameter matches all entries.
+const SELECT_LEDGER_ENTRIES: &str = "
+SELECT sequence, entry_type, check_number, amount, balance, atm_id, recorded_at
+  FROM ledger_entries
+  WHERE account_id = $1
+    AND sequence > $2
+    AND ($3::text IS NULL OR entry_type = $3)
+    AND ($4::double precision IS NULL OR amount >= $4)
+    AND ($5::double precision IS NULL OR amount <= $5)
+    AND ($6::timestamptz IS NULL OR recorded_at >= $6)
+    AND ($7::timestamptz IS NULL OR recorded_at < $7)
+  ORDER BY sequence
+  LIMIT $8";
+
+// The event store is checked rather than a projection, which may lag behind the events when
+// projections are asynchronous.
+const SELECT_ACCOUNT: &str =
+    "SELECT 1 FROM events WHERE aggregate_type = 'account' AND aggregate_id = $1 LIMIT 1";
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+// Reads the ledger rows projected into `ledger_entries` by `LedgerEntriesQuery`, this allows
+// a ledger to be paged and filtered without loading the entire `BankAccountView`.
+#[derive(Clone)]
+pub struct LedgerQuery {
+    pool: Pool<Postgres>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerEntryType {
+    Deposit,
+    AtmWithdrawal,
+    Check,
+}
+
+impl LedgerEntryType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LedgerEntryType::Deposit => "deposit",
+            LedgerEntryType::AtmWithdrawal => "atm_withdrawal",
+            LedgerEntryType::Check => "check",
+        }
+    }
+
+    pub(crate) fn parse(entry_type: &str) -> Option<Self> {
+        match entry_type {
+            "deposit" => Some(LedgerEntryType::Deposit),
+            "atm_withdrawal" => Some(LedgerEntryType::AtmWithdrawal),
+            "check" => Some(LedgerEntryType::Check),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, ToSchema)]
+pub struct LedgerRow {
+    pub(crate) sequence: i64,
+    pub(crate) entry_type: LedgerEntryType,
+    pub(crate) description: String,
+    pub(crate) amount: f64,
+    // The account balance after the entry was applied.
+    pub(crate) balance: f64,
+    // The ATM that dispensed the cash of an ATM withdrawal.
+    pub(crate) atm_id: Option<String>,
+    pub(crate) recorded_at: DateTime<Utc>,
+}
+
+// The filters and cursor of a ledger request, these are taken from the query string, e.g.,
+// `?entry_type=deposit&min_amount=100&from=2022-03-01&to=2022-03-31&after=12&limit=20`.
+// Dates are inclusive and in UTC.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LedgerFilter {
+    /// Matches only entries of this type.
+    pub entry_type: Option<LedgerEntryType>,
+    /// Matches only entries of at least this amount.
+    pub min_amount: Option<f64>,
+    /// Matches only entries of at most this amount.
+    pub max_amount: Option<f64>,
+    /// Matches only entries recorded on or after this day.
+    pub from: Option<NaiveDate>,
+    /// Matches only entries recorded on or before this day.
+    pub to: Option<NaiveDate>,
+    /// The cursor, only entries with a sequence after this will be returned.
+    pub after: Option<i64>,
+    /// The number of entries in a page, 50 by default and at most 500.
+    pub limit: Option<i64>,
+}
+
+impl LedgerFilter {
+    fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    fn recorded_from(&self) -> Option<DateTime<Utc>> {
+        self.from.map(start_of_day)
+    }
+
+    // The exclusive upper bound, the start of the day following `to`.
+    fn recorded_before(&self) -> Option<DateTime<Utc>> {
+        self.to.map(|to| start_of_day(to) + Duration::days(1))
+    }
+}
+
+fn start_of_day(date: NaiveDate) -> DateTime<Utc> {
+    date.and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LedgerPage {
+    pub(crate) entries: Vec<LedgerRow>,
+    // Pass as `after` to fetch the following page, this is absent on the last page.
+    pub(crate) next_cursor: Option<i64>,
+}
+
+impl LedgerQuery {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        Self { pool }
+    }
+
+    // Returns `None` if there is no such account.
+    pub async fn load(
+        &self,
+        account_id: &str,
+        filter: &LedgerFilter,
+    ) -> Result<Option<LedgerPage>, sqlx::Error> {
+        let account = sqlx::query(SELECT_ACCOUNT)
+            .bind(account_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        if account.is_none() {
+            return Ok(None);
+        }
+        let limit = filter.limit();
+        // One additional row is requested to find whether another page follows.
+        let rows = sqlx::query(SELECT_LEDGER_ENTRIES)
+            .bind(account_id)
+            .bind(filter.after.unwrap_or(-1))
+            .bind(filter.entry_type.map(|entry_type| entry_type.as_str()))
+            .bind(filter.min_amount)
+            .bind(filter.max_amount)
+            .bind(filter.recorded_from())
+            .bind(filter.recorded_before())
+            .bind(limit + 1)
+            .fetch_all(&self.pool)
+            .await?;
+        let mut entries = Vec::with_capacity(rows.len());
+        for row in rows {
+            let entry_type: String = row.get("entry_type");
+            let entry_type = LedgerEntryType::parse(&entry_type).ok_or_else(|| {
+                sqlx::Error::Decode(format!("unknown ledger entry type: {}", entry_type).into())
+            })?;
+            let check_number: Option<String> = row.get("check_number");
+            entries.push(LedgerRow {
+                sequence: row.get("sequence"),
+                entry_type,
+                description: description(entry_type, check_number),
+                amount: row.get("amount"),
+                balance: row.get("balance"),
+                atm_id: row.get("atm_id"),
+                recorded_at: row.get("recorded_at"),
+            });
+        }
+        let next_cursor = if entries.len() as i64 > limit {
+            entries.truncate(limit as usize);
+            entries.last().map(|entry| entry.sequence)
+        } else {
+            None
+        };
+        Ok(Some(LedgerPage {
+            entries,
+            next_cursor,
+        }))
+    }
+}
+
+// Checks are described by their check number, other entries by their type.
+fn description(entry_type: LedgerEntryType, check_number: Option<String>) -> String {
+    match (entry_type, check_number) {
+        (LedgerEntryType::Check, Some(check_number)) => check_number,
+        (LedgerEntryType::AtmWithdrawal, _) => "atm withdrawal".to_string(),
+        (entry_type, _) => entry_type.as_str().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod ledger_tests {
+    use chrono::{DateTime, NaiveDate, Utc};
+
+    use crate::ledger::{description, LedgerEntryType, LedgerFilter};
+
+    #[test]
+    fn test_description() {
+        assert_eq!("deposit", description(LedgerEntryType::Deposit, None));
+        assert_eq!(
+            "atm withdrawal",
+            description(LedgerEntryType::AtmWithdrawal, None)
+        );
+        assert_eq!(
+            "1170",
+            description(LedgerEntryType::Check, Some("1170".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_filter_bounds() {
+        let filter = LedgerFilter {
+            from: NaiveDate::from_ymd_opt(2022, 3, 1),
+            to: NaiveDate::from_ymd_opt(2022, 3, 31),
+            limit: Some(10_000),
+            ..LedgerFilter::default()
+        };
+        let expected_from: DateTime<Utc> = "2022-03-01T00:00:00Z".parse().unwrap();
+        let expected_before: DateTime<Utc> = "2022-04-01T00:00:00Z".parse().unwrap();
+        assert_eq!(Some(expected_from), filter.recorded_from());
+        assert_eq!(Some(expected_before), filter.recorded_before());
+        assert_eq!(500, filter.limit());
+        assert_eq!(50, LedgerFilter::default().limit());
+    }
+}
@@ -0,0 +1,137 @@
+use async_trait::async_trait;
+use cqrs_es::persist::PersistenceError;
+use cqrs_es::EventEnvelope;
+use sqlx::{Pool, Postgres};
+
+use crate::dead_letter::FallibleQuery;
+use crate::domain::aggregate::BankAccount;
+use crate::domain::events::BankAccountEvent;
+use crate::ledger::LedgerEntryType;
+use crate::queries::{event_time, sql_error};
+
+// Replaying an event overwrites its row with the same values.
+const UPSERT_LEDGER_ENTRY: &str = "
+INSERT INTO ledger_entries (account_id, sequence, entry_type, amount, balance, check_number, atm_id, recorded_at)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+ON CONFLICT (account_id, sequence) DO UPDATE
+  SET entry_type = $3, amount = $4, balance = $5, check_number = $6, atm_id = $7, recorded_at = $8";
+
+// A projection holding each ledger event as a typed row in `ledger_entries`, so that ledgers
+// may be paged and filtered by `LedgerQuery` and analyzed with SQL rather than by reading
+// the JSON of `account_query`.
+#[derive(Clone)]
+pub struct LedgerEntriesQuery {
+    pool: Pool<Postgres>,
+}
+
+impl LedgerEntriesQuery {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        Self { pool }
+    }
+}
+
+// The columns of a ledger event, those not relevant to its type are null.
+#[derive(Debug, PartialEq)]
+struct LedgerRow<'a> {
+    entry_type: LedgerEntryType,
+    amount: f64,
+    balance: f64,
+    check_number: Option<&'a str>,
+    atm_id: Option<&'a str>,
+}
+
+fn ledger_row(event: &BankAccountEvent) -> Option<LedgerRow<'_>> {
+    match event {
+        BankAccountEvent::CustomerDepositedMoney { amount, balance } => Some(LedgerRow {
+            entry_type: LedgerEntryType::Deposit,
+            amount: *amount,
+            balance: *balance,
+            check_number: None,
+            atm_id: None,
+        }),
+        BankAccountEvent::CustomerWithdrewCash {
+            amount,
+            atm_id,
+            balance,
+            ..
+        } => Some(LedgerRow {
+            entry_type: LedgerEntryType::AtmWithdrawal,
+            amount: *amount,
+            balance: *balance,
+            check_number: None,
+            atm_id: Some(atm_id),
+        }),
+        BankAccountEvent::CustomerWroteCheck {
+            check_number,
+            amount,
+            balance,
+        } => Some(LedgerRow {
+            entry_type: LedgerEntryType::Check,
+            amount: *amount,
+            balance: *balance,
+            check_number: Some(check_number),
+            atm_id: None,
+        }),
+        _ => None,
+    }
+}
+
+#[async_trait]
+impl FallibleQuery<BankAccount> for LedgerEntriesQuery {
+    async fn apply(
+        &self,
+        aggregate_id: &str,
+        events: &[EventEnvelope<BankAccount>],
+    ) -> Result<(), PersistenceError> {
+        for event in events {
+            if let Some(row) = ledger_row(&event.payload) {
+                sqlx::query(UPSERT_LEDGER_ENTRY)
+                    .bind(aggregate_id)
+                    .bind(event.sequence as i64)
+                    .bind(row.entry_type.as_str())
+                    .bind(row.amount)
+                    .bind(row.balance)
+                    .bind(row.check_number)
+                    .bind(row.atm_id)
+                    .bind(event_time(event))
+                    .execute(&self.pool)
+                    .await
+                    .map_err(sql_error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod ledger_entries_tests {
+    use crate::domain::events::BankAccountEvent;
+    use crate::ledger::LedgerEntryType;
+    use crate::ledger_entries::{ledger_row, LedgerRow};
+
+    #[test]
+    fn test_ledger_rows() {
+        let withdrawal = BankAccountEvent::CustomerWithdrewCash {
+            amount: 40.0,
+            atm_id: "ATM-1".to_string(),
+            balance: 60.0,
+            withdrawal_id: String::new(),
+        };
+        let expected = LedgerRow {
+            entry_type: LedgerEntryType::AtmWithdrawal,
+            amount: 40.0,
+            balance: 60.0,
+            check_number: None,
+            atm_id: Some("ATM-1"),
+        };
+        assert_eq!(Some(expected), ledger_row(&withdrawal));
+
+        let check = BankAccountEvent::CustomerWroteCheck {
+            check_number: "1170".to_string(),
+            amount: 25.0,
+            balance: 35.0,
+        };
+        assert_eq!(Some("1170"), ledger_row(&check).unwrap().check_number);
+        assert_eq!(None, ledger_row(&BankAccountEvent::AccountClosed));
+    }
+}
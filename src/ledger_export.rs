@@ -0,0 +1,447 @@
+use std::fmt::Write;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Row};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::ledger::LedgerEntryType;
+
+const SELECT_ACCOUNT_TYPE: &str = "SELECT account_type FROM account_summary WHERE account_id = $1";
+
+// Both dates are optional, a null parameter matches all entries.
+const SELECT_EXPORT_ENTRIES: &str = "
+SELECT sequence, entry_type, amount, balance, check_number, atm_id, recorded_at
+  FROM ledger_entries
+  WHERE account_id = $1
+    AND ($2::timestamptz IS NULL OR recorded_at >= $2)
+    AND ($3::timestamptz IS NULL OR recorded_at < $3)
+  ORDER BY sequence";
+
+// The balance after the last entry before the end of the range, which is optional.
+const SELECT_CLOSING_BALANCE: &str = "
+SELECT balance
+  FROM ledger_entries
+  WHERE account_id = $1
+    AND ($2::timestamptz IS NULL OR recorded_at < $2)
+  ORDER BY sequence DESC
+  LIMIT 1";
+
+const CSV_HEADER: &str = "date,sequence,type,description,amount,balance,check_number,atm_id";
+const OFX_DATE_FORMAT: &str = "%Y%m%d%H%M%S";
+const OFX_DAY_FORMAT: &str = "%Y%m%d";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+    Ofx,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Ofx => "application/x-ofx",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ofx => "ofx",
+        }
+    }
+}
+
+// The format and range of a ledger export, these are taken from the query string, e.g.,
+// `?format=ofx&from=2022-03-01&to=2022-03-31`. Dates are inclusive and in UTC.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportParams {
+    /// The format of the file, JSON by default.
+    #[serde(default)]
+    pub format: ExportFormat,
+    /// The first day of the export.
+    pub from: Option<NaiveDate>,
+    /// The last day of the export.
+    pub to: Option<NaiveDate>,
+}
+
+// The ledger of an account as exported, the amount of a withdrawal or check is negative.
+#[derive(Debug, Serialize)]
+pub struct LedgerExport {
+    account_id: String,
+    account_type: String,
+    entries: Vec<ExportEntry>,
+    // The requested range, this bounds an OFX statement even if it holds no entries.
+    #[serde(skip)]
+    from: Option<NaiveDate>,
+    #[serde(skip)]
+    to: Option<NaiveDate>,
+    // The balance at the end of the range, this is the balance of an OFX statement.
+    #[serde(skip)]
+    closing_balance: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportEntry {
+    sequence: i64,
+    entry_type: String,
+    description: String,
+    amount: f64,
+    balance: f64,
+    check_number: Option<String>,
+    atm_id: Option<String>,
+    recorded_at: DateTime<Utc>,
+}
+
+fn start_of_day(date: NaiveDate) -> DateTime<Utc> {
+    date.and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+// Returns `None` if there is no such account.
+pub async fn load_ledger_export(
+    pool: &Pool<Postgres>,
+    account_id: &str,
+    params: &ExportParams,
+) -> Result<Option<LedgerExport>, sqlx::Error> {
+    let account_type: String = match sqlx::query(SELECT_ACCOUNT_TYPE)
+        .bind(account_id)
+        .fetch_optional(pool)
+        .await?
+    {
+        Some(row) => row.get("account_type"),
+        None => return Ok(None),
+    };
+    let end = params.to.map(|to| start_of_day(to) + Duration::days(1));
+    let rows = sqlx::query(SELECT_EXPORT_ENTRIES)
+        .bind(account_id)
+        .bind(params.from.map(start_of_day))
+        .bind(end)
+        .fetch_all(pool)
+        .await?;
+    let closing_balance = sqlx::query(SELECT_CLOSING_BALANCE)
+        .bind(account_id)
+        .bind(end)
+        .fetch_optional(pool)
+        .await?
+        .map_or(0.0, |row| row.get("balance"));
+    let mut entries = Vec::with_capacity(rows.len());
+    for row in rows {
+        let entry_type: String = row.get("entry_type");
+        let entry_type = LedgerEntryType::parse(&entry_type).ok_or_else(|| {
+            sqlx::Error::Decode(format!("unknown ledger entry type: {}", entry_type).into())
+        })?;
+        entries.push(export_entry(
+            row.get("sequence"),
+            entry_type,
+            row.get("amount"),
+            row.get("balance"),
+            row.get("check_number"),
+            row.get("atm_id"),
+            row.get("recorded_at"),
+        ));
+    }
+    Ok(Some(LedgerExport {
+        account_id: account_id.to_string(),
+        account_type,
+        entries,
+        from: params.from,
+        to: params.to,
+        closing_balance,
+    }))
+}
+
+// The exported form of a ledger row, the amount of a withdrawal or check is negated.
+fn export_entry(
+    sequence: i64,
+    entry_type: LedgerEntryType,
+    amount: f64,
+    balance: f64,
+    check_number: Option<String>,
+    atm_id: Option<String>,
+    recorded_at: DateTime<Utc>,
+) -> ExportEntry {
+    let (description, amount) = match entry_type {
+        LedgerEntryType::Deposit => ("Deposit".to_string(), amount),
+        LedgerEntryType::AtmWithdrawal => (
+            format!("ATM withdrawal {}", atm_id.as_deref().unwrap_or_default()),
+            -amount,
+        ),
+        LedgerEntryType::Check => (
+            format!("Check {}", check_number.as_deref().unwrap_or_default()),
+            -amount,
+        ),
+    };
+    ExportEntry {
+        sequence,
+        entry_type: entry_type.as_str().to_string(),
+        description: description.trim_end().to_string(),
+        amount,
+        balance,
+        check_number,
+        atm_id,
+        recorded_at,
+    }
+}
+
+impl LedgerExport {
+    pub fn render(&self, format: ExportFormat) -> String {
+        match format {
+            ExportFormat::Json => serde_json::to_string(self).unwrap_or_default(),
+            ExportFormat::Csv => self.to_csv(),
+            ExportFormat::Ofx => self.to_ofx(Utc::now()),
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        writeln!(csv, "{}", CSV_HEADER).unwrap();
+        for entry in &self.entries {
+            writeln!(
+                csv,
+                "{},{},{},{},{},{},{},{}",
+                entry.recorded_at.to_rfc3339(),
+                entry.sequence,
+                entry.entry_type,
+                csv_field(&entry.description),
+                entry.amount,
+                entry.balance,
+                csv_field(entry.check_number.as_deref().unwrap_or_default()),
+                csv_field(entry.atm_id.as_deref().unwrap_or_default())
+            )
+            .unwrap();
+        }
+        csv
+    }
+
+    // An OFX 2.2 bank statement, each entry is a transaction identified by its sequence.
+    fn to_ofx(&self, now: DateTime<Utc>) -> String {
+        let now = now.format(OFX_DATE_FORMAT);
+        let account_type = match self.account_type.as_str() {
+            "Savings" => "SAVINGS",
+            _ => "CHECKING",
+        };
+        let mut ofx = String::new();
+        ofx.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n");
+        ofx.push_str("<?OFX OFXHEADER=\"200\" VERSION=\"220\" SECURITY=\"NONE\" OLDFILEUID=\"NONE\" NEWFILEUID=\"NONE\"?>\n");
+        ofx.push_str("<OFX>\n");
+        ofx.push_str(
+            "<SIGNONMSGSRSV1><SONRS><STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>",
+        );
+        writeln!(
+            ofx,
+            "<DTSERVER>{}</DTSERVER><LANGUAGE>ENG</LANGUAGE></SONRS></SIGNONMSGSRSV1>",
+            now
+        )
+        .unwrap();
+        ofx.push_str("<BANKMSGSRSV1><STMTTRNRS><TRNUID>0</TRNUID><STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>\n");
+        ofx.push_str("<STMTRS><CURDEF>USD</CURDEF>\n");
+        writeln!(
+            ofx,
+            "<BANKACCTFROM><BANKID>CQRSDEMO</BANKID><ACCTID>{}</ACCTID><ACCTTYPE>{}</ACCTTYPE></BANKACCTFROM>",
+            xml_escape(&self.account_id),
+            account_type
+        )
+        .unwrap();
+        // The statement covers the requested range, or without one its first and last entries.
+        let start = match (self.from, self.entries.first()) {
+            (Some(from), _) => from.format(OFX_DAY_FORMAT).to_string(),
+            (None, Some(first)) => first.recorded_at.format(OFX_DATE_FORMAT).to_string(),
+            (None, None) => now.to_string(),
+        };
+        let end = match (self.to, self.entries.last()) {
+            (Some(to), _) => to.format(OFX_DAY_FORMAT).to_string(),
+            (None, Some(last)) => last.recorded_at.format(OFX_DATE_FORMAT).to_string(),
+            (None, None) => now.to_string(),
+        };
+        writeln!(
+            ofx,
+            "<BANKTRANLIST><DTSTART>{}</DTSTART><DTEND>{}</DTEND>",
+            start, end
+        )
+        .unwrap();
+        for entry in &self.entries {
+            ofx.push_str("<STMTTRN>");
+            write!(
+                ofx,
+                "<TRNTYPE>{}</TRNTYPE><DTPOSTED>{}</DTPOSTED><TRNAMT>{:.2}</TRNAMT><FITID>{}</FITID>",
+                ofx_transaction_type(&entry.entry_type),
+                entry.recorded_at.format(OFX_DATE_FORMAT),
+                entry.amount,
+                entry.sequence
+            )
+            .unwrap();
+            if let Some(check_number) = &entry.check_number {
+                write!(ofx, "<CHECKNUM>{}</CHECKNUM>", xml_escape(check_number)).unwrap();
+            }
+            writeln!(
+                ofx,
+                "<NAME>{}</NAME></STMTTRN>",
+                xml_escape(&entry.description)
+            )
+            .unwrap();
+        }
+        ofx.push_str("</BANKTRANLIST>\n");
+        writeln!(
+            ofx,
+            "<LEDGERBAL><BALAMT>{:.2}</BALAMT><DTASOF>{}</DTASOF></LEDGERBAL>",
+            self.closing_balance, end
+        )
+        .unwrap();
+        ofx.push_str("</STMTRS></STMTTRNRS></BANKMSGSRSV1>\n");
+        ofx.push_str("</OFX>\n");
+        ofx
+    }
+}
+
+fn ofx_transaction_type(entry_type: &str) -> &'static str {
+    match LedgerEntryType::parse(entry_type) {
+        Some(LedgerEntryType::Deposit) => "DEP",
+        Some(LedgerEntryType::AtmWithdrawal) => "ATM",
+        Some(LedgerEntryType::Check) => "CHECK",
+        None => "OTHER",
+    }
+}
+
+// Quotes a field containing a comma, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod ledger_export_tests {
+    use chrono::{DateTime, NaiveDate, Utc};
+
+    use crate::ledger::LedgerEntryType;
+    use crate::ledger_export::{csv_field, export_entry, ExportEntry, LedgerExport};
+
+    fn entry(sequence: i64, entry_type: &str, amount: f64, balance: f64) -> ExportEntry {
+        ExportEntry {
+            sequence,
+            entry_type: entry_type.to_string(),
+            description: "description".to_string(),
+            amount,
+            balance,
+            check_number: (entry_type == "check").then(|| "1170".to_string()),
+            atm_id: None,
+            recorded_at: "2022-03-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_ofx_transactions() {
+        let export = LedgerExport {
+            account_id: "ACCT-1".to_string(),
+            account_type: "Savings".to_string(),
+            entries: vec![
+                entry(2, "deposit", 200.0, 200.0),
+                entry(3, "atm_withdrawal", -40.0, 160.0),
+                entry(4, "check", -25.5, 134.5),
+            ],
+            from: None,
+            to: None,
+            closing_balance: 134.5,
+        };
+        let ofx = export.to_ofx(Utc::now());
+        assert!(ofx.contains("<ACCTID>ACCT-1</ACCTID><ACCTTYPE>SAVINGS</ACCTTYPE>"));
+        assert!(ofx.contains("<TRNTYPE>DEP</TRNTYPE><DTPOSTED>20220301120000</DTPOSTED><TRNAMT>200.00</TRNAMT><FITID>2</FITID>"));
+        assert!(ofx.contains("<TRNTYPE>ATM</TRNTYPE><DTPOSTED>20220301120000</DTPOSTED><TRNAMT>-40.00</TRNAMT><FITID>3</FITID>"));
+        assert!(ofx.contains("<TRNTYPE>CHECK</TRNTYPE><DTPOSTED>20220301120000</DTPOSTED><TRNAMT>-25.50</TRNAMT><FITID>4</FITID><CHECKNUM>1170</CHECKNUM>"));
+        assert!(ofx.contains("<BALAMT>134.50</BALAMT>"));
+    }
+
+    #[test]
+    fn test_export_entries() {
+        let recorded_at = "2022-03-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let deposit = export_entry(
+            2,
+            LedgerEntryType::Deposit,
+            200.0,
+            200.0,
+            None,
+            None,
+            recorded_at,
+        );
+        assert_eq!(
+            ("deposit", "Deposit", 200.0),
+            (
+                deposit.entry_type.as_str(),
+                deposit.description.as_str(),
+                deposit.amount
+            )
+        );
+        let withdrawal = export_entry(
+            3,
+            LedgerEntryType::AtmWithdrawal,
+            40.0,
+            160.0,
+            None,
+            Some("ATM-1".to_string()),
+            recorded_at,
+        );
+        assert_eq!(
+            ("atm_withdrawal", "ATM withdrawal ATM-1", -40.0),
+            (
+                withdrawal.entry_type.as_str(),
+                withdrawal.description.as_str(),
+                withdrawal.amount
+            )
+        );
+        let check = export_entry(
+            4,
+            LedgerEntryType::Check,
+            25.5,
+            134.5,
+            Some("1170".to_string()),
+            None,
+            recorded_at,
+        );
+        assert_eq!(
+            ("check", "Check 1170", -25.5),
+            (
+                check.entry_type.as_str(),
+                check.description.as_str(),
+                check.amount
+            )
+        );
+    }
+
+    #[test]
+    fn test_empty_ofx_statement() {
+        let export = LedgerExport {
+            account_id: "ACCT-1".to_string(),
+            account_type: "Checking".to_string(),
+            entries: vec![],
+            from: NaiveDate::from_ymd_opt(2022, 3, 1),
+            to: NaiveDate::from_ymd_opt(2022, 3, 31),
+            // The balance left by entries before the range.
+            closing_balance: 160.0,
+        };
+        let ofx = export.to_ofx(Utc::now());
+        assert!(ofx.contains("<DTSTART>20220301</DTSTART><DTEND>20220331</DTEND>"));
+        assert!(ofx.contains("<BALAMT>160.00</BALAMT><DTASOF>20220331</DTASOF>"));
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!("ATM-1", csv_field("ATM-1"));
+        assert_eq!("\"Main St, \"\"A\"\"\"", csv_field("Main St, \"A\""));
+    }
+}
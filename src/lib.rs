@@ -1,10 +1,40 @@
 #![forbid(unsafe_code)]
 #![deny(clippy::all)]
 
+mod account_resources;
+mod account_stream;
+mod account_summary;
+pub mod admin_extractor;
+mod async_projections;
+mod atm_registry;
 pub mod command_extractor;
 mod config;
+mod consistency;
+pub mod dead_letter;
 mod domain;
+mod event_log;
+pub mod event_logging;
+pub mod event_publisher;
+mod event_store;
+mod fault_injection;
+pub mod graphql;
+pub mod grpc;
+mod ledger;
+mod ledger_entries;
+mod ledger_export;
+mod mem_view_repository;
+mod metrics;
+mod openapi;
+mod outbox;
+mod point_in_time;
 mod queries;
+pub mod rebuild;
+mod reports;
+mod review_queue;
 pub mod route_handler;
+pub mod routes;
+mod screening;
 mod services;
 pub mod state;
+pub mod storage_extractor;
+pub mod webhooks;
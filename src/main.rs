@@ -1,20 +1,39 @@
-use axum::routing::get;
-use axum::Router;
-use cqrs_demo::route_handler::{command_handler, query_handler};
-use cqrs_demo::state::new_application_state;
+use cqrs_demo::event_logging::init_tracing;
+use cqrs_demo::grpc::BankAccountGrpc;
+use cqrs_demo::rebuild::ProjectionRebuilder;
+use cqrs_demo::routes::router;
+use cqrs_demo::state::{database_pool, new_application_state, new_dead_letter_retry};
 
 #[tokio::main]
 async fn main() {
+    init_tracing();
+    // `cargo run -- rebuild` regenerates the account projections from the event store.
+    if std::env::args().nth(1).as_deref() == Some("rebuild") {
+        let progress = ProjectionRebuilder::new(database_pool().await)
+            .run()
+            .await
+            .expect("projection rebuild failed");
+        println!("account projections rebuilt: {:?}", progress);
+        return;
+    }
+    // `cargo run -- retry-dead-letters` reprocesses the events that a projection failed to apply.
+    if std::env::args().nth(1).as_deref() == Some("retry-dead-letters") {
+        let report = new_dead_letter_retry()
+            .await
+            .retry()
+            .await
+            .expect("dead letter retry failed");
+        println!("dead letters retried: {:?}", report);
+        return;
+    }
     let state = new_application_state().await;
-    // Configure the Axum routes and services.
-    // For this example a single logical endpoint is used and the HTTP method
-    // distinguishes whether the call is a command or a query.
-    let router = Router::new()
-        .route(
-            "/account/:account_id",
-            get(query_handler).post(command_handler),
-        )
-        .with_state(state);
+    // Internal services may instead call the gRPC service, which is served on port 50051.
+    let grpc = tonic::transport::Server::builder()
+        .add_service(BankAccountGrpc::server(state.clone()))
+        .serve("0.0.0.0:50051".parse().unwrap());
+    tokio::spawn(async { grpc.await.expect("gRPC server failed") });
+    // Configure the Axum routes and services, see `routes`.
+    let router = router().with_state(state);
     // Start the Axum server.
     axum::Server::bind(&"0.0.0.0:3030".parse().unwrap())
         .serve(router.into_make_service())
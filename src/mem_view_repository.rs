@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::RwLock;
+
+use async_trait::async_trait;
+use cqrs_es::persist::{PersistenceError, ViewContext, ViewRepository};
+use cqrs_es::{Aggregate, View};
+use serde_json::Value;
+
+// A view repository held in memory for local development, the views are lost when the
+// application stops. As in Postgres each view is stored serialized along with its version,
+// an update is rejected if the view was updated since it was loaded.
+pub struct MemViewRepository<V, A> {
+    views: RwLock<HashMap<String, (Value, i64)>>,
+    phantom: PhantomData<(V, A)>,
+}
+
+impl<V, A> Default for MemViewRepository<V, A> {
+    fn default() -> Self {
+        Self {
+            views: RwLock::default(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<V, A> MemViewRepository<V, A>
+where
+    V: View<A>,
+    A: Aggregate,
+{
+    // Every view, in order of view id.
+    pub fn load_all(&self) -> Result<Vec<V>, PersistenceError> {
+        let views = self.views.read().unwrap();
+        let mut view_ids: Vec<&String> = views.keys().collect();
+        view_ids.sort();
+        let mut loaded = Vec::with_capacity(view_ids.len());
+        for view_id in view_ids {
+            loaded.push(serde_json::from_value(views[view_id].0.clone())?);
+        }
+        Ok(loaded)
+    }
+}
+
+#[async_trait]
+impl<V, A> ViewRepository<V, A> for MemViewRepository<V, A>
+where
+    V: View<A>,
+    A: Aggregate,
+{
+    async fn load(&self, view_id: &str) -> Result<Option<V>, PersistenceError> {
+        Ok(self.load_with_context(view_id).await?.map(|(view, _)| view))
+    }
+
+    async fn load_with_context(
+        &self,
+        view_id: &str,
+    ) -> Result<Option<(V, ViewContext)>, PersistenceError> {
+        let views = self.views.read().unwrap();
+        match views.get(view_id) {
+            Some((payload, version)) => {
+                let view = serde_json::from_value(payload.clone())?;
+                Ok(Some((
+                    view,
+                    ViewContext::new(view_id.to_string(), *version),
+                )))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn update_view(&self, view: V, context: ViewContext) -> Result<(), PersistenceError> {
+        let payload = serde_json::to_value(&view)?;
+        let mut views = self.views.write().unwrap();
+        let version = views
+            .get(&context.view_instance_id)
+            .map_or(0, |view| view.1);
+        if version != context.version {
+            return Err(PersistenceError::OptimisticLockError);
+        }
+        views.insert(context.view_instance_id, (payload, version + 1));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod mem_view_repository_tests {
+    use cqrs_es::persist::{PersistenceError, ViewContext, ViewRepository};
+
+    use crate::domain::aggregate::BankAccount;
+    use crate::mem_view_repository::MemViewRepository;
+    use crate::queries::BankAccountView;
+
+    #[tokio::test]
+    async fn test_update_view() {
+        let repo = MemViewRepository::<BankAccountView, BankAccount>::default();
+        assert!(repo.load("ACCT-1").await.unwrap().is_none());
+        let view = BankAccountView {
+            balance: 100.0,
+            ..BankAccountView::default()
+        };
+        let context = ViewContext::new("ACCT-1".to_string(), 0);
+        repo.update_view(view, context).await.unwrap();
+        let (view, context) = repo.load_with_context("ACCT-1").await.unwrap().unwrap();
+        assert_eq!(100.0, view.balance);
+        assert_eq!(1, context.version);
+
+        // A view that was loaded before the last update may not replace it.
+        let stale_context = ViewContext::new("ACCT-1".to_string(), 0);
+        let result = repo.update_view(view, stale_context).await;
+        assert!(matches!(result, Err(PersistenceError::OptimisticLockError)));
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+// Counters and gauges of the application, labelled by projection, these are rendered in the
+// Prometheus text format by the `/metrics` endpoint.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    counters: Mutex<BTreeMap<&'static str, BTreeMap<String, u64>>>,
+    gauges: Mutex<BTreeMap<&'static str, BTreeMap<String, u64>>>,
+}
+
+impl Metrics {
+    pub fn increment(&self, name: &'static str, projection: &str) {
+        self.add(name, projection, 1);
+    }
+
+    pub fn add(&self, name: &'static str, projection: &str, value: u64) {
+        let mut counters = self.counters.lock().unwrap();
+        *counters
+            .entry(name)
+            .or_default()
+            .entry(projection.to_string())
+            .or_default() += value;
+    }
+
+    pub fn set(&self, name: &'static str, projection: &str, value: u64) {
+        let mut gauges = self.gauges.lock().unwrap();
+        gauges
+            .entry(name)
+            .or_default()
+            .insert(projection.to_string(), value);
+    }
+
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        render_metrics(&mut text, "counter", &self.counters.lock().unwrap());
+        render_metrics(&mut text, "gauge", &self.gauges.lock().unwrap());
+        text
+    }
+}
+
+fn render_metrics(
+    text: &mut String,
+    metric_type: &str,
+    metrics: &BTreeMap<&'static str, BTreeMap<String, u64>>,
+) {
+    for (name, values) in metrics {
+        writeln!(text, "# TYPE {} {}", name, metric_type).unwrap();
+        for (projection, value) in values {
+            writeln!(text, "{}{{projection=\"{}\"}} {}", name, projection, value).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use crate::metrics::Metrics;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.add("projection_failures_total", "account_query", 0);
+        metrics.increment("projection_failures_total", "ledger_entries");
+        metrics.increment("projection_failures_total", "ledger_entries");
+        metrics.set("projection_lag_events", "ledger_entries", 5);
+        metrics.set("projection_lag_events", "ledger_entries", 3);
+        assert_eq!(
+            "# TYPE projection_failures_total counter\n\
+             projection_failures_total{projection=\"account_query\"} 0\n\
+             projection_failures_total{projection=\"ledger_entries\"} 2\n\
+             # TYPE projection_lag_events gauge\n\
+             projection_lag_events{projection=\"ledger_entries\"} 3\n",
+            metrics.render()
+        );
+    }
+}
@@ -0,0 +1,489 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::{Config, SwaggerFile};
+
+use crate::account_resources::{NewAccount, NewCheck, NewDeposit, NewWithdrawal};
+use crate::account_summary::{AccountPage, AccountSort, AccountSummary, SortOrder};
+use crate::admin_extractor::ADMIN_API_KEY_HDR;
+use crate::dead_letter::{DeadLetter, RetryReport};
+use crate::domain::aggregate::{AccountStatus, AccountType};
+use crate::domain::atm::aggregate::AtmStatus;
+use crate::domain::atm::commands::AtmCommand;
+use crate::domain::commands::BankAccountCommand;
+use crate::event_log::StoredEvent;
+use crate::ledger::{LedgerEntryType, LedgerPage, LedgerRow};
+use crate::ledger_export::ExportFormat;
+use crate::queries::{AtmView, BankAccountView, LedgerEntry};
+use crate::rebuild::{RebuildProgress, RebuildStatus};
+use crate::reports::{ReportFormat, ReportRow, ReportTotals};
+use crate::review_queue::FlaggedTransaction;
+use crate::route_handler;
+use crate::webhooks::{NewWebhook, WebhookDelivery, WebhookSubscription};
+
+// The OpenAPI document of the REST routes, generated from the handlers and the types of their
+// parameters and bodies. GraphQL is described by its own schema, see `/graphql`.
+#[derive(OpenApi)]
+#[openapi(
+    info(description = "Commands and queries for bank accounts and ATMs."),
+    paths(
+        route_handler::query_handler,
+        route_handler::command_handler,
+        route_handler::close_account_handler,
+        route_handler::deposit_handler,
+        route_handler::withdrawal_handler,
+        route_handler::check_handler,
+        route_handler::ledger_handler,
+        route_handler::events_handler,
+        route_handler::export_handler,
+        route_handler::account_stream_handler,
+        route_handler::accounts_handler,
+        route_handler::open_account_handler,
+        route_handler::rebuild_progress_handler,
+        route_handler::rebuild_handler,
+        route_handler::dead_letters_handler,
+        route_handler::dead_letter_retry_handler,
+        route_handler::metrics_handler,
+        route_handler::webhooks_handler,
+        route_handler::create_webhook_handler,
+        route_handler::delete_webhook_handler,
+        route_handler::webhook_deliveries_handler,
+        route_handler::daily_report_handler,
+        route_handler::review_queue_handler,
+        route_handler::atm_query_handler,
+        route_handler::atm_command_handler,
+        route_handler::atms_query_handler,
+    ),
+    components(schemas(
+        BankAccountCommand,
+        NewAccount,
+        NewDeposit,
+        NewWithdrawal,
+        NewCheck,
+        AccountType,
+        AccountStatus,
+        BankAccountView,
+        LedgerEntry,
+        LedgerEntryType,
+        LedgerPage,
+        LedgerRow,
+        AccountPage,
+        AccountSummary,
+        AccountSort,
+        SortOrder,
+        ExportFormat,
+        StoredEvent,
+        RebuildProgress,
+        RebuildStatus,
+        DeadLetter,
+        RetryReport,
+        NewWebhook,
+        WebhookSubscription,
+        WebhookDelivery,
+        ReportFormat,
+        ReportRow,
+        ReportTotals,
+        FlaggedTransaction,
+        AtmCommand,
+        AtmStatus,
+        AtmView,
+    )),
+    modifiers(&AdminApiKey),
+    tags(
+        (name = "accounts", description = "Account commands and queries"),
+        (name = "atms", description = "The registered ATMs and their cash inventory"),
+        (name = "reports", description = "Totals of the deposits, withdrawals and checks"),
+        (name = "webhooks", description = "Partner notifications of account events, for admins"),
+        (name = "admin", description = "Events, projection rebuilds and dead letters, for admins"),
+        (name = "metrics", description = "Application metrics"),
+    )
+)]
+pub struct ApiDoc;
+
+// Admin routes require the `X-Admin-Api-Key` header.
+struct AdminApiKey;
+
+impl Modify for AdminApiKey {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "admin_api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(ADMIN_API_KEY_HDR))),
+        );
+    }
+}
+
+// A file of Swagger UI for the OpenAPI document, e.g., `index.html`, or `None` if there is no
+// such file. The assets are embedded in the application by `utoipa-swagger-ui`.
+pub fn swagger_ui_file(path: &str) -> Result<Option<SwaggerFile<'static>>, Box<dyn Error>> {
+    utoipa_swagger_ui::serve(path, Arc::new(Config::from("/openapi.json")))
+}
+
+#[cfg(test)]
+mod openapi_tests {
+    use std::collections::BTreeSet;
+
+    use axum::body::{Body, Bytes};
+    use axum::extract::FromRequest;
+    use axum::http::{Method, Request, StatusCode};
+    use axum::middleware::{from_fn, Next};
+    use axum::Router;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+    use utoipa::openapi::schema::AdditionalProperties;
+    use utoipa::openapi::{PathItemType, RefOr, Schema, SchemaType};
+    use utoipa::OpenApi;
+
+    use crate::admin_extractor::ADMIN_API_KEY_HDR;
+    use crate::config::StorageMode;
+    use crate::domain::atm::commands::AtmCommand;
+    use crate::openapi::ApiDoc;
+    use crate::routes::router;
+    use crate::routes::routes;
+    use crate::state::application_state_with_storage;
+
+    // Routes that are described elsewhere.
+    const UNDOCUMENTED_ROUTES: [&str; 5] = [
+        "/graphql",
+        "/openapi.json",
+        "/docs",
+        "/docs/",
+        "/docs/*file",
+    ];
+
+    const METHODS: [Method; 5] = [
+        Method::GET,
+        Method::POST,
+        Method::PUT,
+        Method::PATCH,
+        Method::DELETE,
+    ];
+
+    // Answers every request that matches a method of a route without calling its handler.
+    async fn matched(_request: Request<Body>, _next: Next<Body>) -> StatusCode {
+        StatusCode::OK
+    }
+
+    fn method(path_item_type: &PathItemType) -> Method {
+        match path_item_type {
+            PathItemType::Get => Method::GET,
+            PathItemType::Post => Method::POST,
+            PathItemType::Put => Method::PUT,
+            PathItemType::Patch => Method::PATCH,
+            PathItemType::Delete => Method::DELETE,
+            PathItemType::Options => Method::OPTIONS,
+            PathItemType::Head => Method::HEAD,
+            PathItemType::Trace => Method::TRACE,
+            PathItemType::Connect => Method::CONNECT,
+        }
+    }
+
+    // The OpenAPI form of an Axum path, e.g., `/account/{account_id}` for `/account/:account_id`.
+    fn openapi_path(path: &str) -> String {
+        path.split('/')
+            .map(|segment| match segment.strip_prefix(':') {
+                Some(param) => format!("{{{}}}", param),
+                None => segment.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    #[tokio::test]
+    async fn test_spec_matches_routes() {
+        // The routes are probed without calling their handlers, so no database is needed.
+        let state = application_state_with_storage(StorageMode::Memory).await;
+        let router = routes()
+            .into_iter()
+            .fold(Router::new(), |router, (path, method_router)| {
+                router.route(path, method_router.route_layer(from_fn(matched)))
+            })
+            .with_state(state);
+        let mut routed = BTreeSet::new();
+        for (path, _) in routes() {
+            if UNDOCUMENTED_ROUTES.contains(&path) {
+                continue;
+            }
+            let uri = path
+                .replace(":account_id", "ACCT-1")
+                .replace(":atm_id", "ATM-1");
+            let uri = uri.replace(":id", "1");
+            for method in METHODS {
+                let request = Request::builder()
+                    .method(method.clone())
+                    .uri(&uri)
+                    .body(Body::empty())
+                    .unwrap();
+                let response = router.clone().oneshot(request).await.unwrap();
+                if response.status() == StatusCode::OK {
+                    routed.insert((openapi_path(path), method.to_string()));
+                }
+            }
+        }
+        let documented: BTreeSet<(String, String)> = ApiDoc::openapi()
+            .paths
+            .paths
+            .iter()
+            .flat_map(|(path, item)| {
+                item.operations
+                    .keys()
+                    .map(|operation| (path.clone(), method(operation).to_string()))
+            })
+            .collect();
+        assert_eq!(documented, routed);
+    }
+
+    // Checks a JSON value against a schema of the spec, every field of an object must be
+    // documented and every required field present.
+    fn check_schema(
+        spec: &utoipa::openapi::OpenApi,
+        schema: &RefOr<Schema>,
+        value: &Value,
+        at: &str,
+    ) -> Result<(), String> {
+        let schema = match schema {
+            RefOr::Ref(reference) => {
+                let name = reference.ref_location.rsplit('/').next().unwrap();
+                let components = spec.components.as_ref().unwrap();
+                let schema = components
+                    .schemas
+                    .get(name)
+                    .ok_or_else(|| format!("{}: undocumented schema {}", at, name))?;
+                return check_schema(spec, schema, value, at);
+            }
+            RefOr::T(schema) => schema,
+        };
+        match schema {
+            Schema::Object(object) => {
+                if value.is_null() && object.nullable {
+                    return Ok(());
+                }
+                let matches = match object.schema_type {
+                    SchemaType::Object => value.is_object(),
+                    SchemaType::Value => true,
+                    SchemaType::String => value.is_string(),
+                    SchemaType::Integer => value.is_i64() || value.is_u64(),
+                    SchemaType::Number => value.is_number(),
+                    SchemaType::Boolean => value.is_boolean(),
+                    SchemaType::Array => value.is_array(),
+                };
+                if !matches {
+                    return Err(format!("{}: {} does not match the schema type", at, value));
+                }
+                if let Some(values) = &object.enum_values {
+                    if !values.contains(value) {
+                        return Err(format!("{}: {} is not one of {:?}", at, value, values));
+                    }
+                }
+                let Some(fields) = value.as_object() else {
+                    return Ok(());
+                };
+                for required in &object.required {
+                    if !fields.contains_key(required) {
+                        return Err(format!("{}: missing {}", at, required));
+                    }
+                }
+                for (name, field) in fields {
+                    let at = format!("{}.{}", at, name);
+                    match (
+                        object.properties.get(name),
+                        object.additional_properties.as_deref(),
+                    ) {
+                        (Some(schema), _) => check_schema(spec, schema, field, &at)?,
+                        (None, Some(AdditionalProperties::RefOr(schema))) => {
+                            check_schema(spec, schema, field, &at)?
+                        }
+                        (None, Some(AdditionalProperties::FreeForm(true))) => {}
+                        (None, _) => return Err(format!("{}: undocumented field", at)),
+                    }
+                }
+                Ok(())
+            }
+            Schema::Array(array) => match value.as_array() {
+                Some(items) => items.iter().enumerate().try_for_each(|(index, item)| {
+                    check_schema(spec, &array.items, item, &format!("{}[{}]", at, index))
+                }),
+                None if value.is_null() && array.nullable => Ok(()),
+                None => Err(format!("{}: {} is not an array", at, value)),
+            },
+            Schema::OneOf(one_of) => {
+                if one_of
+                    .items
+                    .iter()
+                    .any(|schema| check_schema(spec, schema, value, at).is_ok())
+                {
+                    Ok(())
+                } else {
+                    Err(format!("{}: {} matches none of the variants", at, value))
+                }
+            }
+            Schema::AllOf(all_of) => all_of
+                .items
+                .iter()
+                .try_for_each(|schema| check_schema(spec, schema, value, at)),
+            _ => Ok(()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spec_matches_bodies() {
+        let spec = ApiDoc::openapi();
+        let state = application_state_with_storage(StorageMode::Memory).await;
+        let register_atm = AtmCommand::RegisterAtm {
+            atm_id: "ATM-1".to_string(),
+            location: "Main St branch".to_string(),
+        };
+        state.atm_cqrs.execute("ATM-1", register_atm).await.unwrap();
+        let load_cash = AtmCommand::LoadCash { amount: 1000.0 };
+        state.atm_cqrs.execute("ATM-1", load_cash).await.unwrap();
+        let router = router().with_state(state);
+        // Each request along with the path of its operation and the expected status. Requests
+        // are made with an invalid admin key, so that admin routes are refused.
+        let requests = [
+            (
+                Method::POST,
+                "/atm/ATM-1",
+                "/atm/{atm_id}",
+                Some(json!({"LoadCash": {"amount": 1000.0}})),
+                403,
+            ),
+            (
+                Method::POST,
+                "/accounts",
+                "/accounts",
+                Some(
+                    json!({"account_id": "ACCT-1", "account_type": "Savings", "holders": ["Ann"]}),
+                ),
+                201,
+            ),
+            (
+                Method::POST,
+                "/account/ACCT-1",
+                "/account/{account_id}",
+                Some(json!({"DepositMoney": {"amount": 200.0}})),
+                204,
+            ),
+            (
+                Method::POST,
+                "/account/ACCT-1/deposits",
+                "/account/{account_id}/deposits",
+                Some(json!({"amount": 100.0})),
+                204,
+            ),
+            (
+                Method::POST,
+                "/account/ACCT-1/withdrawals",
+                "/account/{account_id}/withdrawals",
+                Some(json!({"amount": 40.0, "atm_id": "ATM-1"})),
+                204,
+            ),
+            (
+                Method::POST,
+                "/account/ACCT-1/checks",
+                "/account/{account_id}/checks",
+                Some(json!({"check_number": "1170", "amount": 60.0})),
+                204,
+            ),
+            (
+                Method::GET,
+                "/account/ACCT-1",
+                "/account/{account_id}",
+                None,
+                200,
+            ),
+            (Method::GET, "/atm/ATM-1", "/atm/{atm_id}", None, 200),
+            (Method::GET, "/atms", "/atms", None, 200),
+            (Method::GET, "/accounts", "/accounts", None, 501),
+            (
+                Method::GET,
+                "/account/ACCT-1/ledger",
+                "/account/{account_id}/ledger",
+                None,
+                501,
+            ),
+            (
+                Method::GET,
+                "/admin/dead-letters",
+                "/admin/dead-letters",
+                None,
+                403,
+            ),
+        ];
+        for (request_method, uri, path, body, status) in requests {
+            let operation = spec
+                .paths
+                .paths
+                .get(path)
+                .and_then(|item| {
+                    item.operations
+                        .iter()
+                        .find(|(operation, _)| method(operation) == request_method)
+                })
+                .map(|(_, operation)| operation)
+                .unwrap_or_else(|| panic!("{} {} is not documented", request_method, path));
+            let request = Request::builder()
+                .method(request_method.clone())
+                .uri(uri)
+                .header(ADMIN_API_KEY_HDR, "invalid");
+            let request = match body {
+                Some(body) => {
+                    let content = &operation.request_body.as_ref().unwrap().content;
+                    let schema = &content["application/json"].schema;
+                    check_schema(&spec, schema, &body, "request").unwrap();
+                    request
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(body.to_string()))
+                        .unwrap()
+                }
+                None => request.body(Body::empty()).unwrap(),
+            };
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(
+                status,
+                response.status().as_u16(),
+                "{} {}",
+                request_method,
+                uri
+            );
+            let documented = match &operation.responses.responses[&status.to_string()] {
+                RefOr::T(response) => response,
+                RefOr::Ref(_) => panic!("unexpected response reference"),
+            };
+            let body = Bytes::from_request(Request::new(response.into_body()), &())
+                .await
+                .unwrap();
+            match documented.content.get("application/json") {
+                Some(content) => {
+                    let body: Value = serde_json::from_slice(&body).unwrap();
+                    check_schema(&spec, &content.schema, &body, "response")
+                        .unwrap_or_else(|err| panic!("{} {}: {}", request_method, uri, err));
+                }
+                None if documented.content.contains_key("text/plain") => {}
+                None => assert!(
+                    body.is_empty(),
+                    "{} {} has an undocumented body",
+                    request_method,
+                    uri
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parameter_descriptions() {
+        for (path, item) in ApiDoc::openapi().paths.paths {
+            for operation in item.operations.values() {
+                for parameter in operation.parameters.iter().flatten() {
+                    assert!(
+                        parameter.description.is_some(),
+                        "{} {} has no description",
+                        path,
+                        parameter.name
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,479 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use cqrs_es::persist::{
+    PersistedEventRepository, PersistenceError, ReplayStream, SerializedEvent, SerializedSnapshot,
+};
+use cqrs_es::{Aggregate, EventEnvelope, Query};
+use postgres_es::PostgresEventRepository;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Pool, Postgres, Row};
+use tokio::task::JoinHandle;
+
+use crate::dead_letter::DeadLetters;
+use crate::domain::aggregate::BankAccount;
+use crate::domain::atm::aggregate::Atm;
+use crate::domain::atm::commands::AtmCommand;
+use crate::domain::events::BankAccountEvent;
+use crate::event_store::AppCqrs;
+use crate::services::BankAccountApi;
+use crate::webhooks::enqueue_deliveries;
+
+// An external side effect that may only be performed after the event requiring it has been
+// committed. These are stored as the payload of a row in the `outbox` table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OutboxMessage {
+    DispenseCash {
+        atm_id: String,
+        amount: f64,
+        // The reservation of the cash, messages without one are identified by their id.
+        #[serde(default)]
+        withdrawal_id: String,
+    },
+}
+
+impl OutboxMessage {
+    // Any side effects that are required by a newly committed event.
+    fn for_event(event: &SerializedEvent) -> Result<Option<Self>, PersistenceError> {
+        if event.aggregate_type != BankAccount::aggregate_type() {
+            return Ok(None);
+        }
+        let payload: BankAccountEvent = serde_json::from_value(event.payload.clone())?;
+        Ok(Self::for_account_event(&payload))
+    }
+
+    fn for_account_event(event: &BankAccountEvent) -> Option<Self> {
+        match event {
+            BankAccountEvent::CustomerWithdrewCash {
+                amount,
+                atm_id,
+                withdrawal_id,
+                ..
+            } => Some(OutboxMessage::DispenseCash {
+                atm_id: atm_id.clone(),
+                amount: *amount,
+                withdrawal_id: withdrawal_id.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn message_type(&self) -> &'static str {
+        match self {
+            OutboxMessage::DispenseCash { .. } => "DispenseCash",
+        }
+    }
+}
+
+const INSERT_EVENT: &str = "
+INSERT INTO events (aggregate_type, aggregate_id, sequence, event_type, event_version, payload, metadata)
+VALUES ($1, $2, $3, $4, $5, $6, $7)";
+
+const INSERT_OUTBOX_MESSAGE: &str = "
+INSERT INTO outbox (aggregate_type, aggregate_id, sequence, message_type, payload)
+VALUES ($1, $2, $3, $4, $5)";
+
+// An event repository that commits any outbox messages and webhook deliveries along with the
+// events that caused them.
+// Reads are delegated to the standard `PostgresEventRepository`.
+pub struct OutboxEventRepository {
+    pool: Pool<Postgres>,
+    events: PostgresEventRepository,
+}
+
+impl OutboxEventRepository {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        let events = PostgresEventRepository::new(pool.clone());
+        Self { pool, events }
+    }
+
+    async fn insert_events<A: Aggregate>(
+        &self,
+        events: &[SerializedEvent],
+    ) -> Result<(), PersistenceError> {
+        let mut tx = self.pool.begin().await.map_err(persistence_error)?;
+        for event in events {
+            sqlx::query(INSERT_EVENT)
+                .bind(A::aggregate_type())
+                .bind(&event.aggregate_id)
+                .bind(event.sequence as i64)
+                .bind(&event.event_type)
+                .bind(&event.event_version)
+                .bind(&event.payload)
+                .bind(&event.metadata)
+                .execute(&mut *tx)
+                .await
+                .map_err(persistence_error)?;
+            if let Some(message) = OutboxMessage::for_event(event)? {
+                sqlx::query(INSERT_OUTBOX_MESSAGE)
+                    .bind(A::aggregate_type())
+                    .bind(&event.aggregate_id)
+                    .bind(event.sequence as i64)
+                    .bind(message.message_type())
+                    .bind(serde_json::to_value(&message)?)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(persistence_error)?;
+            }
+            if A::aggregate_type() == BankAccount::aggregate_type() {
+                enqueue_deliveries(&mut tx, event)
+                    .await
+                    .map_err(persistence_error)?;
+            }
+        }
+        tx.commit().await.map_err(persistence_error)
+    }
+}
+
+#[async_trait]
+impl PersistedEventRepository for OutboxEventRepository {
+    async fn get_events<A: Aggregate>(
+        &self,
+        aggregate_id: &str,
+    ) -> Result<Vec<SerializedEvent>, PersistenceError> {
+        self.events.get_events::<A>(aggregate_id).await
+    }
+
+    async fn get_last_events<A: Aggregate>(
+        &self,
+        aggregate_id: &str,
+        last_sequence: usize,
+    ) -> Result<Vec<SerializedEvent>, PersistenceError> {
+        self.events
+            .get_last_events::<A>(aggregate_id, last_sequence)
+            .await
+    }
+
+    async fn get_snapshot<A: Aggregate>(
+        &self,
+        aggregate_id: &str,
+    ) -> Result<Option<SerializedSnapshot>, PersistenceError> {
+        self.events.get_snapshot::<A>(aggregate_id).await
+    }
+
+    async fn persist<A: Aggregate>(
+        &self,
+        events: &[SerializedEvent],
+        snapshot_update: Option<(String, Value, usize)>,
+    ) -> Result<(), PersistenceError> {
+        if snapshot_update.is_some() {
+            return Err(PersistenceError::UnknownError(
+                "snapshots are not supported by the outbox event repository".into(),
+            ));
+        }
+        self.insert_events::<A>(events).await
+    }
+
+    async fn stream_events<A: Aggregate>(
+        &self,
+        aggregate_id: &str,
+    ) -> Result<ReplayStream, PersistenceError> {
+        self.events.stream_events::<A>(aggregate_id).await
+    }
+
+    async fn stream_all_events<A: Aggregate>(&self) -> Result<ReplayStream, PersistenceError> {
+        self.events.stream_all_events::<A>().await
+    }
+}
+
+// A unique key violation on the events table means another command has already
+// committed an event with the same sequence.
+fn persistence_error(err: sqlx::Error) -> PersistenceError {
+    let unique_violation = match &err {
+        sqlx::Error::Database(database_error) => database_error.code().as_deref() == Some("23505"),
+        _ => false,
+    };
+    match err {
+        _ if unique_violation => PersistenceError::OptimisticLockError,
+        sqlx::Error::Io(_) | sqlx::Error::Tls(_) => {
+            PersistenceError::ConnectionError(Box::new(err))
+        }
+        _ => PersistenceError::UnknownError(Box::new(err)),
+    }
+}
+
+// A message is claimed by pushing back its next attempt for the length of a lease, so that the
+// claim is committed before delivery and no other dispatcher delivers it while it is held. Should
+// the dispatcher stop, the message is delivered again once the lease expires.
+const CLAIM_PENDING_MESSAGES: &str = "
+UPDATE outbox SET next_attempt_at = now() + make_interval(secs => $2)
+  WHERE id IN (
+    SELECT id FROM outbox
+      WHERE processed_at IS NULL AND abandoned_at IS NULL AND next_attempt_at <= now()
+      ORDER BY id
+      LIMIT $1
+      FOR UPDATE SKIP LOCKED)
+  RETURNING id, aggregate_type, aggregate_id, sequence, payload, completed_steps";
+
+const MARK_PROCESSED: &str = "UPDATE outbox SET processed_at = now() WHERE id = $1";
+
+// The steps completed before the failure are recorded, so that a retry resumes with the next.
+// A message that fails `MAX_ATTEMPTS` times is abandoned.
+const MARK_FAILED: &str = "
+UPDATE outbox
+  SET attempts = attempts + 1,
+      last_error = $2,
+      next_attempt_at = now() + make_interval(secs => LEAST(power(2, attempts), $3)),
+      completed_steps = $4,
+      abandoned_at = CASE WHEN attempts + 1 >= $5 THEN now() END
+  WHERE id = $1
+  RETURNING abandoned_at IS NOT NULL AS abandoned";
+
+// Abandoned messages with an unresolved dead letter are retried from their first attempt.
+const REQUEUE_ABANDONED: &str = "
+UPDATE outbox SET attempts = 0, abandoned_at = NULL, next_attempt_at = now()
+  FROM dead_letters
+  WHERE dead_letters.projection = $1 AND dead_letters.resolved_at IS NULL
+    AND dead_letters.aggregate_type = outbox.aggregate_type
+    AND dead_letters.aggregate_id = outbox.aggregate_id
+    AND dead_letters.sequence = outbox.sequence
+    AND outbox.abandoned_at IS NOT NULL AND outbox.processed_at IS NULL";
+
+const RESOLVE_DEAD_LETTERS: &str = "
+UPDATE dead_letters SET resolved_at = now() WHERE projection = $1 AND resolved_at IS NULL";
+
+// The name under which abandoned outbox messages are recorded as dead letters.
+pub const OUTBOX_DEAD_LETTERS: &str = "outbox";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const BATCH_SIZE: i64 = 20;
+const MAX_ATTEMPTS: i32 = 10;
+const MAX_BACKOFF_SECONDS: f64 = 300.0;
+const LEASE_SECONDS: f64 = 300.0;
+
+// Polls the outbox and performs the side effects of committed events.
+// Delivery is at-least-once: a message is only marked as processed after its side effect
+// succeeds, failures are retried with an exponential backoff until `MAX_ATTEMPTS` is reached.
+// The message is then abandoned and recorded as a dead letter, to be requeued by
+// `DeadLetterRetry` once the cause is fixed.
+pub struct OutboxDispatcher {
+    pool: Pool<Postgres>,
+    services: Arc<dyn BankAccountApi>,
+    atm_cqrs: Arc<AppCqrs<Atm>>,
+    dead_letters: DeadLetters,
+}
+
+impl OutboxDispatcher {
+    pub fn new(
+        pool: Pool<Postgres>,
+        services: Arc<dyn BankAccountApi>,
+        atm_cqrs: Arc<AppCqrs<Atm>>,
+        dead_letters: DeadLetters,
+    ) -> Self {
+        Self {
+            pool,
+            services,
+            atm_cqrs,
+            dead_letters,
+        }
+    }
+
+    // Runs the dispatcher in a background task for the life of the application.
+    pub fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                self.drain().await;
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        })
+    }
+
+    // Dispatches messages until none are due, a message that fails is retried once its backoff
+    // has passed.
+    pub async fn drain(&self) {
+        loop {
+            match self.dispatch_pending().await {
+                // A full batch suggests that more messages are waiting.
+                Ok(dispatched) if dispatched as i64 == BATCH_SIZE => continue,
+                Ok(_) => return,
+                Err(err) => {
+                    println!("Error: outbox dispatch failed: {}\n", err);
+                    return;
+                }
+            }
+        }
+    }
+
+    // The claimed messages are delivered outside of any transaction, a slow ATM holds no locks.
+    async fn dispatch_pending(&self) -> Result<usize, sqlx::Error> {
+        let mut rows = sqlx::query(CLAIM_PENDING_MESSAGES)
+            .bind(BATCH_SIZE)
+            .bind(LEASE_SECONDS)
+            .fetch_all(&self.pool)
+            .await?;
+        rows.sort_by_key(|row| row.get::<i64, _>("id"));
+        let dispatched = rows.len();
+        for row in rows {
+            let id: i64 = row.get("id");
+            let mut completed_steps: i32 = row.get("completed_steps");
+            let idempotency_key = format!("outbox-{}", id);
+            let result = match serde_json::from_value::<OutboxMessage>(row.get("payload")) {
+                Ok(message) => {
+                    self.deliver(&message, &idempotency_key, &mut completed_steps)
+                        .await
+                }
+                Err(err) => Err(err.to_string()),
+            };
+            let err = match result {
+                Ok(_) => {
+                    sqlx::query(MARK_PROCESSED)
+                        .bind(id)
+                        .execute(&self.pool)
+                        .await?;
+                    continue;
+                }
+                Err(err) => err,
+            };
+            let abandoned: bool = sqlx::query(MARK_FAILED)
+                .bind(id)
+                .bind(&err)
+                .bind(MAX_BACKOFF_SECONDS)
+                .bind(completed_steps)
+                .bind(MAX_ATTEMPTS)
+                .fetch_one(&self.pool)
+                .await?
+                .get("abandoned");
+            if abandoned {
+                let aggregate_type: String = row.get("aggregate_type");
+                let aggregate_id: String = row.get("aggregate_id");
+                self.dead_letters
+                    .record_failure(
+                        OUTBOX_DEAD_LETTERS,
+                        &aggregate_type,
+                        &aggregate_id,
+                        row.get("sequence"),
+                        &format!("outbox message {} abandoned: {}", id, err),
+                    )
+                    .await;
+            }
+        }
+        Ok(dispatched)
+    }
+
+    // Performs the steps of a message following those already completed, counting each as it
+    // succeeds. Every step is also idempotent, a step that succeeded without being recorded
+    // (e.g., if the dispatcher stopped) is repeated with the same idempotency key.
+    async fn deliver(
+        &self,
+        message: &OutboxMessage,
+        idempotency_key: &str,
+        completed_steps: &mut i32,
+    ) -> Result<(), String> {
+        match message {
+            // Once the cash has left the ATM it is recorded in the ATM registry, a retry after
+            // recording fails does not call the ATM again.
+            OutboxMessage::DispenseCash {
+                atm_id,
+                amount,
+                withdrawal_id,
+            } => {
+                if *completed_steps < 1 {
+                    self.services
+                        .atm_withdrawal(atm_id, *amount, idempotency_key)
+                        .await
+                        .map_err(|err| format!("atm rule violation: {:?}", err))?;
+                    *completed_steps = 1;
+                }
+                // The reserved cash is dispensed by the id of its reservation.
+                let withdrawal_id = match withdrawal_id.as_str() {
+                    "" => idempotency_key,
+                    withdrawal_id => withdrawal_id,
+                };
+                let command = AtmCommand::DispenseCash {
+                    amount: *amount,
+                    withdrawal_id: withdrawal_id.to_string(),
+                };
+                self.atm_cqrs
+                    .execute(atm_id, command)
+                    .await
+                    .map_err(|err| err.to_string())?;
+                *completed_steps = 2;
+                Ok(())
+            }
+        }
+    }
+}
+
+// Without Postgres there is no outbox, so the side effects of each event are instead performed as
+// it is dispatched, after it has been committed to the in-memory event store. A failure is
+// logged and not retried.
+#[async_trait]
+impl Query<BankAccount> for OutboxDispatcher {
+    async fn dispatch(&self, aggregate_id: &str, events: &[EventEnvelope<BankAccount>]) {
+        for event in events {
+            if let Some(message) = OutboxMessage::for_account_event(&event.payload) {
+                let idempotency_key = format!("{}-{}", aggregate_id, event.sequence);
+                if let Err(err) = self.deliver(&message, &idempotency_key, &mut 0).await {
+                    println!("Error: side effect {:?} failed: {}\n", message, err);
+                }
+            }
+        }
+    }
+}
+
+// Requeues the abandoned messages that were recorded as dead letters, returning their number.
+pub async fn requeue_abandoned(pool: &Pool<Postgres>) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let requeued = sqlx::query(REQUEUE_ABANDONED)
+        .bind(OUTBOX_DEAD_LETTERS)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(RESOLVE_DEAD_LETTERS)
+        .bind(OUTBOX_DEAD_LETTERS)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(requeued.rows_affected())
+}
+
+#[cfg(test)]
+mod outbox_tests {
+    use cqrs_es::persist::SerializedEvent;
+    use serde_json::{json, Value};
+
+    use crate::outbox::OutboxMessage;
+
+    fn serialized_event(aggregate_type: &str, event_type: &str, payload: Value) -> SerializedEvent {
+        SerializedEvent::new(
+            "ACCT-1".to_string(),
+            2,
+            aggregate_type.to_string(),
+            event_type.to_string(),
+            "1.0".to_string(),
+            payload,
+            json!({}),
+        )
+    }
+
+    #[test]
+    fn test_withdrawal_dispenses_cash() {
+        let event = serialized_event(
+            "account",
+            "CustomerWithdrewCash",
+            json!({"CustomerWithdrewCash": {
+                "amount": 40.0,
+                "atm_id": "ATM-1",
+                "balance": 60.0,
+                "withdrawal_id": "withdrawal-1"
+            }}),
+        );
+        let expected = OutboxMessage::DispenseCash {
+            atm_id: "ATM-1".to_string(),
+            amount: 40.0,
+            withdrawal_id: "withdrawal-1".to_string(),
+        };
+        assert_eq!(Some(expected), OutboxMessage::for_event(&event).unwrap());
+    }
+
+    #[test]
+    fn test_deposit_has_no_side_effects() {
+        let event = serialized_event(
+            "account",
+            "CustomerDepositedMoney",
+            json!({"CustomerDepositedMoney": {"amount": 40.0, "balance": 40.0}}),
+        );
+        assert_eq!(None, OutboxMessage::for_event(&event).unwrap());
+    }
+}
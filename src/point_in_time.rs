@@ -0,0 +1,105 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use cqrs_es::persist::PersistenceError;
+use cqrs_es::{EventEnvelope, EventStore, View};
+
+use crate::domain::aggregate::BankAccount;
+use crate::queries::{event_time, BankAccountView};
+
+// The point in time at which the state of an account is requested, given as either
+// the sequence of the last event to include, an RFC 3339 timestamp, or a date.
+// A date includes every event committed on that day (in UTC).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AsOf {
+    Sequence(usize),
+    Time(DateTime<Utc>),
+}
+
+impl AsOf {
+    pub fn parse(as_of: &str) -> Result<Self, String> {
+        if let Ok(sequence) = as_of.parse::<usize>() {
+            return Ok(AsOf::Sequence(sequence));
+        }
+        if let Ok(time) = DateTime::parse_from_rfc3339(as_of) {
+            return Ok(AsOf::Time(time.with_timezone(&Utc)));
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(as_of, "%Y-%m-%d") {
+            let end_of_day = date.and_hms_opt(0, 0, 0).unwrap().and_utc() + Duration::days(1)
+                - Duration::nanoseconds(1);
+            return Ok(AsOf::Time(end_of_day));
+        }
+        Err(format!(
+            "invalid as_of, expected a sequence, an RFC 3339 timestamp or a date: {}",
+            as_of
+        ))
+    }
+
+    fn includes(&self, event: &EventEnvelope<BankAccount>) -> bool {
+        match self {
+            AsOf::Sequence(sequence) => event.sequence <= *sequence,
+            AsOf::Time(time) => event_time(event) <= *time,
+        }
+    }
+}
+
+// Reconstructs the view of an account as it was at a point in time by replaying its events
+// from the event store, the stored projection is not used.
+// Returns `None` if the account had no events at that time.
+pub async fn account_view_as_of<S: EventStore<BankAccount>>(
+    store: &S,
+    account_id: &str,
+    as_of: AsOf,
+) -> Result<Option<BankAccountView>, PersistenceError> {
+    let events = store
+        .load_events(account_id)
+        .await
+        .map_err(|err| PersistenceError::UnknownError(Box::new(err)))?;
+    let mut view: Option<BankAccountView> = None;
+    // Events are loaded in sequence order.
+    for event in events.iter().take_while(|event| as_of.includes(event)) {
+        view.get_or_insert_with(BankAccountView::default)
+            .update(event);
+    }
+    Ok(view)
+}
+
+#[cfg(test)]
+mod point_in_time_tests {
+    use std::collections::HashMap;
+
+    use chrono::{DateTime, Utc};
+    use cqrs_es::EventEnvelope;
+
+    use crate::domain::aggregate::BankAccount;
+    use crate::domain::events::BankAccountEvent;
+    use crate::point_in_time::AsOf;
+
+    fn deposit_at(sequence: usize, time: &str) -> EventEnvelope<BankAccount> {
+        EventEnvelope {
+            aggregate_id: "ACCT-1".to_string(),
+            sequence,
+            payload: BankAccountEvent::CustomerDepositedMoney {
+                amount: 100.0,
+                balance: 100.0 * sequence as f64,
+            },
+            metadata: HashMap::from([("time".to_string(), time.to_string())]),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Ok(AsOf::Sequence(12)), AsOf::parse("12"));
+        let time: DateTime<Utc> = "2022-03-31T10:00:00Z".parse().unwrap();
+        assert_eq!(Ok(AsOf::Time(time)), AsOf::parse("2022-03-31T10:00:00Z"));
+        assert!(AsOf::parse("March 31st").is_err());
+    }
+
+    #[test]
+    fn test_date_includes_the_whole_day() {
+        let as_of = AsOf::parse("2022-03-31").unwrap();
+        assert!(as_of.includes(&deposit_at(1, "2022-03-31T23:59:59Z")));
+        assert!(!as_of.includes(&deposit_at(2, "2022-04-01T00:00:00Z")));
+        let as_of = AsOf::Sequence(1);
+        assert!(as_of.includes(&deposit_at(1, "2022-04-01T00:00:00Z")));
+        assert!(!as_of.includes(&deposit_at(2, "2022-03-01T00:00:00Z")));
+    }
+}
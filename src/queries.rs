@@ -1,55 +1,178 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+
 use async_trait::async_trait;
-use cqrs_es::persist::GenericQuery;
-use cqrs_es::{EventEnvelope, Query, View};
-use postgres_es::PostgresViewRepository;
+use chrono::{DateTime, Utc};
+use cqrs_es::persist::{PersistenceError, ViewContext, ViewRepository};
+use cqrs_es::{Aggregate, EventEnvelope, View};
 use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Row};
+use utoipa::ToSchema;
 
+use crate::command_extractor::recorded_time;
+use crate::dead_letter::FallibleQuery;
 use crate::domain::aggregate::BankAccount;
+use crate::domain::atm::aggregate::{Atm, AtmStatus};
+use crate::domain::atm::events::AtmEvent;
 use crate::domain::events::BankAccountEvent;
+use crate::ledger::LedgerEntryType;
+use crate::mem_view_repository::MemViewRepository;
+
+// Our main query, this one will serialize and persist our view in Postgres (or in memory)
+// after it is updated. Unlike the `GenericQuery` provided by `cqrs_es`, failures are returned so that
+// they may be recorded and retried.
+pub type AccountQuery = ViewProjection<AccountViewRepository, BankAccountView, BankAccount>;
+
+pub type AccountViewRepository = dyn ViewRepository<BankAccountView, BankAccount>;
+
+// The number of ledger entries kept in the view, the full ledger is paged by `LedgerQuery`.
+const RECENT_LEDGER_ENTRIES: usize = 20;
+
+// A projection that maintains a materialized view of each aggregate instance.
+pub struct ViewProjection<R: ?Sized, V, A> {
+    view_repository: Arc<R>,
+    phantom: PhantomData<(V, A)>,
+}
 
-pub struct SimpleLoggingQuery {}
+impl<R: ?Sized, V, A> ViewProjection<R, V, A> {
+    pub fn new(view_repository: Arc<R>) -> Self {
+        Self {
+            view_repository,
+            phantom: PhantomData,
+        }
+    }
+}
 
-// Our simplest query, this is great for debugging but absolutely useless in production.
-// This query just pretty prints the events as they are processed.
 #[async_trait]
-impl Query<BankAccount> for SimpleLoggingQuery {
-    async fn dispatch(&self, aggregate_id: &str, events: &[EventEnvelope<BankAccount>]) {
+impl<R, V, A> FallibleQuery<A> for ViewProjection<R, V, A>
+where
+    R: ViewRepository<V, A> + ?Sized,
+    V: View<A>,
+    A: Aggregate,
+{
+    async fn apply(
+        &self,
+        view_id: &str,
+        events: &[EventEnvelope<A>],
+    ) -> Result<(), PersistenceError> {
+        let (mut view, view_context) = self
+            .view_repository
+            .load_with_context(view_id)
+            .await?
+            .unwrap_or_else(|| (V::default(), ViewContext::new(view_id.to_string(), 0)));
         for event in events {
-            let payload = serde_json::to_string_pretty(&event.payload).unwrap();
-            println!("{}-{}\n{}", aggregate_id, event.sequence, payload);
+            view.update(event);
         }
+        self.view_repository.update_view(view, view_context).await
     }
-}
 
-// Our second query, this one will be handled with Postgres `GenericQuery`
-// which will serialize and persist our view after it is updated. It also
-// provides a `load` method to deserialize the view on request.
-pub type AccountQuery = GenericQuery<
-    PostgresViewRepository<BankAccountView, BankAccount>,
-    BankAccountView,
-    BankAccount,
->;
+    // The view is rebuilt from all of the events, replacing the stored view.
+    async fn reprocess(
+        &self,
+        view_id: &str,
+        events: &[EventEnvelope<A>],
+    ) -> Result<(), PersistenceError> {
+        let view_context = match self.view_repository.load_with_context(view_id).await? {
+            Some((_, view_context)) => view_context,
+            None => ViewContext::new(view_id.to_string(), 0),
+        };
+        let mut view = V::default();
+        for event in events {
+            view.update(event);
+        }
+        self.view_repository.update_view(view, view_context).await
+    }
+}
 
 // The view for a BankAccount query, for a standard http application this should
 // be designed to reflect the response dto that will be returned to a user.
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 pub struct BankAccountView {
-    account_id: Option<String>,
-    balance: f64,
-    written_checks: Vec<String>,
-    ledger: Vec<LedgerEntry>,
+    // The sequence of the last event applied to the view.
+    #[serde(default)]
+    pub(crate) version: usize,
+    pub(crate) account_id: Option<String>,
+    pub(crate) balance: f64,
+    pub(crate) written_checks: Vec<String>,
+    // The most recent entries of the ledger, oldest first.
+    pub(crate) ledger: Vec<LedgerEntry>,
+}
+
+impl BankAccountView {
+    fn push_ledger_entry(&mut self, entry: LedgerEntry) {
+        self.ledger.push(entry);
+        if self.ledger.len() > RECENT_LEDGER_ENTRIES {
+            let excess = self.ledger.len() - RECENT_LEDGER_ENTRIES;
+            self.ledger.drain(..excess);
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// An entry in the account ledger along with the account balance after it was applied.
+// The sequence and time are those of the event that created the entry.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(from = "StoredLedgerEntry")]
 pub struct LedgerEntry {
+    pub(crate) sequence: usize,
+    pub(crate) recorded_at: DateTime<Utc>,
+    pub(crate) entry_type: LedgerEntryType,
+    pub(crate) description: String,
+    pub(crate) amount: f64,
+    pub(crate) balance: f64,
+}
+
+// A ledger entry as stored in a view, those stored before the sequence, time, type and balance
+// were recorded lack these until the views are rebuilt. The type of these is taken from the
+// description, which was the type or the check number.
+#[derive(Deserialize)]
+struct StoredLedgerEntry {
+    #[serde(default)]
+    sequence: usize,
+    #[serde(default)]
+    recorded_at: DateTime<Utc>,
+    #[serde(default)]
+    entry_type: Option<LedgerEntryType>,
     description: String,
     amount: f64,
+    #[serde(default)]
+    balance: f64,
+}
+
+impl From<StoredLedgerEntry> for LedgerEntry {
+    fn from(entry: StoredLedgerEntry) -> Self {
+        let entry_type = entry
+            .entry_type
+            .unwrap_or(match entry.description.as_str() {
+                "deposit" => LedgerEntryType::Deposit,
+                "atm withdrawal" => LedgerEntryType::AtmWithdrawal,
+                _ => LedgerEntryType::Check,
+            });
+        Self {
+            sequence: entry.sequence,
+            recorded_at: entry.recorded_at,
+            entry_type,
+            description: entry.description,
+            amount: entry.amount,
+            balance: entry.balance,
+        }
+    }
 }
+
 impl LedgerEntry {
-    fn new(description: &str, amount: f64) -> Self {
+    fn new(
+        event: &EventEnvelope<BankAccount>,
+        entry_type: LedgerEntryType,
+        description: &str,
+        amount: f64,
+        balance: f64,
+    ) -> Self {
         Self {
+            sequence: event.sequence,
+            recorded_at: event_time(event),
+            entry_type,
             description: description.to_string(),
             amount,
+            balance,
         }
     }
 }
@@ -59,19 +182,37 @@ impl LedgerEntry {
 // design the events to carry the balance information instead.
 impl View<BankAccount> for BankAccountView {
     fn update(&mut self, event: &EventEnvelope<BankAccount>) {
+        // An event may be applied again, e.g., by a projection rebuild and the live projection.
+        if event.sequence <= self.version {
+            return;
+        }
+        self.version = event.sequence;
         match &event.payload {
-            BankAccountEvent::AccountOpened { account_id } => {
+            BankAccountEvent::AccountOpened { account_id, .. } => {
                 self.account_id = Some(account_id.clone());
             }
 
             BankAccountEvent::CustomerDepositedMoney { amount, balance } => {
-                self.ledger.push(LedgerEntry::new("deposit", *amount));
+                self.push_ledger_entry(LedgerEntry::new(
+                    event,
+                    LedgerEntryType::Deposit,
+                    "deposit",
+                    *amount,
+                    *balance,
+                ));
                 self.balance = *balance;
             }
 
-            BankAccountEvent::CustomerWithdrewCash { amount, balance } => {
-                self.ledger
-                    .push(LedgerEntry::new("atm withdrawal", *amount));
+            BankAccountEvent::CustomerWithdrewCash {
+                amount, balance, ..
+            } => {
+                self.push_ledger_entry(LedgerEntry::new(
+                    event,
+                    LedgerEntryType::AtmWithdrawal,
+                    "atm withdrawal",
+                    *amount,
+                    *balance,
+                ));
                 self.balance = *balance;
             }
 
@@ -80,10 +221,197 @@ impl View<BankAccount> for BankAccountView {
                 amount,
                 balance,
             } => {
-                self.ledger.push(LedgerEntry::new(check_number, *amount));
+                self.push_ledger_entry(LedgerEntry::new(
+                    event,
+                    LedgerEntryType::Check,
+                    check_number,
+                    *amount,
+                    *balance,
+                ));
                 self.written_checks.push(check_number.clone());
                 self.balance = *balance;
             }
+
+            BankAccountEvent::AccountClosed | BankAccountEvent::TransactionFlagged { .. } => {}
         }
     }
 }
+
+// A query for the registered ATMs, the view tracks the cash inventory of each ATM so that
+// operations can plan replenishment, and is used to validate withdrawals.
+pub type AtmQuery = ViewProjection<AtmViewRepository, AtmView, Atm>;
+
+pub type AtmViewRepository = dyn ViewRepository<AtmView, Atm>;
+
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct AtmView {
+    atm_id: Option<String>,
+    location: String,
+    status: AtmStatus,
+    cash_on_hand: f64,
+    cash_dispensed: f64,
+}
+
+impl AtmView {
+    pub fn status(&self) -> AtmStatus {
+        self.status
+    }
+
+    pub fn cash_on_hand(&self) -> f64 {
+        self.cash_on_hand
+    }
+}
+
+impl View<Atm> for AtmView {
+    fn update(&mut self, event: &EventEnvelope<Atm>) {
+        match &event.payload {
+            AtmEvent::AtmRegistered { atm_id, location } => {
+                self.atm_id = Some(atm_id.clone());
+                self.location = location.clone();
+                self.status = AtmStatus::Active;
+            }
+            AtmEvent::AtmDecommissioned => {
+                self.status = AtmStatus::Decommissioned;
+            }
+            AtmEvent::CashLoaded { cash_on_hand, .. } => {
+                self.cash_on_hand = *cash_on_hand;
+            }
+            // Reservations are held by the `Atm` aggregate, the cash is on hand until dispensed.
+            AtmEvent::CashReserved { .. } => {}
+            AtmEvent::CashDispensed {
+                amount,
+                cash_on_hand,
+                ..
+            } => {
+                self.cash_dispensed += amount;
+                self.cash_on_hand = *cash_on_hand;
+            }
+        }
+    }
+}
+
+const SELECT_ATM_VIEWS: &str = "SELECT payload FROM atm_query ORDER BY view_id";
+
+// Loads the views of every ATM, the `ViewRepository` only supports loading a single view.
+#[derive(Clone)]
+pub enum AtmViews {
+    Postgres(Pool<Postgres>),
+    Memory(Arc<MemViewRepository<AtmView, Atm>>),
+}
+
+impl AtmViews {
+    pub async fn load(&self) -> Result<Vec<AtmView>, PersistenceError> {
+        match self {
+            AtmViews::Postgres(pool) => {
+                let rows = sqlx::query(SELECT_ATM_VIEWS)
+                    .fetch_all(pool)
+                    .await
+                    .map_err(sql_error)?;
+                let mut views = Vec::with_capacity(rows.len());
+                for row in rows {
+                    views.push(serde_json::from_value(row.get("payload"))?);
+                }
+                Ok(views)
+            }
+            AtmViews::Memory(repo) => repo.load_all(),
+        }
+    }
+}
+
+pub fn sql_error(err: sqlx::Error) -> PersistenceError {
+    PersistenceError::UnknownError(Box::new(err))
+}
+
+// The time that an event was committed, taken from its metadata. Every event is committed with
+// a time, those stored before this was so fall back to the Unix epoch rather than the current
+// time, so that each replay of an event projects the same time.
+pub fn event_time(event: &EventEnvelope<BankAccount>) -> DateTime<Utc> {
+    recorded_time(&event.metadata).unwrap_or(DateTime::UNIX_EPOCH)
+}
+
+#[cfg(test)]
+mod queries_tests {
+    use std::collections::HashMap;
+
+    use chrono::DateTime;
+    use cqrs_es::{EventEnvelope, View};
+
+    use crate::command_extractor::with_time;
+    use crate::domain::events::BankAccountEvent;
+    use crate::ledger::LedgerEntryType;
+    use crate::queries::{event_time, BankAccountView, RECENT_LEDGER_ENTRIES};
+
+    #[test]
+    fn test_recent_ledger_entries() {
+        let mut view = BankAccountView::default();
+        for sequence in 1..=RECENT_LEDGER_ENTRIES + 5 {
+            view.update(&EventEnvelope {
+                aggregate_id: "ACCT-1".to_string(),
+                sequence,
+                payload: BankAccountEvent::CustomerDepositedMoney {
+                    amount: 10.0,
+                    balance: 10.0 * sequence as f64,
+                },
+                metadata: HashMap::default(),
+            });
+        }
+        assert_eq!(RECENT_LEDGER_ENTRIES, view.ledger.len());
+        assert_eq!(6, view.ledger[0].sequence);
+        assert_eq!(RECENT_LEDGER_ENTRIES + 5, view.version);
+        assert_eq!(250.0, view.balance);
+    }
+
+    #[test]
+    fn test_event_applied_again() {
+        let mut view = BankAccountView::default();
+        let event = EventEnvelope {
+            aggregate_id: "ACCT-1".to_string(),
+            sequence: 2,
+            payload: BankAccountEvent::CustomerDepositedMoney {
+                amount: 10.0,
+                balance: 10.0,
+            },
+            metadata: HashMap::default(),
+        };
+        view.update(&event);
+        view.update(&event);
+        assert_eq!(1, view.ledger.len());
+        assert_eq!(2, view.version);
+    }
+
+    #[test]
+    fn test_event_time() {
+        let mut event = EventEnvelope {
+            aggregate_id: "ACCT-1".to_string(),
+            sequence: 1,
+            payload: BankAccountEvent::CustomerDepositedMoney {
+                amount: 10.0,
+                balance: 10.0,
+            },
+            metadata: HashMap::default(),
+        };
+        // An event stored without a time is given the same time whenever it is replayed.
+        assert_eq!(DateTime::UNIX_EPOCH, event_time(&event));
+        event.metadata = with_time(event.metadata);
+        let time = event_time(&event);
+        assert_ne!(DateTime::UNIX_EPOCH, time);
+        assert_eq!(time, event_time(&event));
+        let recorded = "2022-03-01T10:15:00+00:00";
+        event.metadata = with_time(HashMap::from([("time".to_string(), recorded.to_string())]));
+        assert_eq!(recorded, event_time(&event).to_rfc3339());
+    }
+
+    #[test]
+    fn test_stored_ledger_entries() {
+        let view: BankAccountView = serde_json::from_str(
+            r#"{"account_id": "ACCT-1", "balance": 143.72, "written_checks": ["1170"],
+                "ledger": [{"description": "deposit", "amount": 200.0},
+                           {"description": "1170", "amount": 56.28}]}"#,
+        )
+        .unwrap();
+        assert_eq!(LedgerEntryType::Deposit, view.ledger[0].entry_type);
+        assert_eq!(LedgerEntryType::Check, view.ledger[1].entry_type);
+        assert_eq!(0, view.ledger[1].sequence);
+        assert_eq!(0.0, view.ledger[1].balance);
+    }
+}
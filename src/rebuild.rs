@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use cqrs_es::persist::{PersistedEventRepository, PersistenceError};
+use cqrs_es::{Aggregate, EventEnvelope, View};
+use postgres_es::{PostgresEventRepository, PostgresViewRepository};
+use serde::Serialize;
+use sqlx::{Pool, Postgres, Row};
+use utoipa::ToSchema;
+
+use crate::account_summary::AccountSummaryQuery;
+use crate::async_projections::RUNNER_LOCK_KEY;
+use crate::dead_letter::FallibleQuery;
+use crate::domain::aggregate::BankAccount;
+use crate::ledger_entries::LedgerEntriesQuery;
+use crate::queries::{sql_error, AccountQuery, BankAccountView};
+
+const SHADOW_TABLE: &str = "account_query_rebuild";
+
+const DROP_SHADOW_TABLE: &str = "DROP TABLE IF EXISTS account_query_rebuild";
+
+const CREATE_SHADOW_TABLE: &str =
+    "CREATE TABLE account_query_rebuild (LIKE account_query INCLUDING ALL)";
+
+const COUNT_EVENTS: &str = "SELECT count(*) AS count FROM events WHERE aggregate_type = $1";
+
+// Blocks new events from being committed while still allowing them to be read.
+const LOCK_EVENTS: &str = "LOCK TABLE events IN EXCLUSIVE MODE";
+
+// Pauses the async projections, waiting for any batch that they are applying.
+const LOCK_ASYNC_PROJECTIONS: &str = "SELECT pg_advisory_xact_lock($1)";
+
+const SELECT_SHADOW_VIEW: &str = "SELECT payload FROM account_query_rebuild WHERE view_id = $1";
+
+const UPSERT_SHADOW_VIEW: &str = "
+INSERT INTO account_query_rebuild (view_id, version, payload)
+VALUES ($1, 1, $2)
+ON CONFLICT (view_id)
+  DO UPDATE SET version = account_query_rebuild.version + 1, payload = $2";
+
+const SELECT_LAST_SEQUENCES: &str = "
+SELECT aggregate_id, max(sequence) AS sequence
+  FROM events
+  WHERE aggregate_type = $1
+  GROUP BY aggregate_id";
+
+const SWAP_TABLES: [&str; 3] = [
+    "ALTER TABLE account_query RENAME TO account_query_old",
+    "ALTER TABLE account_query_rebuild RENAME TO account_query",
+    "DROP TABLE account_query_old",
+];
+
+const PROGRESS_INTERVAL: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, ToSchema)]
+pub enum RebuildStatus {
+    #[default]
+    Idle,
+    Replaying,
+    CatchingUp,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct RebuildProgress {
+    status: RebuildStatus,
+    total_events: i64,
+    replayed_events: u64,
+    failed_events: u64,
+    started_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+    error: Option<String>,
+}
+
+// Regenerates the account projections by replaying every committed event, this is needed
+// whenever `BankAccountView::update` changes or if `account_query` becomes corrupted.
+//
+// The views are rebuilt into a shadow table while the application continues to run.
+// Once every event has been replayed, new events and the async projections are briefly blocked
+// while those committed during the replay are applied to the shadow table, which is then swapped
+// in within the same transaction. A view ignores any event at or below its last sequence, so a
+// live projection applying one of these events again leaves the view unchanged. The ledger
+// entries and account summaries are keyed on sequence, so these are updated in place during the
+// replay and by the live projections during the catch-up. If any event fails to replay the
+// rebuild fails and the current views are kept.
+//
+// The reports are not rebuilt, each event is counted in them only once so a replayed event
+// leaves them unchanged.
+#[derive(Clone)]
+pub struct ProjectionRebuilder {
+    pool: Pool<Postgres>,
+    progress: Arc<Mutex<RebuildProgress>>,
+}
+
+impl ProjectionRebuilder {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        Self {
+            pool,
+            progress: Arc::default(),
+        }
+    }
+
+    pub fn progress(&self) -> RebuildProgress {
+        self.progress.lock().unwrap().clone()
+    }
+
+    // Starts a rebuild in a background task, returns false if one is already running.
+    pub fn start(&self) -> bool {
+        if !self.begin() {
+            return false;
+        }
+        let rebuilder = self.clone();
+        tokio::spawn(async move {
+            let result = rebuilder.rebuild().await;
+            rebuilder.finish(result);
+        });
+        true
+    }
+
+    // Runs a rebuild to completion.
+    pub async fn run(&self) -> Result<RebuildProgress, String> {
+        if !self.begin() {
+            return Err("a rebuild is already running".to_string());
+        }
+        let result = self.rebuild().await;
+        self.finish(result);
+        let progress = self.progress();
+        match &progress.error {
+            None => Ok(progress),
+            Some(err) => Err(err.clone()),
+        }
+    }
+
+    fn begin(&self) -> bool {
+        let mut progress = self.progress.lock().unwrap();
+        if matches!(
+            progress.status,
+            RebuildStatus::Replaying | RebuildStatus::CatchingUp
+        ) {
+            return false;
+        }
+        *progress = RebuildProgress {
+            status: RebuildStatus::Replaying,
+            started_at: Some(Utc::now()),
+            ..RebuildProgress::default()
+        };
+        true
+    }
+
+    fn finish(&self, result: Result<(), PersistenceError>) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.finished_at = Some(Utc::now());
+        match result {
+            Ok(_) => progress.status = RebuildStatus::Completed,
+            Err(err) => {
+                println!("Error: projection rebuild failed: {}\n", err);
+                progress.status = RebuildStatus::Failed;
+                progress.error = Some(err.to_string());
+            }
+        }
+    }
+
+    async fn rebuild(&self) -> Result<(), PersistenceError> {
+        let result = self.replace_views().await;
+        if result.is_err() {
+            // Any rebuilt views are discarded, those in use are left unchanged.
+            if let Err(err) = sqlx::query(DROP_SHADOW_TABLE).execute(&self.pool).await {
+                println!("Error: unable to drop {}: {}\n", SHADOW_TABLE, err);
+            }
+        }
+        result
+    }
+
+    async fn replace_views(&self) -> Result<(), PersistenceError> {
+        sqlx::query(DROP_SHADOW_TABLE)
+            .execute(&self.pool)
+            .await
+            .map_err(sql_error)?;
+        sqlx::query(CREATE_SHADOW_TABLE)
+            .execute(&self.pool)
+            .await
+            .map_err(sql_error)?;
+        let total_events: i64 = sqlx::query(COUNT_EVENTS)
+            .bind(BankAccount::aggregate_type())
+            .fetch_one(&self.pool)
+            .await
+            .map_err(sql_error)?
+            .get("count");
+        self.progress.lock().unwrap().total_events = total_events;
+
+        let queries = self.queries();
+        let repo = PostgresEventRepository::new(self.pool.clone());
+        let mut last_sequences: HashMap<String, usize> = HashMap::new();
+        let mut stream = repo.stream_all_events::<BankAccount>().await?;
+        while let Some(event) = stream.next::<BankAccount>().await {
+            let event = event?;
+            last_sequences.insert(event.aggregate_id.clone(), event.sequence);
+            self.apply(&queries, &event).await;
+        }
+        let failed_events = self.progress.lock().unwrap().failed_events;
+        if failed_events > 0 {
+            let err = format!("{} events failed to replay", failed_events);
+            return Err(PersistenceError::UnknownError(err.into()));
+        }
+
+        // Apply any events committed during the replay, then swap in the rebuilt views.
+        self.progress.lock().unwrap().status = RebuildStatus::CatchingUp;
+        let mut tx = self.pool.begin().await.map_err(sql_error)?;
+        sqlx::query(LOCK_ASYNC_PROJECTIONS)
+            .bind(RUNNER_LOCK_KEY)
+            .execute(&mut *tx)
+            .await
+            .map_err(sql_error)?;
+        sqlx::query(LOCK_EVENTS)
+            .execute(&mut *tx)
+            .await
+            .map_err(sql_error)?;
+        let rows = sqlx::query(SELECT_LAST_SEQUENCES)
+            .bind(BankAccount::aggregate_type())
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(sql_error)?;
+        for row in rows {
+            let aggregate_id: String = row.get("aggregate_id");
+            let sequence: i64 = row.get("sequence");
+            let replayed = last_sequences.get(&aggregate_id).copied().unwrap_or(0);
+            if sequence as usize <= replayed {
+                continue;
+            }
+            let mut view: BankAccountView = match sqlx::query(SELECT_SHADOW_VIEW)
+                .bind(&aggregate_id)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(sql_error)?
+            {
+                Some(row) => serde_json::from_value(row.get("payload"))?,
+                None => BankAccountView::default(),
+            };
+            for event in repo
+                .get_last_events::<BankAccount>(&aggregate_id, replayed)
+                .await?
+            {
+                view.update(&EventEnvelope::try_from(event)?);
+                self.record_progress(false);
+            }
+            sqlx::query(UPSERT_SHADOW_VIEW)
+                .bind(&aggregate_id)
+                .bind(serde_json::to_value(&view)?)
+                .execute(&mut *tx)
+                .await
+                .map_err(sql_error)?;
+        }
+        for statement in SWAP_TABLES {
+            sqlx::query(statement)
+                .execute(&mut *tx)
+                .await
+                .map_err(sql_error)?;
+        }
+        tx.commit().await.map_err(sql_error)
+    }
+
+    // The shadow view and the projections that are updated in place.
+    fn queries(&self) -> Vec<Box<dyn FallibleQuery<BankAccount>>> {
+        let shadow_view_repo =
+            Arc::new(PostgresViewRepository::new(SHADOW_TABLE, self.pool.clone()));
+        vec![
+            Box::new(AccountQuery::new(shadow_view_repo)),
+            Box::new(LedgerEntriesQuery::new(self.pool.clone())),
+            Box::new(AccountSummaryQuery::new(self.pool.clone())),
+        ]
+    }
+
+    async fn apply(
+        &self,
+        queries: &[Box<dyn FallibleQuery<BankAccount>>],
+        event: &EventEnvelope<BankAccount>,
+    ) {
+        let mut failed = false;
+        for query in queries {
+            let result = query
+                .apply(&event.aggregate_id, std::slice::from_ref(event))
+                .await;
+            if let Err(err) = result {
+                println!(
+                    "Error: rebuild failed for {}-{}: {}\n",
+                    event.aggregate_id, event.sequence, err
+                );
+                failed = true;
+            }
+        }
+        self.record_progress(failed);
+    }
+
+    fn record_progress(&self, failed: bool) {
+        let mut progress = self.progress.lock().unwrap();
+        if failed {
+            progress.failed_events += 1;
+        }
+        progress.replayed_events += 1;
+        if progress.replayed_events.is_multiple_of(PROGRESS_INTERVAL) {
+            println!(
+                "projection rebuild: {} of {} events replayed",
+                progress.replayed_events, progress.total_events
+            );
+        }
+    }
+}
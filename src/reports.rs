@@ -0,0 +1,279 @@
+use std::fmt::Write;
+
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use cqrs_es::persist::PersistenceError;
+use cqrs_es::EventEnvelope;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Row};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::dead_letter::FallibleQuery;
+use crate::domain::aggregate::BankAccount;
+use crate::domain::events::BankAccountEvent;
+use crate::queries::{event_time, sql_error};
+
+const INSERT_REPORT_ACCOUNT: &str = "
+INSERT INTO report_accounts (account_id, account_type)
+VALUES ($1, $2)
+ON CONFLICT (account_id) DO NOTHING";
+
+const SELECT_REPORT_ACCOUNT: &str =
+    "SELECT account_type FROM report_accounts WHERE account_id = $1";
+
+// Inserts nothing if the event has already been counted, the row is held until the totals are
+// added so that concurrent projections of the same event cannot both count it.
+const INSERT_REPORT_EVENT: &str = "
+INSERT INTO report_events (account_id, sequence)
+VALUES ($1, $2)
+ON CONFLICT (account_id, sequence) DO NOTHING";
+
+const ADD_DAILY_TOTALS: &str = "
+INSERT INTO daily_report (day, account_type, deposit_count, deposit_total, withdrawal_count, withdrawal_total, check_count, check_total)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+ON CONFLICT (day, account_type) DO UPDATE
+  SET deposit_count = daily_report.deposit_count + EXCLUDED.deposit_count,
+      deposit_total = daily_report.deposit_total + EXCLUDED.deposit_total,
+      withdrawal_count = daily_report.withdrawal_count + EXCLUDED.withdrawal_count,
+      withdrawal_total = daily_report.withdrawal_total + EXCLUDED.withdrawal_total,
+      check_count = daily_report.check_count + EXCLUDED.check_count,
+      check_total = daily_report.check_total + EXCLUDED.check_total";
+
+// Both dates are optional.
+const SELECT_REPORT: &str = "
+SELECT day, account_type, deposit_count, deposit_total, withdrawal_count, withdrawal_total,
+       check_count, check_total
+  FROM daily_report
+  WHERE ($1::date IS NULL OR day >= $1)
+    AND ($2::date IS NULL OR day <= $2)
+  ORDER BY day, account_type";
+
+const CSV_HEADER: &str = "day,account_type,deposit_count,deposit_total,withdrawal_count,withdrawal_total,check_count,check_total";
+
+// A reporting projection holding the totals of deposits, withdrawals and checks for each
+// day and account type. Days are in UTC.
+#[derive(Clone)]
+pub struct ReportQuery {
+    pool: Pool<Postgres>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+// The range and format of a report, these are taken from the query string, e.g.,
+// `?from=2022-03-01&to=2022-03-31&format=csv`. Dates are inclusive and in UTC.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ReportParams {
+    /// The first day of the report.
+    pub from: Option<NaiveDate>,
+    /// The last day of the report.
+    pub to: Option<NaiveDate>,
+    /// The format of the report, JSON by default.
+    #[serde(default)]
+    pub format: ReportFormat,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, ToSchema)]
+pub struct ReportTotals {
+    deposit_count: i64,
+    deposit_total: f64,
+    withdrawal_count: i64,
+    withdrawal_total: f64,
+    check_count: i64,
+    check_total: f64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReportRow {
+    day: NaiveDate,
+    account_type: String,
+    #[serde(flatten)]
+    totals: ReportTotals,
+}
+
+impl ReportQuery {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        Self { pool }
+    }
+
+    pub async fn load(&self, params: &ReportParams) -> Result<Vec<ReportRow>, sqlx::Error> {
+        let rows = sqlx::query(SELECT_REPORT)
+            .bind(params.from)
+            .bind(params.to)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| ReportRow {
+                day: row.get("day"),
+                account_type: row.get("account_type"),
+                totals: ReportTotals {
+                    deposit_count: row.get("deposit_count"),
+                    deposit_total: row.get("deposit_total"),
+                    withdrawal_count: row.get("withdrawal_count"),
+                    withdrawal_total: row.get("withdrawal_total"),
+                    check_count: row.get("check_count"),
+                    check_total: row.get("check_total"),
+                },
+            })
+            .collect())
+    }
+}
+
+// The contribution of an event to the daily totals.
+fn event_totals(event: &BankAccountEvent) -> Option<ReportTotals> {
+    match event {
+        BankAccountEvent::CustomerDepositedMoney { amount, .. } => Some(ReportTotals {
+            deposit_count: 1,
+            deposit_total: *amount,
+            ..ReportTotals::default()
+        }),
+        BankAccountEvent::CustomerWithdrewCash { amount, .. } => Some(ReportTotals {
+            withdrawal_count: 1,
+            withdrawal_total: *amount,
+            ..ReportTotals::default()
+        }),
+        BankAccountEvent::CustomerWroteCheck { amount, .. } => Some(ReportTotals {
+            check_count: 1,
+            check_total: *amount,
+            ..ReportTotals::default()
+        }),
+        _ => None,
+    }
+}
+
+pub fn to_csv(rows: &[ReportRow]) -> String {
+    let mut csv = String::new();
+    writeln!(csv, "{}", CSV_HEADER).unwrap();
+    for row in rows {
+        let totals = &row.totals;
+        writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{}",
+            row.day,
+            row.account_type,
+            totals.deposit_count,
+            totals.deposit_total,
+            totals.withdrawal_count,
+            totals.withdrawal_total,
+            totals.check_count,
+            totals.check_total
+        )
+        .unwrap();
+    }
+    csv
+}
+
+#[async_trait]
+impl FallibleQuery<BankAccount> for ReportQuery {
+    async fn apply(
+        &self,
+        aggregate_id: &str,
+        events: &[EventEnvelope<BankAccount>],
+    ) -> Result<(), PersistenceError> {
+        for event in events {
+            let sequence = event.sequence as i64;
+            if let BankAccountEvent::AccountOpened { account_type, .. } = &event.payload {
+                sqlx::query(INSERT_REPORT_ACCOUNT)
+                    .bind(aggregate_id)
+                    .bind(account_type.as_str())
+                    .execute(&self.pool)
+                    .await
+                    .map_err(sql_error)?;
+                continue;
+            }
+            let totals = match event_totals(&event.payload) {
+                Some(totals) => totals,
+                None => continue,
+            };
+            let mut tx = self.pool.begin().await.map_err(sql_error)?;
+            // Each event is recorded as counted, rather than the last counted sequence of the
+            // account, so that an event retried as a dead letter is counted after those that
+            // followed it.
+            let counted = sqlx::query(INSERT_REPORT_EVENT)
+                .bind(aggregate_id)
+                .bind(sequence)
+                .execute(&mut *tx)
+                .await
+                .map_err(sql_error)?;
+            if counted.rows_affected() == 0 {
+                continue;
+            }
+            // The account type is recorded when the account is opened, without it the event
+            // cannot be counted and is left to be retried as a dead letter.
+            let account = sqlx::query(SELECT_REPORT_ACCOUNT)
+                .bind(aggregate_id)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(sql_error)?
+                .ok_or_else(|| {
+                    PersistenceError::UnknownError(
+                        format!("account {} is missing from report_accounts", aggregate_id).into(),
+                    )
+                })?;
+            let account_type: String = account.get("account_type");
+            sqlx::query(ADD_DAILY_TOTALS)
+                .bind(event_time(event).date_naive())
+                .bind(account_type)
+                .bind(totals.deposit_count)
+                .bind(totals.deposit_total)
+                .bind(totals.withdrawal_count)
+                .bind(totals.withdrawal_total)
+                .bind(totals.check_count)
+                .bind(totals.check_total)
+                .execute(&mut *tx)
+                .await
+                .map_err(sql_error)?;
+            tx.commit().await.map_err(sql_error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod reports_tests {
+    use chrono::NaiveDate;
+
+    use crate::domain::events::BankAccountEvent;
+    use crate::reports::{event_totals, to_csv, ReportRow, ReportTotals};
+
+    #[test]
+    fn test_event_totals() {
+        let check = BankAccountEvent::CustomerWroteCheck {
+            check_number: "1170".to_string(),
+            amount: 25.0,
+            balance: 35.0,
+        };
+        let expected = ReportTotals {
+            check_count: 1,
+            check_total: 25.0,
+            ..ReportTotals::default()
+        };
+        assert_eq!(Some(expected), event_totals(&check));
+        assert_eq!(None, event_totals(&BankAccountEvent::AccountClosed));
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let rows = vec![ReportRow {
+            day: NaiveDate::from_ymd_opt(2022, 3, 1).unwrap(),
+            account_type: "Savings".to_string(),
+            totals: ReportTotals {
+                deposit_count: 2,
+                deposit_total: 300.5,
+                ..ReportTotals::default()
+            },
+        }];
+        assert_eq!(
+            "day,account_type,deposit_count,deposit_total,withdrawal_count,withdrawal_total,check_count,check_total\n\
+             2022-03-01,Savings,2,300.5,0,0,0,0\n",
+            to_csv(&rows)
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use cqrs_es::persist::PersistenceError;
+use cqrs_es::EventEnvelope;
+use serde::Serialize;
+use sqlx::{Pool, Postgres, Row};
+use utoipa::ToSchema;
+
+use crate::dead_letter::FallibleQuery;
+use crate::domain::aggregate::BankAccount;
+use crate::domain::events::BankAccountEvent;
+use crate::queries::{event_time, sql_error};
+
+const INSERT_FLAGGED_TRANSACTION: &str = "
+INSERT INTO review_queue (account_id, sequence, transaction, amount, reason, flagged_at)
+VALUES ($1, $2, $3, $4, $5, $6)
+ON CONFLICT (account_id, sequence) DO NOTHING";
+
+const SELECT_FLAGGED_TRANSACTIONS: &str = "
+SELECT account_id, sequence, transaction, amount, reason, flagged_at
+  FROM review_queue
+  ORDER BY flagged_at, account_id, sequence";
+
+// A projection of every transaction that has been flagged for review by the fraud and
+// anti-money laundering screening, across all accounts.
+#[derive(Clone)]
+pub struct ReviewQueue {
+    pool: Pool<Postgres>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FlaggedTransaction {
+    account_id: String,
+    sequence: i64,
+    transaction: String,
+    amount: f64,
+    reason: String,
+    flagged_at: DateTime<Utc>,
+}
+
+impl ReviewQueue {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        Self { pool }
+    }
+
+    pub async fn load(&self) -> Result<Vec<FlaggedTransaction>, sqlx::Error> {
+        let rows = sqlx::query(SELECT_FLAGGED_TRANSACTIONS)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| FlaggedTransaction {
+                account_id: row.get("account_id"),
+                sequence: row.get("sequence"),
+                transaction: row.get("transaction"),
+                amount: row.get("amount"),
+                reason: row.get("reason"),
+                flagged_at: row.get("flagged_at"),
+            })
+            .collect())
+    }
+}
+
+// Rows are keyed on the account and sequence of the `TransactionFlagged` event,
+// so replaying events will not add duplicates to the queue.
+#[async_trait]
+impl FallibleQuery<BankAccount> for ReviewQueue {
+    async fn apply(
+        &self,
+        aggregate_id: &str,
+        events: &[EventEnvelope<BankAccount>],
+    ) -> Result<(), PersistenceError> {
+        for event in events {
+            if let BankAccountEvent::TransactionFlagged {
+                transaction,
+                amount,
+                reason,
+            } = &event.payload
+            {
+                sqlx::query(INSERT_FLAGGED_TRANSACTION)
+                    .bind(aggregate_id)
+                    .bind(event.sequence as i64)
+                    .bind(transaction)
+                    .bind(amount)
+                    .bind(reason)
+                    .bind(event_time(event))
+                    .execute(&self.pool)
+                    .await
+                    .map_err(sql_error)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,18 +1,109 @@
-use crate::command_extractor::CommandExtractor;
+use crate::account_resources::{NewAccount, NewCheck, NewDeposit, NewWithdrawal};
+use crate::account_summary::AccountFilter;
+use crate::admin_extractor::AdminExtractor;
+use crate::command_extractor::{command_metadata, CommandExtractor};
+use crate::consistency::{committed_version, load_min_version, VersionedView, VERSION_HDR};
+use crate::domain::aggregate::ACCOUNT_ALREADY_OPEN;
+use crate::domain::atm::commands::AtmCommand;
+use crate::domain::commands::{BankAccountCommand, CommandEnvelope};
+use crate::event_log::{load_account_events, EventRange};
+use crate::ledger::LedgerFilter;
+use crate::ledger_export::{load_ledger_export, ExportParams};
+use crate::openapi::{swagger_ui_file, ApiDoc};
+use crate::point_in_time::{account_view_as_of, AsOf};
+use crate::reports::{to_csv, ReportFormat, ReportParams};
 use crate::state::ApplicationState;
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
-use axum::response::{IntoResponse, Response};
+use crate::storage_extractor::PostgresExtractor;
+use crate::webhooks::NewWebhook;
+use async_graphql::http::{
+    GraphiQLSource, WebSocket, WebSocketProtocols, WsMessage, ALL_WEBSOCKET_PROTOCOLS,
+};
+use async_graphql::Data;
+use axum::extract::ws::{CloseFrame, Message, WebSocketUpgrade};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode, Uri};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{Html, IntoResponse, Redirect, Response};
 use axum::Json;
-use cqrs_es::persist::ViewRepository;
+use cqrs_es::AggregateError;
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::future::ready;
+use utoipa::{IntoParams, OpenApi};
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ViewParams {
+    /// Reconstructs the view as of a sequence, an RFC 3339 timestamp or a date.
+    as_of: Option<String>,
+    /// Waits briefly for the view to include the event with this sequence, e.g., the
+    /// `X-Aggregate-Version` returned by a command.
+    min_version: Option<usize>,
+}
 
 // Serves as our query endpoint to respond with the materialized `BankAccountView`
 // for the requested account.
+//
+// With an `as_of` parameter the view is instead reconstructed from the event store
+// as it was at that point in time.
+//
+// The version of the view is returned in an `X-Aggregate-Version` header, if the view has not
+// reached the requested `min_version` in time a `503 Service Unavailable` is returned instead.
+#[utoipa::path(
+    get,
+    path = "/account/{account_id}",
+    tag = "accounts",
+    params(("account_id" = String, Path, description = "The account id"), ViewParams),
+    responses(
+        (status = 200, description = "The view of the account", body = BankAccountView, headers(("X-Aggregate-Version" = usize, description = "The version of the view"))),
+        (status = 400, description = "The `as_of` parameter could not be read", body = String, content_type = "text/plain"),
+        (status = 404, description = "The account was not found"),
+        (status = 503, description = "The view has not reached `min_version`", body = String, content_type = "text/plain"),
+        (status = 500, description = "The request failed", body = String, content_type = "text/plain")
+    )
+)]
 pub async fn query_handler(
     Path(account_id): Path<String>,
+    Query(params): Query<ViewParams>,
     State(state): State<ApplicationState>,
 ) -> Response {
-    let view = match state.account_query.load(&account_id).await {
+    let view = match params.as_of {
+        None => match load_min_version(
+            state.account_query.as_ref(),
+            &account_id,
+            params.min_version,
+        )
+        .await
+        {
+            Ok(VersionedView::Current(view)) => Ok(view),
+            Ok(VersionedView::Stale(view)) => {
+                let version = view.map_or(0, |view| view.version);
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    [
+                        (header::RETRY_AFTER.as_str(), "1".to_string()),
+                        (VERSION_HDR, version.to_string()),
+                    ],
+                    format!(
+                        "view is stale, version {} of {} requested",
+                        version,
+                        params.min_version.unwrap_or_default()
+                    ),
+                )
+                    .into_response();
+            }
+            Err(err) => Err(err),
+        },
+        Some(as_of) => {
+            let as_of = match AsOf::parse(&as_of) {
+                Ok(as_of) => as_of,
+                Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+            };
+            account_view_as_of(state.account_events.as_ref(), &account_id, as_of).await
+        }
+    };
+    let view = match view {
         Ok(view) => view,
         Err(err) => {
             println!("Error: {:#?}\n", err);
@@ -21,25 +112,903 @@ pub async fn query_handler(
     };
     match view {
         None => StatusCode::NOT_FOUND.into_response(),
-        Some(account_view) => (StatusCode::OK, Json(account_view)).into_response(),
+        Some(account_view) => (
+            StatusCode::OK,
+            [(VERSION_HDR, account_view.version.to_string())],
+            Json(account_view),
+        )
+            .into_response(),
+    }
+}
+
+// Lists the accounts matching the requested filters, sorted and paged.
+#[utoipa::path(
+    get,
+    path = "/accounts",
+    tag = "accounts",
+    params(AccountFilter),
+    responses(
+        (status = 200, description = "A page of the matching accounts", body = AccountPage),
+        (status = 500, description = "The request failed", body = String, content_type = "text/plain"),
+        (status = 501, description = "The route requires Postgres storage", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn accounts_handler(
+    _postgres: PostgresExtractor,
+    Query(filter): Query<AccountFilter>,
+    State(state): State<ApplicationState>,
+) -> Response {
+    match state.account_summary.load(&filter).await {
+        Ok(account_page) => (StatusCode::OK, Json(account_page)).into_response(),
+        Err(err) => {
+            println!("Error: {:#?}\n", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
+
+// Serves a page of the ledger for the requested account, the entries may be filtered
+// by type, amount and date.
+#[utoipa::path(
+    get,
+    path = "/account/{account_id}/ledger",
+    tag = "accounts",
+    params(("account_id" = String, Path, description = "The account id"), LedgerFilter),
+    responses(
+        (status = 200, description = "A page of the ledger of the account", body = LedgerPage),
+        (status = 404, description = "The account was not found"),
+        (status = 500, description = "The request failed", body = String, content_type = "text/plain"),
+        (status = 501, description = "The route requires Postgres storage", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn ledger_handler(
+    _postgres: PostgresExtractor,
+    Path(account_id): Path<String>,
+    Query(filter): Query<LedgerFilter>,
+    State(state): State<ApplicationState>,
+) -> Response {
+    match state.ledger.load(&account_id, &filter).await {
+        Ok(Some(ledger_page)) => (StatusCode::OK, Json(ledger_page)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => {
+            println!("Error: {:#?}\n", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
+
+// Serves the totals of deposits, withdrawals and checks for each day and account type.
+#[utoipa::path(
+    get,
+    path = "/reports/daily",
+    tag = "reports",
+    params(ReportParams),
+    responses(
+        (status = 200, description = "The totals of each day and account type", body = [ReportRow], content_type = ["application/json", "text/csv"]),
+        (status = 500, description = "The request failed", body = String, content_type = "text/plain"),
+        (status = 501, description = "The route requires Postgres storage", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn daily_report_handler(
+    _postgres: PostgresExtractor,
+    Query(params): Query<ReportParams>,
+    State(state): State<ApplicationState>,
+) -> Response {
+    match state.reports.load(&params).await {
+        Ok(rows) => match params.format {
+            ReportFormat::Json => (StatusCode::OK, Json(rows)).into_response(),
+            ReportFormat::Csv => (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "text/csv")],
+                to_csv(&rows),
+            )
+                .into_response(),
+        },
+        Err(err) => {
+            println!("Error: {:#?}\n", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StreamParams {
+    /// Resumes the stream with the events following this sequence.
+    pub after: Option<usize>,
+}
+
+// Pushes each new event of an account along with its updated view, as Server-Sent Events or,
+// if the connection is upgraded, as WebSocket text messages. An SSE client that reconnects
+// resumes from its `Last-Event-ID`, which is the sequence of the last event it received.
+#[utoipa::path(
+    get,
+    path = "/account/{account_id}/stream",
+    tag = "accounts",
+    params(("account_id" = String, Path, description = "The account id"), StreamParams, ("Last-Event-ID" = Option<usize>, Header, description = "Resumes the stream with the events following this sequence")),
+    responses(
+        (status = 200, description = "Each new event of the account with its updated view, as Server-Sent Events or WebSocket messages", content_type = "text/event-stream"),
+        (status = 500, description = "The request failed", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn account_stream_handler(
+    Path(account_id): Path<String>,
+    Query(params): Query<StreamParams>,
+    headers: HeaderMap,
+    ws: Option<WebSocketUpgrade>,
+    State(state): State<ApplicationState>,
+) -> Response {
+    let last_event_id = headers
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+    let after = params.after.or(last_event_id);
+    let updates = match state
+        .account_stream
+        .subscribe(state.account_events.clone(), account_id, after)
+        .await
+    {
+        Ok(updates) => updates,
+        Err(err) => {
+            println!("Error: {:#?}\n", err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+        }
+    };
+    match ws {
+        Some(ws) => ws.on_upgrade(|mut socket| async move {
+            let mut updates = Box::pin(updates);
+            while let Some(update) = updates.next().await {
+                let update = match serde_json::to_string(&update) {
+                    Ok(update) => update,
+                    Err(err) => {
+                        println!("Error: {:#?}\n", err);
+                        break;
+                    }
+                };
+                if socket.send(Message::Text(update)).await.is_err() {
+                    break;
+                }
+            }
+        }),
+        None => {
+            let events = updates.map(|update| {
+                Event::default()
+                    .id(update.sequence.to_string())
+                    .json_data(&update)
+            });
+            Sse::new(events)
+                .keep_alive(KeepAlive::default())
+                .into_response()
+        }
+    }
+}
+
+// Downloads the ledger of an account as JSON, CSV or OFX for use in budgeting tools.
+#[utoipa::path(
+    get,
+    path = "/account/{account_id}/export",
+    tag = "accounts",
+    params(("account_id" = String, Path, description = "The account id"), ExportParams),
+    responses(
+        (status = 200, description = "The ledger of the account as a file", body = String, content_type = ["application/json", "text/csv", "application/x-ofx"]),
+        (status = 404, description = "The account was not found"),
+        (status = 500, description = "The request failed", body = String, content_type = "text/plain"),
+        (status = 501, description = "The route requires Postgres storage", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn export_handler(
+    _postgres: PostgresExtractor,
+    Path(account_id): Path<String>,
+    Query(params): Query<ExportParams>,
+    State(state): State<ApplicationState>,
+) -> Response {
+    match load_ledger_export(&state.pool, &account_id, &params).await {
+        Ok(Some(export)) => {
+            // Only safe characters of the account id are used in the file name.
+            let file_name: String = account_id
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+                .collect();
+            let disposition = format!(
+                "attachment; filename=\"{}-ledger.{}\"",
+                file_name,
+                params.format.extension()
+            );
+            (
+                StatusCode::OK,
+                [
+                    (
+                        header::CONTENT_TYPE,
+                        params.format.content_type().to_string(),
+                    ),
+                    (header::CONTENT_DISPOSITION, disposition),
+                ],
+                export.render(params.format),
+            )
+                .into_response()
+        }
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => {
+            println!("Error: {:#?}\n", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
+
+// Serves the events committed for the requested account, this is restricted to the admin role.
+#[utoipa::path(
+    get,
+    path = "/account/{account_id}/events",
+    tag = "admin",
+    params(("account_id" = String, Path, description = "The account id"), EventRange),
+    security(("admin_api_key" = [])),
+    responses(
+        (status = 200, description = "The committed events of the account", body = [StoredEvent]),
+        (status = 403, description = "The admin api key is missing or invalid", body = String, content_type = "text/plain"),
+        (status = 500, description = "The request failed", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn events_handler(
+    _admin: AdminExtractor,
+    Path(account_id): Path<String>,
+    Query(range): Query<EventRange>,
+    State(state): State<ApplicationState>,
+) -> Response {
+    match load_account_events(&state.account_events, &account_id, &range).await {
+        Ok(events) => (StatusCode::OK, Json(events)).into_response(),
+        Err(err) => {
+            println!("Error: {:#?}\n", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
+
+// Starts a rebuild of the account projections, this is restricted to the admin role.
+#[utoipa::path(
+    post,
+    path = "/admin/rebuild",
+    tag = "admin",
+    security(("admin_api_key" = [])),
+    responses(
+        (status = 202, description = "The rebuild was started", body = RebuildProgress),
+        (status = 409, description = "A rebuild is already running", body = String, content_type = "text/plain"),
+        (status = 403, description = "The admin api key is missing or invalid", body = String, content_type = "text/plain"),
+        (status = 501, description = "The route requires Postgres storage", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn rebuild_handler(
+    _admin: AdminExtractor,
+    _postgres: PostgresExtractor,
+    State(state): State<ApplicationState>,
+) -> Response {
+    if state.rebuilder.start() {
+        (StatusCode::ACCEPTED, Json(state.rebuilder.progress())).into_response()
+    } else {
+        (
+            StatusCode::CONFLICT,
+            "a rebuild is already running".to_string(),
+        )
+            .into_response()
+    }
+}
+
+// Serves the progress of the current or last rebuild, this is restricted to the admin role.
+#[utoipa::path(
+    get,
+    path = "/admin/rebuild",
+    tag = "admin",
+    security(("admin_api_key" = [])),
+    responses(
+        (status = 200, description = "The progress of the current or last rebuild", body = RebuildProgress),
+        (status = 403, description = "The admin api key is missing or invalid", body = String, content_type = "text/plain"),
+        (status = 501, description = "The route requires Postgres storage", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn rebuild_progress_handler(
+    _admin: AdminExtractor,
+    _postgres: PostgresExtractor,
+    State(state): State<ApplicationState>,
+) -> Response {
+    (StatusCode::OK, Json(state.rebuilder.progress())).into_response()
+}
+
+// Lists the events that a projection failed to apply, this is restricted to the admin role.
+#[utoipa::path(
+    get,
+    path = "/admin/dead-letters",
+    tag = "admin",
+    security(("admin_api_key" = [])),
+    responses(
+        (status = 200, description = "The unresolved projection failures", body = [DeadLetter]),
+        (status = 403, description = "The admin api key is missing or invalid", body = String, content_type = "text/plain"),
+        (status = 500, description = "The request failed", body = String, content_type = "text/plain"),
+        (status = 501, description = "The route requires Postgres storage", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn dead_letters_handler(
+    _admin: AdminExtractor,
+    _postgres: PostgresExtractor,
+    State(state): State<ApplicationState>,
+) -> Response {
+    match state.dead_letters.load().await {
+        Ok(dead_letters) => (StatusCode::OK, Json(dead_letters)).into_response(),
+        Err(err) => {
+            println!("Error: {:#?}\n", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
+
+// Reprocesses every aggregate with a dead letter, this is restricted to the admin role.
+#[utoipa::path(
+    post,
+    path = "/admin/dead-letters/retry",
+    tag = "admin",
+    security(("admin_api_key" = [])),
+    responses(
+        (status = 200, description = "The aggregates that were reprocessed", body = RetryReport),
+        (status = 403, description = "The admin api key is missing or invalid", body = String, content_type = "text/plain"),
+        (status = 500, description = "The request failed", body = String, content_type = "text/plain"),
+        (status = 501, description = "The route requires Postgres storage", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn dead_letter_retry_handler(
+    _admin: AdminExtractor,
+    _postgres: PostgresExtractor,
+    State(state): State<ApplicationState>,
+) -> Response {
+    match state.dead_letter_retry.retry().await {
+        Ok(report) => (StatusCode::OK, Json(report)).into_response(),
+        Err(err) => {
+            println!("Error: {:#?}\n", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
+
+// Serves the application metrics in the Prometheus text format.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "metrics",
+    responses(
+        (status = 200, description = "The application metrics in the Prometheus text format", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn metrics_handler(State(state): State<ApplicationState>) -> Response {
+    (StatusCode::OK, state.metrics.render()).into_response()
+}
+
+// Serves the OpenAPI document of the REST routes.
+pub async fn openapi_handler() -> Response {
+    Json(ApiDoc::openapi()).into_response()
+}
+
+// Redirects to Swagger UI, whose assets are loaded relative to `/docs/`.
+pub async fn docs_handler() -> Redirect {
+    Redirect::to("/docs/")
+}
+
+// Serves Swagger UI for browsing and calling the REST routes.
+pub async fn swagger_ui_handler(uri: Uri) -> Response {
+    let path = uri.path().trim_start_matches("/docs/");
+    match swagger_ui_file(path) {
+        Ok(Some(file)) => (
+            [(header::CONTENT_TYPE, file.content_type)],
+            file.bytes.into_owned(),
+        )
+            .into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => {
+            println!("Error: {:#?}\n", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
+
+// Subscribes a partner endpoint to account events, this is restricted to the admin role.
+// The response includes the secret used to sign each delivery.
+#[utoipa::path(
+    post,
+    path = "/webhooks",
+    tag = "webhooks",
+    request_body = NewWebhook,
+    security(("admin_api_key" = [])),
+    responses(
+        (status = 201, description = "The subscription along with its signing secret", body = WebhookSubscription),
+        (status = 400, description = "The url is not http or https", body = String, content_type = "text/plain"),
+        (status = 403, description = "The admin api key is missing or invalid", body = String, content_type = "text/plain"),
+        (status = 500, description = "The request failed", body = String, content_type = "text/plain"),
+        (status = 501, description = "The route requires Postgres storage", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn create_webhook_handler(
+    _admin: AdminExtractor,
+    _postgres: PostgresExtractor,
+    State(state): State<ApplicationState>,
+    Json(webhook): Json<NewWebhook>,
+) -> Response {
+    if !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://") {
+        return (
+            StatusCode::BAD_REQUEST,
+            "url must be http or https".to_string(),
+        )
+            .into_response();
+    }
+    match state.webhooks.create(webhook).await {
+        Ok(subscription) => (StatusCode::CREATED, Json(subscription)).into_response(),
+        Err(err) => {
+            println!("Error: {:#?}\n", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
+
+// Lists the webhook subscriptions, this is restricted to the admin role.
+#[utoipa::path(
+    get,
+    path = "/webhooks",
+    tag = "webhooks",
+    security(("admin_api_key" = [])),
+    responses(
+        (status = 200, description = "The webhook subscriptions", body = [WebhookSubscription]),
+        (status = 403, description = "The admin api key is missing or invalid", body = String, content_type = "text/plain"),
+        (status = 500, description = "The request failed", body = String, content_type = "text/plain"),
+        (status = 501, description = "The route requires Postgres storage", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn webhooks_handler(
+    _admin: AdminExtractor,
+    _postgres: PostgresExtractor,
+    State(state): State<ApplicationState>,
+) -> Response {
+    match state.webhooks.load().await {
+        Ok(subscriptions) => (StatusCode::OK, Json(subscriptions)).into_response(),
+        Err(err) => {
+            println!("Error: {:#?}\n", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
+
+// Removes a webhook subscription along with its deliveries, this is restricted to the admin role.
+#[utoipa::path(
+    delete,
+    path = "/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = i64, Path, description = "The subscription id")),
+    security(("admin_api_key" = [])),
+    responses(
+        (status = 204, description = "The subscription was removed"),
+        (status = 404, description = "The subscription was not found"),
+        (status = 403, description = "The admin api key is missing or invalid", body = String, content_type = "text/plain"),
+        (status = 500, description = "The request failed", body = String, content_type = "text/plain"),
+        (status = 501, description = "The route requires Postgres storage", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn delete_webhook_handler(
+    _admin: AdminExtractor,
+    _postgres: PostgresExtractor,
+    Path(id): Path<i64>,
+    State(state): State<ApplicationState>,
+) -> Response {
+    match state.webhooks.delete(id).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => {
+            println!("Error: {:#?}\n", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
+
+// Serves the delivery log of a webhook subscription, this is restricted to the admin role.
+#[utoipa::path(
+    get,
+    path = "/webhooks/{id}/deliveries",
+    tag = "webhooks",
+    params(("id" = i64, Path, description = "The subscription id")),
+    security(("admin_api_key" = [])),
+    responses(
+        (status = 200, description = "The delivery log of the subscription", body = [WebhookDelivery]),
+        (status = 403, description = "The admin api key is missing or invalid", body = String, content_type = "text/plain"),
+        (status = 500, description = "The request failed", body = String, content_type = "text/plain"),
+        (status = 501, description = "The route requires Postgres storage", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn webhook_deliveries_handler(
+    _admin: AdminExtractor,
+    _postgres: PostgresExtractor,
+    Path(id): Path<i64>,
+    State(state): State<ApplicationState>,
+) -> Response {
+    match state.webhooks.deliveries(id).await {
+        Ok(deliveries) => (StatusCode::OK, Json(deliveries)).into_response(),
+        Err(err) => {
+            println!("Error: {:#?}\n", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
     }
 }
 
-// Serves as our command endpoint to make changes in a `BankAccount` aggregate.
+// Serves as our command endpoint to make changes in a `BankAccount` aggregate, the resulting
+// version of the account is returned in an `X-Aggregate-Version` header.
+#[utoipa::path(
+    post,
+    path = "/account/{account_id}",
+    tag = "accounts",
+    params(("account_id" = String, Path, description = "The account id")),
+    request_body = BankAccountCommand,
+    responses(
+        (status = 204, description = "The command was executed", headers(("X-Aggregate-Version" = usize, description = "The resulting version of the account"))),
+        (status = 400, description = "The command was rejected", body = String, content_type = "text/plain"),
+        (status = 409, description = "The account is already open", body = String, content_type = "text/plain")
+    )
+)]
 pub async fn command_handler(
     Path(account_id): Path<String>,
     State(state): State<ApplicationState>,
     CommandExtractor(metadata, command): CommandExtractor,
 ) -> Response {
-    match state
+    match execute_command(&state, &account_id, metadata, command).await {
+        Ok(version) => versioned_response(StatusCode::NO_CONTENT, version),
+        Err(response) => response,
+    }
+}
+
+// Opens an account, as `POST /account/:account_id` with an `OpenAccount` command. The new
+// account is found at the returned `Location`.
+#[utoipa::path(
+    post,
+    path = "/accounts",
+    tag = "accounts",
+    request_body = NewAccount,
+    responses(
+        (status = 201, description = "The account was opened", headers(("Location" = String, description = "The path of the new account"), ("X-Aggregate-Version" = usize, description = "The resulting version of the account"))),
+        (status = 400, description = "The command was rejected", body = String, content_type = "text/plain"),
+        (status = 409, description = "The account is already open", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn open_account_handler(
+    State(state): State<ApplicationState>,
+    CommandExtractor(metadata, account): CommandExtractor<NewAccount>,
+) -> Response {
+    let location = HeaderValue::from_str(&account.location())
+        .expect("a percent-encoded location is a valid header value");
+    let account_id = account.account_id().to_string();
+    match execute_command(&state, &account_id, metadata, account.into()).await {
+        Ok(version) => {
+            let mut response = versioned_response(StatusCode::CREATED, version);
+            response.headers_mut().insert(header::LOCATION, location);
+            response
+        }
+        Err(response) => response,
+    }
+}
+
+// Closes an account, as `POST /account/:account_id` with a `CloseAccount` command.
+#[utoipa::path(
+    delete,
+    path = "/account/{account_id}",
+    tag = "accounts",
+    params(("account_id" = String, Path, description = "The account id")),
+    responses(
+        (status = 204, description = "The account was closed", headers(("X-Aggregate-Version" = usize, description = "The resulting version of the account"))),
+        (status = 400, description = "The command was rejected", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn close_account_handler(
+    Path(account_id): Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
+    State(state): State<ApplicationState>,
+) -> Response {
+    let metadata = command_metadata(&uri.to_string(), &headers);
+    let command = BankAccountCommand::CloseAccount;
+    match execute_command(&state, &account_id, metadata, command).await {
+        Ok(version) => versioned_response(StatusCode::NO_CONTENT, version),
+        Err(response) => response,
+    }
+}
+
+// Deposits into an account, as `POST /account/:account_id` with a `DepositMoney` command.
+#[utoipa::path(
+    post,
+    path = "/account/{account_id}/deposits",
+    tag = "accounts",
+    params(("account_id" = String, Path, description = "The account id")),
+    request_body = NewDeposit,
+    responses(
+        (status = 204, description = "The money was deposited", headers(("X-Aggregate-Version" = usize, description = "The resulting version of the account"))),
+        (status = 400, description = "The command was rejected", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn deposit_handler(
+    Path(account_id): Path<String>,
+    State(state): State<ApplicationState>,
+    CommandExtractor(metadata, deposit): CommandExtractor<NewDeposit>,
+) -> Response {
+    match execute_command(&state, &account_id, metadata, deposit.into()).await {
+        Ok(version) => versioned_response(StatusCode::NO_CONTENT, version),
+        Err(response) => response,
+    }
+}
+
+// Withdraws cash from an ATM, as `POST /account/:account_id` with a `WithdrawMoney` command.
+#[utoipa::path(
+    post,
+    path = "/account/{account_id}/withdrawals",
+    tag = "accounts",
+    params(("account_id" = String, Path, description = "The account id")),
+    request_body = NewWithdrawal,
+    responses(
+        (status = 204, description = "The cash was withdrawn", headers(("X-Aggregate-Version" = usize, description = "The resulting version of the account"))),
+        (status = 400, description = "The command was rejected", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn withdrawal_handler(
+    Path(account_id): Path<String>,
+    State(state): State<ApplicationState>,
+    CommandExtractor(metadata, withdrawal): CommandExtractor<NewWithdrawal>,
+) -> Response {
+    match execute_command(&state, &account_id, metadata, withdrawal.into()).await {
+        Ok(version) => versioned_response(StatusCode::NO_CONTENT, version),
+        Err(response) => response,
+    }
+}
+
+// Writes a check, as `POST /account/:account_id` with a `WriteCheck` command.
+#[utoipa::path(
+    post,
+    path = "/account/{account_id}/checks",
+    tag = "accounts",
+    params(("account_id" = String, Path, description = "The account id")),
+    request_body = NewCheck,
+    responses(
+        (status = 204, description = "The check was written", headers(("X-Aggregate-Version" = usize, description = "The resulting version of the account"))),
+        (status = 400, description = "The command was rejected", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn check_handler(
+    Path(account_id): Path<String>,
+    State(state): State<ApplicationState>,
+    CommandExtractor(metadata, check): CommandExtractor<NewCheck>,
+) -> Response {
+    match execute_command(&state, &account_id, metadata, check.into()).await {
+        Ok(version) => versioned_response(StatusCode::NO_CONTENT, version),
+        Err(response) => response,
+    }
+}
+
+// Executes a command against an account and returns the resulting version of the account,
+// a rejected command is a `400 Bad Request`.
+async fn execute_command(
+    state: &ApplicationState,
+    account_id: &str,
+    metadata: HashMap<String, String>,
+    command: BankAccountCommand,
+) -> Result<Option<usize>, Response> {
+    let command = CommandEnvelope::new(command, metadata.clone());
+    let execute = state
         .cqrs
-        .execute_with_metadata(&account_id, command, metadata)
+        .execute_with_metadata(account_id, command, metadata);
+    committed_version(execute).await.map_err(|err| {
+        println!("Error: {:#?}\n", err);
+        // Opening an account that exists conflicts with it, any other rejection is a bad request.
+        let status = match &err {
+            AggregateError::UserError(err) if err.to_string() == ACCOUNT_ALREADY_OPEN => {
+                StatusCode::CONFLICT
+            }
+            _ => StatusCode::BAD_REQUEST,
+        };
+        (status, err.to_string()).into_response()
+    })
+}
+
+fn versioned_response(status: StatusCode, version: Option<usize>) -> Response {
+    match version {
+        Some(version) => (status, [(VERSION_HDR, version.to_string())]).into_response(),
+        None => status.into_response(),
+    }
+}
+
+// Executes a GraphQL query or mutation, see `graphql` for the schema.
+pub async fn graphql_handler(
+    headers: HeaderMap,
+    State(state): State<ApplicationState>,
+    Json(request): Json<async_graphql::Request>,
+) -> Response {
+    let request = request.data(state.clone()).data(headers);
+    Json(state.graphql.execute(request).await).into_response()
+}
+
+// Serves GraphQL subscriptions over a WebSocket using either the `graphql-transport-ws` or the
+// older `graphql-ws` protocol, any other request is served the GraphiQL IDE.
+pub async fn graphql_subscription_handler(
+    headers: HeaderMap,
+    ws: Option<WebSocketUpgrade>,
+    State(state): State<ApplicationState>,
+) -> Response {
+    let ws = match ws {
+        Some(ws) => ws,
+        None => {
+            let graphiql = GraphiQLSource::build()
+                .endpoint("/graphql")
+                .subscription_endpoint("/graphql")
+                .finish();
+            return Html(graphiql).into_response();
+        }
+    };
+    let protocol = headers
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|protocols| {
+            protocols
+                .split(',')
+                .find_map(|protocol| protocol.trim().parse::<WebSocketProtocols>().ok())
+        });
+    let protocol = match protocol {
+        Some(protocol) => protocol,
+        None => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "unsupported websocket protocol".to_string(),
+            )
+                .into_response()
+        }
+    };
+    ws.protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |socket| async move {
+            let (mut sink, stream) = socket.split();
+            let stream = stream
+                .take_while(|message| ready(message.is_ok()))
+                .filter_map(|message| {
+                    ready(match message {
+                        Ok(Message::Text(text)) => Some(text.into_bytes()),
+                        Ok(Message::Binary(bytes)) => Some(bytes),
+                        _ => None,
+                    })
+                });
+            let mut data = Data::default();
+            data.insert(state.clone());
+            data.insert(headers);
+            let mut messages = Box::pin(
+                WebSocket::new(state.graphql.clone(), stream, protocol).connection_data(data),
+            );
+            while let Some(message) = messages.next().await {
+                let message = match message {
+                    WsMessage::Text(text) => Message::Text(text),
+                    WsMessage::Close(code, reason) => Message::Close(Some(CloseFrame {
+                        code,
+                        reason: reason.into(),
+                    })),
+                };
+                if sink.send(message).await.is_err() {
+                    break;
+                }
+            }
+        })
+}
+
+// Lists every transaction that has been flagged for review by fraud and
+// anti-money laundering screening.
+#[utoipa::path(
+    get,
+    path = "/review-queue",
+    tag = "accounts",
+    responses(
+        (status = 200, description = "The transactions flagged for review", body = [FlaggedTransaction]),
+        (status = 500, description = "The request failed", body = String, content_type = "text/plain"),
+        (status = 501, description = "The route requires Postgres storage", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn review_queue_handler(
+    _postgres: PostgresExtractor,
+    State(state): State<ApplicationState>,
+) -> Response {
+    match state.review_queue.load().await {
+        Ok(flagged_transactions) => (StatusCode::OK, Json(flagged_transactions)).into_response(),
+        Err(err) => {
+            println!("Error: {:#?}\n", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
+
+// Serves the view of a single ATM, including its current cash inventory.
+#[utoipa::path(
+    get,
+    path = "/atm/{atm_id}",
+    tag = "atms",
+    params(("atm_id" = String, Path, description = "The ATM id")),
+    responses(
+        (status = 200, description = "The view of the ATM", body = AtmView),
+        (status = 404, description = "The ATM was not found"),
+        (status = 500, description = "The request failed", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn atm_query_handler(
+    Path(atm_id): Path<String>,
+    State(state): State<ApplicationState>,
+) -> Response {
+    let view = match state.atm_query.load(&atm_id).await {
+        Ok(view) => view,
+        Err(err) => {
+            println!("Error: {:#?}\n", err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+        }
+    };
+    match view {
+        None => StatusCode::NOT_FOUND.into_response(),
+        Some(atm_view) => (StatusCode::OK, Json(atm_view)).into_response(),
+    }
+}
+
+// Serves the views of every registered ATM so that operations can plan replenishment.
+#[utoipa::path(
+    get,
+    path = "/atms",
+    tag = "atms",
+    responses(
+        (status = 200, description = "The views of every registered ATM", body = [AtmView]),
+        (status = 500, description = "The request failed", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn atms_query_handler(State(state): State<ApplicationState>) -> Response {
+    match state.atm_views.load().await {
+        Ok(atm_views) => (StatusCode::OK, Json(atm_views)).into_response(),
+        Err(err) => {
+            println!("Error: {:#?}\n", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
+
+// Serves as our command endpoint to register and manage an `Atm` aggregate, any command other
+// than `ReserveCash` and `DispenseCash` is accepted. This is restricted to the admin role.
+#[utoipa::path(
+    post,
+    path = "/atm/{atm_id}",
+    tag = "atms",
+    params(("atm_id" = String, Path, description = "The ATM id")),
+    request_body = AtmCommand,
+    security(("admin_api_key" = [])),
+    responses(
+        (status = 204, description = "The command was executed"),
+        (status = 400, description = "The command was rejected", body = String, content_type = "text/plain"),
+        (status = 403, description = "The admin api key is missing or invalid", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn atm_command_handler(
+    _admin: AdminExtractor,
+    Path(atm_id): Path<String>,
+    State(state): State<ApplicationState>,
+    CommandExtractor(metadata, command): CommandExtractor<AtmCommand>,
+) -> Response {
+    match &command {
+        // Cash is reserved and recorded as dispensed only by the withdrawals of accounts.
+        AtmCommand::ReserveCash { .. } | AtmCommand::DispenseCash { .. } => {
+            let err = "ReserveCash and DispenseCash are only issued by account withdrawals";
+            return (StatusCode::BAD_REQUEST, err).into_response();
+        }
+        AtmCommand::RegisterAtm {
+            atm_id: registered_id,
+            ..
+        } if *registered_id != atm_id => {
+            let err = "the atm_id of the command does not match the path";
+            return (StatusCode::BAD_REQUEST, err).into_response();
+        }
+        _ => {}
+    }
+    match state
+        .atm_cqrs
+        .execute_with_metadata(&atm_id, command, metadata)
         .await
     {
         Ok(_) => StatusCode::NO_CONTENT.into_response(),
-        Err(err) =>  {
+        Err(err) => {
             println!("Error: {:#?}\n", err);
             (StatusCode::BAD_REQUEST, err.to_string()).into_response()
-        },
+        }
     }
 }
@@ -0,0 +1,79 @@
+use axum::routing::{delete, get, post, MethodRouter};
+use axum::Router;
+
+use crate::route_handler::{
+    account_stream_handler, accounts_handler, atm_command_handler, atm_query_handler,
+    atms_query_handler, check_handler, close_account_handler, command_handler,
+    create_webhook_handler, daily_report_handler, dead_letter_retry_handler, dead_letters_handler,
+    delete_webhook_handler, deposit_handler, docs_handler, events_handler, export_handler,
+    graphql_handler, graphql_subscription_handler, ledger_handler, metrics_handler,
+    open_account_handler, openapi_handler, query_handler, rebuild_handler,
+    rebuild_progress_handler, review_queue_handler, swagger_ui_handler, webhook_deliveries_handler,
+    webhooks_handler, withdrawal_handler,
+};
+use crate::state::ApplicationState;
+
+// The Axum routes of the application, each path along with the handlers of its methods. These
+// are described by the OpenAPI document served at `/openapi.json`, except for GraphQL and the
+// documentation itself.
+//
+// For this example a single logical endpoint is used and the HTTP method
+// distinguishes whether the call is a command or a query. Each command may also be
+// sent to its own resource route.
+pub fn routes() -> Vec<(&'static str, MethodRouter<ApplicationState>)> {
+    vec![
+        (
+            "/account/:account_id",
+            get(query_handler)
+                .post(command_handler)
+                .delete(close_account_handler),
+        ),
+        ("/account/:account_id/deposits", post(deposit_handler)),
+        ("/account/:account_id/withdrawals", post(withdrawal_handler)),
+        ("/account/:account_id/checks", post(check_handler)),
+        ("/account/:account_id/ledger", get(ledger_handler)),
+        ("/account/:account_id/events", get(events_handler)),
+        ("/account/:account_id/export", get(export_handler)),
+        ("/account/:account_id/stream", get(account_stream_handler)),
+        (
+            "/accounts",
+            get(accounts_handler).post(open_account_handler),
+        ),
+        (
+            "/admin/rebuild",
+            get(rebuild_progress_handler).post(rebuild_handler),
+        ),
+        ("/admin/dead-letters", get(dead_letters_handler)),
+        ("/admin/dead-letters/retry", post(dead_letter_retry_handler)),
+        ("/metrics", get(metrics_handler)),
+        (
+            "/webhooks",
+            get(webhooks_handler).post(create_webhook_handler),
+        ),
+        ("/webhooks/:id", delete(delete_webhook_handler)),
+        ("/webhooks/:id/deliveries", get(webhook_deliveries_handler)),
+        (
+            "/graphql",
+            get(graphql_subscription_handler).post(graphql_handler),
+        ),
+        ("/openapi.json", get(openapi_handler)),
+        ("/docs", get(docs_handler)),
+        ("/docs/", get(swagger_ui_handler)),
+        ("/docs/*file", get(swagger_ui_handler)),
+        ("/reports/daily", get(daily_report_handler)),
+        ("/review-queue", get(review_queue_handler)),
+        (
+            "/atm/:atm_id",
+            get(atm_query_handler).post(atm_command_handler),
+        ),
+        ("/atms", get(atms_query_handler)),
+    ]
+}
+
+pub fn router() -> Router<ApplicationState> {
+    routes()
+        .into_iter()
+        .fold(Router::new(), |router, (path, method_router)| {
+            router.route(path, method_router)
+        })
+}
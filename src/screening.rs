@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use cqrs_es::{EventEnvelope, Query};
+
+use crate::command_extractor::request_time;
+use crate::domain::aggregate::BankAccount;
+use crate::domain::commands::BankAccountCommand;
+use crate::domain::events::BankAccountEvent;
+use crate::queries::event_time;
+use crate::services::{ScreeningDecision, TransactionScreening};
+
+// A simple rule based screening of transactions:
+// - deposits above `max_deposit` are rejected
+// - deposits of `large_deposit` or more are flagged for review
+// - more than `rapid_withdrawals` withdrawals from an account within `withdrawal_window`
+//   are flagged for review
+//
+// Screening only reads the committed withdrawals from `RecentWithdrawals`, so a command that is
+// rejected or fails to commit is never counted. These are tracked in memory so this is only
+// suitable for a single instance, a production system would rely on an external fraud
+// detection service.
+pub struct RuleBasedScreening {
+    large_deposit: f64,
+    max_deposit: f64,
+    rapid_withdrawals: usize,
+    recent_withdrawals: RecentWithdrawals,
+}
+
+impl RuleBasedScreening {
+    pub fn new(recent_withdrawals: RecentWithdrawals) -> Self {
+        Self {
+            large_deposit: 10_000.0,
+            max_deposit: 50_000.0,
+            rapid_withdrawals: 3,
+            recent_withdrawals,
+        }
+    }
+}
+
+// A projection of the times of the committed withdrawals from each account, those older than
+// the window are discarded.
+#[derive(Clone)]
+pub struct RecentWithdrawals {
+    window: Duration,
+    withdrawals: Arc<Mutex<HashMap<String, Vec<DateTime<Utc>>>>>,
+}
+
+impl Default for RecentWithdrawals {
+    fn default() -> Self {
+        Self {
+            window: Duration::minutes(10),
+            withdrawals: Arc::default(),
+        }
+    }
+}
+
+impl RecentWithdrawals {
+    // The number of withdrawals from the account within the window preceding this time.
+    fn count(&self, account_id: &str, time: DateTime<Utc>) -> usize {
+        self.withdrawals
+            .lock()
+            .unwrap()
+            .get(account_id)
+            .map(|withdrawals| {
+                withdrawals
+                    .iter()
+                    .filter(|withdrawal| time - **withdrawal < self.window)
+                    .count()
+            })
+            .unwrap_or(0)
+    }
+}
+
+#[async_trait]
+impl Query<BankAccount> for RecentWithdrawals {
+    async fn dispatch(&self, aggregate_id: &str, events: &[EventEnvelope<BankAccount>]) {
+        let mut withdrawals = self.withdrawals.lock().unwrap();
+        for event in events {
+            if let BankAccountEvent::CustomerWithdrewCash { .. } = event.payload {
+                let time = event_time(event);
+                let account_withdrawals = withdrawals.entry(aggregate_id.to_string()).or_default();
+                account_withdrawals.retain(|withdrawal| time - *withdrawal < self.window);
+                account_withdrawals.push(time);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl TransactionScreening for RuleBasedScreening {
+    async fn screen(
+        &self,
+        account: &BankAccount,
+        command: &BankAccountCommand,
+        metadata: &HashMap<String, String>,
+    ) -> ScreeningDecision {
+        match command {
+            BankAccountCommand::DepositMoney { amount } if *amount > self.max_deposit => {
+                ScreeningDecision::Reject(format!(
+                    "deposit exceeds the maximum of {}",
+                    self.max_deposit
+                ))
+            }
+            BankAccountCommand::DepositMoney { amount } if *amount >= self.large_deposit => {
+                ScreeningDecision::FlagForReview("large deposit".to_string())
+            }
+            BankAccountCommand::WithdrawMoney { .. } => {
+                let time = request_time(metadata);
+                // Including this withdrawal.
+                let withdrawals = self.recent_withdrawals.count(account.account_id(), time) + 1;
+                if withdrawals > self.rapid_withdrawals {
+                    ScreeningDecision::FlagForReview(format!(
+                        "{} withdrawals within {} minutes",
+                        withdrawals,
+                        self.recent_withdrawals.window.num_minutes()
+                    ))
+                } else {
+                    ScreeningDecision::Approve
+                }
+            }
+            _ => ScreeningDecision::Approve,
+        }
+    }
+}
+
+#[cfg(test)]
+mod screening_tests {
+    use std::collections::HashMap;
+
+    use cqrs_es::{EventEnvelope, Query};
+
+    use crate::domain::aggregate::BankAccount;
+    use crate::domain::commands::BankAccountCommand;
+    use crate::domain::events::BankAccountEvent;
+    use crate::screening::{RecentWithdrawals, RuleBasedScreening};
+    use crate::services::{ScreeningDecision, TransactionScreening};
+
+    #[tokio::test]
+    async fn test_deposits() {
+        let screening = RuleBasedScreening::new(RecentWithdrawals::default());
+        let account = BankAccount::default();
+        let metadata = HashMap::default();
+        let deposit = |amount| BankAccountCommand::DepositMoney { amount };
+
+        let decision = screening
+            .screen(&account, &deposit(9_999.99), &metadata)
+            .await;
+        assert_eq!(ScreeningDecision::Approve, decision);
+        let decision = screening
+            .screen(&account, &deposit(10_000.0), &metadata)
+            .await;
+        assert_eq!(
+            ScreeningDecision::FlagForReview("large deposit".to_string()),
+            decision
+        );
+        let decision = screening
+            .screen(&account, &deposit(50_000.01), &metadata)
+            .await;
+        assert!(matches!(decision, ScreeningDecision::Reject(_)));
+    }
+
+    #[tokio::test]
+    async fn test_rapid_withdrawals() {
+        let recent_withdrawals = RecentWithdrawals::default();
+        let screening = RuleBasedScreening::new(recent_withdrawals.clone());
+        let account = BankAccount::default();
+        let withdrawal = BankAccountCommand::WithdrawMoney {
+            amount: 20.0,
+            atm_id: "ATM-N468290".to_string(),
+        };
+        let metadata = |time: &str| HashMap::from([("time".to_string(), time.to_string())]);
+        let commit = |sequence, time| EventEnvelope {
+            aggregate_id: account.account_id().to_string(),
+            sequence,
+            payload: BankAccountEvent::CustomerWithdrewCash {
+                amount: 20.0,
+                atm_id: "ATM-N468290".to_string(),
+                balance: 100.0,
+                withdrawal_id: String::new(),
+            },
+            metadata: metadata(time),
+        };
+
+        for (sequence, time) in [
+            "2022-03-31T10:00:00Z",
+            "2022-03-31T10:01:00Z",
+            "2022-03-31T10:02:00Z",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let decision = screening
+                .screen(&account, &withdrawal, &metadata(time))
+                .await;
+            assert_eq!(ScreeningDecision::Approve, decision);
+            recent_withdrawals
+                .dispatch(account.account_id(), &[commit(sequence + 1, time)])
+                .await;
+        }
+        let decision = screening
+            .screen(&account, &withdrawal, &metadata("2022-03-31T10:03:00Z"))
+            .await;
+        assert!(matches!(decision, ScreeningDecision::FlagForReview(_)));
+        // Only the withdrawal at 10:02 is still within the window.
+        let decision = screening
+            .screen(&account, &withdrawal, &metadata("2022-03-31T10:11:30Z"))
+            .await;
+        assert_eq!(ScreeningDecision::Approve, decision);
+    }
+
+    #[tokio::test]
+    async fn test_uncommitted_withdrawals() {
+        let screening = RuleBasedScreening::new(RecentWithdrawals::default());
+        let account = BankAccount::default();
+        let withdrawal = BankAccountCommand::WithdrawMoney {
+            amount: 20.0,
+            atm_id: "ATM-N468290".to_string(),
+        };
+        let metadata = HashMap::from([("time".to_string(), "2022-03-31T10:00:00Z".to_string())]);
+        for _ in 0..5 {
+            let decision = screening.screen(&account, &withdrawal, &metadata).await;
+            assert_eq!(ScreeningDecision::Approve, decision);
+        }
+    }
+}
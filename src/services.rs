@@ -1,30 +1,157 @@
 use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::domain::aggregate::BankAccount;
+use crate::domain::commands::BankAccountCommand;
 
 pub struct BankAccountServices {
     pub services: Box<dyn BankAccountApi>,
+    pub screening: Box<dyn TransactionScreening>,
+    pub atms: Box<dyn AtmRegistry>,
 }
 
 impl BankAccountServices {
     pub fn new(services: Box<dyn BankAccountApi>) -> Self {
-        Self { services }
+        Self {
+            services,
+            screening: Box::new(NoScreening),
+            atms: Box::new(UnverifiedAtms),
+        }
+    }
+
+    pub fn with_atm_registry(self, atms: Box<dyn AtmRegistry>) -> Self {
+        Self { atms, ..self }
+    }
+
+    pub fn with_screening(self, screening: Box<dyn TransactionScreening>) -> Self {
+        Self { screening, ..self }
     }
 }
 
 // External services must be called during the processing of the command.
+// A withdrawal may be retried, so the ATM must dispense cash only once for each idempotency key.
 #[async_trait]
 pub trait BankAccountApi: Sync + Send {
-    async fn atm_withdrawal(&self, atm_id: &str, amount: f64) -> Result<(), AtmError>;
-    async fn validate_check(&self, account_id: &str, check: &str) -> Result<(), CheckingError>;
+    async fn atm_withdrawal(
+        &self,
+        atm_id: &str,
+        amount: f64,
+        idempotency_key: &str,
+    ) -> Result<(), AtmError>;
+    async fn validate_check(
+        &self,
+        account_id: &str,
+        check: &str,
+        amount: f64,
+    ) -> Result<(), CheckingError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum AtmError {
+    UnknownAtm,
+    AtmOffline,
+    InsufficientCash,
+    LimitExceeded,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum CheckingError {
+    InvalidCheckNumber,
+    StopPayment,
+    DuplicateCheck,
+}
+
+// Allows a single set of services to be shared between the aggregate and the outbox dispatcher.
+#[async_trait]
+impl<T: BankAccountApi + ?Sized> BankAccountApi for Arc<T> {
+    async fn atm_withdrawal(
+        &self,
+        atm_id: &str,
+        amount: f64,
+        idempotency_key: &str,
+    ) -> Result<(), AtmError> {
+        self.as_ref()
+            .atm_withdrawal(atm_id, amount, idempotency_key)
+            .await
+    }
+
+    async fn validate_check(
+        &self,
+        account_id: &str,
+        check: &str,
+        amount: f64,
+    ) -> Result<(), CheckingError> {
+        self.as_ref()
+            .validate_check(account_id, check, amount)
+            .await
+    }
+}
+
+// Fraud and anti-money laundering screening, this is consulted before a transaction
+// is accepted and may approve it, reject it or accept it but flag it for review.
+#[async_trait]
+pub trait TransactionScreening: Sync + Send {
+    async fn screen(
+        &self,
+        account: &BankAccount,
+        command: &BankAccountCommand,
+        metadata: &HashMap<String, String>,
+    ) -> ScreeningDecision;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScreeningDecision {
+    Approve,
+    Reject(String),
+    FlagForReview(String),
+}
+
+// Screening that approves every transaction.
+pub struct NoScreening;
+
+#[async_trait]
+impl TransactionScreening for NoScreening {
+    async fn screen(
+        &self,
+        _account: &BankAccount,
+        _command: &BankAccountCommand,
+        _metadata: &HashMap<String, String>,
+    ) -> ScreeningDecision {
+        ScreeningDecision::Approve
+    }
+}
+
+// The registry of ATMs, a withdrawal is only accepted from a registered and active ATM
+// that has enough cash on hand. The cash is reserved for the withdrawal until it is dispensed,
+// the returned withdrawal id identifies the reservation.
+#[async_trait]
+pub trait AtmRegistry: Sync + Send {
+    async fn reserve_cash(&self, atm_id: &str, amount: f64) -> Result<String, AtmError>;
+}
+
+// Accepts withdrawals from any ATM, no cash is reserved.
+pub struct UnverifiedAtms;
+
+#[async_trait]
+impl AtmRegistry for UnverifiedAtms {
+    async fn reserve_cash(&self, _atm_id: &str, _amount: f64) -> Result<String, AtmError> {
+        Ok(String::new())
+    }
 }
-pub struct AtmError;
-pub struct CheckingError;
 
 // A very simple "happy path" set of services that always succeed.
 pub struct HappyPathBankAccountServices;
 
 #[async_trait]
 impl BankAccountApi for HappyPathBankAccountServices {
-    async fn atm_withdrawal(&self, _atm_id: &str, _amount: f64) -> Result<(), AtmError> {
+    async fn atm_withdrawal(
+        &self,
+        _atm_id: &str,
+        _amount: f64,
+        _idempotency_key: &str,
+    ) -> Result<(), AtmError> {
         Ok(())
     }
 
@@ -32,6 +159,7 @@ impl BankAccountApi for HappyPathBankAccountServices {
         &self,
         _account_id: &str,
         _check_number: &str,
+        _amount: f64,
     ) -> Result<(), CheckingError> {
         Ok(())
     }
@@ -0,0 +1,39 @@
+use async_trait::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+
+use crate::config::StorageMode;
+use crate::state::ApplicationState;
+
+// This is a custom Axum extension that restricts a route to Postgres storage, for the routes
+// served from tables that are not maintained when the application runs with `STORAGE=memory`.
+pub struct PostgresExtractor;
+
+#[async_trait]
+impl FromRequestParts<ApplicationState> for PostgresExtractor {
+    type Rejection = PostgresExtractionError;
+
+    async fn from_request_parts(
+        _parts: &mut Parts,
+        state: &ApplicationState,
+    ) -> Result<Self, Self::Rejection> {
+        match state.storage_mode {
+            StorageMode::Postgres => Ok(PostgresExtractor),
+            StorageMode::Memory => Err(PostgresExtractionError),
+        }
+    }
+}
+
+pub struct PostgresExtractionError;
+
+impl IntoResponse for PostgresExtractionError {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::NOT_IMPLEMENTED,
+            "this route requires Postgres storage".to_string(),
+        )
+            .into_response()
+    }
+}
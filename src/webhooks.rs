@@ -0,0 +1,410 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use cqrs_es::persist::SerializedEvent;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use sqlx::{PgConnection, Pool, Postgres, Row};
+use tokio::task::JoinHandle;
+use utoipa::ToSchema;
+
+pub const SIGNATURE_HDR: &str = "X-Webhook-Signature";
+const DELIVERY_HDR: &str = "X-Webhook-Delivery";
+
+const INSERT_SUBSCRIPTION: &str = "
+INSERT INTO webhook_subscriptions (url, secret, event_types, account_ids)
+VALUES ($1, $2, $3, $4)
+RETURNING id, created_at";
+
+const SELECT_SUBSCRIPTIONS: &str = "
+SELECT id, url, event_types, account_ids, created_at
+  FROM webhook_subscriptions
+  ORDER BY id";
+
+const DELETE_SUBSCRIPTION: &str = "DELETE FROM webhook_subscriptions WHERE id = $1";
+
+const SELECT_DELIVERIES: &str = "
+SELECT id, aggregate_id, sequence, event_type, attempts, response_status, last_error, created_at, delivered_at,
+    abandoned_at
+  FROM webhook_deliveries
+  WHERE subscription_id = $1
+  ORDER BY id DESC
+  LIMIT $2";
+
+// An empty list of event types or accounts matches every event or account.
+const ENQUEUE_DELIVERIES: &str = "
+INSERT INTO webhook_deliveries (subscription_id, aggregate_id, sequence, event_type, payload)
+SELECT id, $1, $2, $3, $4
+  FROM webhook_subscriptions
+  WHERE (cardinality(event_types) = 0 OR $3 = ANY(event_types))
+    AND (cardinality(account_ids) = 0 OR $1 = ANY(account_ids))";
+
+// A delivery is claimed by pushing back its next attempt for the length of a lease, so that the
+// claim is committed before posting and no other dispatcher posts it while it is held.
+const CLAIM_PENDING_DELIVERIES: &str = "
+UPDATE webhook_deliveries d
+  SET next_attempt_at = now() + make_interval(secs => $2)
+  FROM webhook_subscriptions s
+  WHERE s.id = d.subscription_id
+    AND d.id IN (
+      SELECT id FROM webhook_deliveries
+        WHERE delivered_at IS NULL AND abandoned_at IS NULL AND next_attempt_at <= now()
+        ORDER BY id
+        LIMIT $1
+        FOR UPDATE SKIP LOCKED)
+  RETURNING d.id, d.aggregate_id, d.sequence, d.event_type, d.payload, s.url, s.secret";
+
+const MARK_DELIVERED: &str = "
+UPDATE webhook_deliveries
+  SET attempts = attempts + 1, response_status = $2, last_error = NULL, delivered_at = now()
+  WHERE id = $1";
+
+// A delivery that fails `MAX_ATTEMPTS` times is abandoned.
+const MARK_FAILED: &str = "
+UPDATE webhook_deliveries
+  SET attempts = attempts + 1,
+      response_status = $2,
+      last_error = $3,
+      next_attempt_at = now() + make_interval(secs => LEAST(power(2, attempts), $4)),
+      abandoned_at = CASE WHEN attempts + 1 >= $5 THEN now() END
+  WHERE id = $1";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const BATCH_SIZE: i64 = 20;
+const MAX_ATTEMPTS: i32 = 10;
+const MAX_BACKOFF_SECONDS: f64 = 300.0;
+const LEASE_SECONDS: f64 = 60.0;
+const DELIVERY_LOG_SIZE: i64 = 100;
+// Signatures with a timestamp further than this from the time of receipt are rejected,
+// so that a captured delivery cannot be replayed later.
+const SIGNATURE_TOLERANCE_SECONDS: i64 = 300;
+
+// A partner endpoint notified of account events, optionally limited to some event types
+// (e.g., `CustomerDepositedMoney`) and accounts.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewWebhook {
+    pub url: String,
+    #[serde(default)]
+    pub event_types: Vec<String>,
+    #[serde(default)]
+    pub account_ids: Vec<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WebhookSubscription {
+    id: i64,
+    url: String,
+    event_types: Vec<String>,
+    account_ids: Vec<String>,
+    created_at: DateTime<Utc>,
+    // The signing secret is only returned when the subscription is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WebhookDelivery {
+    id: i64,
+    account_id: String,
+    sequence: i64,
+    event_type: String,
+    attempts: i32,
+    response_status: Option<i32>,
+    last_error: Option<String>,
+    created_at: DateTime<Utc>,
+    delivered_at: Option<DateTime<Utc>>,
+    // Set once the delivery has failed `MAX_ATTEMPTS` times, it is not retried after this.
+    abandoned_at: Option<DateTime<Utc>>,
+}
+
+// The webhook subscriptions and their delivery logs.
+#[derive(Clone)]
+pub struct WebhookSubscriptions {
+    pool: Pool<Postgres>,
+}
+
+impl WebhookSubscriptions {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        Self { pool }
+    }
+
+    pub async fn create(&self, webhook: NewWebhook) -> Result<WebhookSubscription, sqlx::Error> {
+        let secret = hex::encode(rand::random::<[u8; 32]>());
+        let row = sqlx::query(INSERT_SUBSCRIPTION)
+            .bind(&webhook.url)
+            .bind(&secret)
+            .bind(&webhook.event_types)
+            .bind(&webhook.account_ids)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(WebhookSubscription {
+            id: row.get("id"),
+            url: webhook.url,
+            event_types: webhook.event_types,
+            account_ids: webhook.account_ids,
+            created_at: row.get("created_at"),
+            secret: Some(secret),
+        })
+    }
+
+    pub async fn load(&self) -> Result<Vec<WebhookSubscription>, sqlx::Error> {
+        let rows = sqlx::query(SELECT_SUBSCRIPTIONS)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| WebhookSubscription {
+                id: row.get("id"),
+                url: row.get("url"),
+                event_types: row.get("event_types"),
+                account_ids: row.get("account_ids"),
+                created_at: row.get("created_at"),
+                secret: None,
+            })
+            .collect())
+    }
+
+    // Returns false if there was no such subscription, its deliveries are also deleted.
+    pub async fn delete(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(DELETE_SUBSCRIPTION)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    // The most recent deliveries of a subscription.
+    pub async fn deliveries(&self, id: i64) -> Result<Vec<WebhookDelivery>, sqlx::Error> {
+        let rows = sqlx::query(SELECT_DELIVERIES)
+            .bind(id)
+            .bind(DELIVERY_LOG_SIZE)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| WebhookDelivery {
+                id: row.get("id"),
+                account_id: row.get("aggregate_id"),
+                sequence: row.get("sequence"),
+                event_type: row.get("event_type"),
+                attempts: row.get("attempts"),
+                response_status: row.get("response_status"),
+                last_error: row.get("last_error"),
+                created_at: row.get("created_at"),
+                delivered_at: row.get("delivered_at"),
+                abandoned_at: row.get("abandoned_at"),
+            })
+            .collect())
+    }
+}
+
+// Records a delivery of an account event for every matching subscription, this is called within
+// the transaction that commits the event so that no notification is lost.
+pub(crate) async fn enqueue_deliveries(
+    conn: &mut PgConnection,
+    event: &SerializedEvent,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(ENQUEUE_DELIVERIES)
+        .bind(&event.aggregate_id)
+        .bind(event.sequence as i64)
+        .bind(&event.event_type)
+        .bind(&event.payload)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+// The `X-Webhook-Signature` of a delivery, an HMAC-SHA256 of the timestamp and body
+// given as `t=<unix timestamp>,v1=<hex signature>`.
+pub fn signature_header(secret: &str, timestamp: i64, body: &str) -> String {
+    let mac = signature_mac(secret, timestamp, body);
+    let signature = hex::encode(mac.finalize().into_bytes());
+    format!("t={},v1={}", timestamp, signature)
+}
+
+// Verifies the `X-Webhook-Signature` of a delivery received at `now` (a unix timestamp), as a
+// receiver should.
+pub fn verify_signature(secret: &str, header: &str, body: &str, now: i64) -> bool {
+    let mut timestamp = None;
+    let mut signature = None;
+    for part in header.split(',') {
+        match part.split_once('=') {
+            Some(("t", value)) => timestamp = value.parse::<i64>().ok(),
+            Some(("v1", value)) => signature = hex::decode(value).ok(),
+            _ => {}
+        }
+    }
+    match (timestamp, signature) {
+        (Some(timestamp), Some(signature))
+            if (now - timestamp).abs() <= SIGNATURE_TOLERANCE_SECONDS =>
+        {
+            signature_mac(secret, timestamp, body)
+                .verify_slice(&signature)
+                .is_ok()
+        }
+        _ => false,
+    }
+}
+
+fn signature_mac(secret: &str, timestamp: i64, body: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    mac
+}
+
+// Polls for pending webhook deliveries and posts them to the subscribers.
+// Like the outbox, delivery is at-least-once: a delivery is retried with an exponential backoff
+// until it receives a successful response or `MAX_ATTEMPTS` is reached, when it is abandoned. Every delivery and its
+// latest result is kept as the delivery log. Deliveries are posted outside of any transaction,
+// so a slow subscriber holds no locks.
+pub struct WebhookDispatcher {
+    pool: Pool<Postgres>,
+    client: reqwest::Client,
+}
+
+impl WebhookDispatcher {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .expect("unable to configure the webhook client");
+        Self { pool, client }
+    }
+
+    // Runs the dispatcher in a background task for the life of the application.
+    pub fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                self.drain().await;
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        })
+    }
+
+    // Posts deliveries until none are due, a delivery that fails is retried once its backoff
+    // has passed.
+    pub async fn drain(&self) {
+        loop {
+            match self.dispatch_pending().await {
+                // A full batch suggests that more deliveries are waiting.
+                Ok(dispatched) if dispatched as i64 == BATCH_SIZE => continue,
+                Ok(_) => return,
+                Err(err) => {
+                    println!("Error: webhook dispatch failed: {}\n", err);
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn dispatch_pending(&self) -> Result<usize, sqlx::Error> {
+        let mut rows = sqlx::query(CLAIM_PENDING_DELIVERIES)
+            .bind(BATCH_SIZE)
+            .bind(LEASE_SECONDS)
+            .fetch_all(&self.pool)
+            .await?;
+        rows.sort_by_key(|row| row.get::<i64, _>("id"));
+        let dispatched = rows.len();
+        for row in rows {
+            let id: i64 = row.get("id");
+            let body = json!({
+                "delivery_id": id,
+                "account_id": row.get::<String, _>("aggregate_id"),
+                "sequence": row.get::<i64, _>("sequence"),
+                "event_type": row.get::<String, _>("event_type"),
+                "event": row.get::<Value, _>("payload"),
+            })
+            .to_string();
+            let url: String = row.get("url");
+            let secret: String = row.get("secret");
+            match self.deliver(id, &url, &secret, body).await {
+                Ok(status) => {
+                    sqlx::query(MARK_DELIVERED)
+                        .bind(id)
+                        .bind(status)
+                        .execute(&self.pool)
+                        .await?
+                }
+                Err((status, err)) => {
+                    sqlx::query(MARK_FAILED)
+                        .bind(id)
+                        .bind(status)
+                        .bind(err)
+                        .bind(MAX_BACKOFF_SECONDS)
+                        .bind(MAX_ATTEMPTS)
+                        .execute(&self.pool)
+                        .await?
+                }
+            };
+        }
+        Ok(dispatched)
+    }
+
+    // Returns the response status, along with an error for anything other than a 2xx response.
+    async fn deliver(
+        &self,
+        id: i64,
+        url: &str,
+        secret: &str,
+        body: String,
+    ) -> Result<i32, (Option<i32>, String)> {
+        let signature = signature_header(secret, Utc::now().timestamp(), &body);
+        let response = self
+            .client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HDR, signature)
+            .header(DELIVERY_HDR, id.to_string())
+            .body(body)
+            .send()
+            .await
+            .map_err(|err| (None, err.to_string()))?;
+        let status = response.status();
+        if status.is_success() {
+            Ok(status.as_u16() as i32)
+        } else {
+            Err((
+                Some(status.as_u16() as i32),
+                format!("unexpected response: {}", status),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod webhooks_tests {
+    use crate::webhooks::{signature_header, verify_signature};
+
+    #[test]
+    fn test_signature() {
+        let body = r#"{"account_id":"ACCT-1","sequence":2}"#;
+        let now = 1_650_000_000;
+        let header = signature_header("secret", now, body);
+        assert!(header.starts_with("t=1650000000,v1="));
+        assert!(verify_signature("secret", &header, body, now));
+        assert!(!verify_signature("other secret", &header, body, now));
+        assert!(!verify_signature(
+            "secret",
+            &header,
+            r#"{"account_id":"ACCT-2"}"#,
+            now
+        ));
+        assert!(!verify_signature("secret", "v1=00", body, now));
+    }
+
+    #[test]
+    fn test_signature_tolerance() {
+        let body = r#"{"account_id":"ACCT-1","sequence":2}"#;
+        let header = signature_header("secret", 1_650_000_000, body);
+        assert!(verify_signature("secret", &header, body, 1_650_000_300));
+        assert!(verify_signature("secret", &header, body, 1_649_999_700));
+        assert!(!verify_signature("secret", &header, body, 1_650_000_301));
+        assert!(!verify_signature("secret", &header, body, 1_649_999_699));
+    }
+}